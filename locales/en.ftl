@@ -0,0 +1,136 @@
+## Problem types, see `ProblemType`.
+## The value is the title, `detail` explains a single occurrence and `description` is shown
+## in the documentation of the problem type.
+
+problem-auth-invalid-credentials = Invalid credentials
+    .detail = Authentication required.
+    .description =
+        The credentials weren't sent, aren't valid or the account is deactivated.
+        Authenticate again with a valid username and password.
+problem-auth-invalid-token = Invalid or expired link
+    .detail = The link is invalid or has expired.
+    .description =
+        The token or signature of the link is not valid, has expired or was already
+        used, e.g. a confirmation link followed twice. Request a new link.
+problem-auth-forbidden = Action not allowed
+    .detail = You don't have permission to perform this action.
+    .description =
+        The credentials are valid, but the user isn't allowed to perform this action,
+        for example because it requires the administrator role.
+problem-resource-not-found = Resource not found
+    .detail = The requested resource doesn't exist.
+    .description = The requested resource doesn't exist or was already deleted.
+problem-resource-conflict = Conflict with an existing resource
+    .detail = The resource conflicts with an existing one.
+    .description =
+        The request conflicts with an existing resource.
+        The conflicting fields are listed in the response.
+problem-user-username-taken = Username taken
+    .detail = The username is already taken.
+    .description =
+        Another user already has this username, or one that looks like it
+        (for example, with different case or with `0` instead of `o`). Choose another one.
+problem-user-email-taken = Email already registered
+    .detail = The email address already belongs to another account.
+    .description =
+        The email address already belongs to another account.
+        Log in with that account or use another address.
+problem-request-payload-too-large = Request too large
+    .detail = The request body is too large.
+    .description =
+        The request body exceeds the maximum size accepted by this resource,
+        for example when uploading an image that is too heavy.
+problem-request-unsupported-media-type = Unsupported format
+    .detail = The format of the request body isn't supported.
+    .description =
+        The request body has a format this resource doesn't accept.
+        Check the `Content-Type` header.
+problem-request-validation-failed = Invalid fields
+    .detail = One or more fields of the request aren't valid.
+    .description =
+        One or more fields of the request aren't valid. Each invalid field appears
+        in the response with a list of errors, each with a stable `code` and
+        a `message` to show to the user.
+problem-request-too-many-requests = Too many requests
+    .detail = Too many requests, try again later.
+    .description =
+        Too many requests were made in a short time. Wait the seconds given
+        in the `Retry-After` header before trying again.
+problem-server-internal-error = Internal error
+    .detail = An internal error occurred in the server.
+    .description =
+        An unexpected error occurred in the server. Try again later;
+        if the problem persists, report it including the value of `instance`.
+
+## Documentation of the problem types.
+
+problems-index-title = Problem types
+problems-index-intro = The errors of the API follow the format of RFC 7807. Their <code>code</code> field is one of the following:
+problems-index-link = All the problem types
+
+## Field errors, see `FieldError`. Named after their code, with `-` instead of `_`.
+
+field-required = This field is required.
+field-too-short = It must have at least { $min } characters.
+field-too-long = It must have at most { $max } characters.
+field-invalid-email = It isn't a valid email address.
+field-invalid-characters =
+    It can only contain unaccented letters, digits, dots, hyphens and underscores,
+    and must start and end with a letter or a digit.
+field-reserved = This name is reserved.
+field-already-registered = It's already registered.
+field-already-taken = It's already taken.
+field-already-exists = It already exists.
+field-not-found = It doesn't exist.
+field-derived-from-user-data = It can't contain your username, name or email address.
+field-too-weak = It's too easy to guess, try a phrase of several words.
+field-breached = It appeared in a data breach, choose another one.
+field-invalid-body = The request body isn't valid.
+field-invalid = The value of this field isn't valid.
+
+## Emails. The value is the plain text body, with `subject` and, when it differs from the
+## plain text one, the `html` body as attributes.
+
+email-email-change-confirmation =
+    Hi { $username },
+    Visit { $link } to confirm your new email address.
+    The link expires in 24 hours.
+    .subject = Confirm your new email address
+    .html =
+        Hi { $username },<br />
+        Click <a href="{ $link }">here</a> to confirm your new email address.<br />
+        The link expires in 24 hours.
+email-email-change-notice =
+    Hi { $username },
+    A change of the email address of your account to { $new_email } was requested.
+    If it wasn't you, change your password as soon as possible.
+    .subject = Email address change requested
+email-address-in-use =
+    Someone tried to use this email address in another account, but it already belongs to an existing account.
+    If it wasn't you, you can ignore this message.
+    .subject = Email address change attempt
+email-account-deletion-scheduled =
+    Hi { $username },
+    Your account will be deleted on { $date }.
+    If you change your mind, you can cancel the deletion before that date.
+    .subject = Account deletion scheduled
+email-subscription-confirmation =
+    Hi!
+    Visit { $link } to confirm your subscription to the newsletter.
+    The link expires in 24 hours. If you didn't subscribe, you can ignore this message.
+    .subject = Confirm your subscription
+    .html =
+        Hi!<br />
+        Click <a href="{ $link }">here</a> to confirm your subscription to the newsletter.<br />
+        The link expires in 24 hours. If you didn't subscribe, you can ignore this message.
+email-data-export-ready =
+    Hi { $username },
+    Your data is ready to be downloaded at { $link }
+    The file will be available for 7 days.
+    .subject = Your data is ready
+
+## Footer of the newsletter issues and unsubscription form.
+
+newsletter-unsubscribe-text = To stop receiving this newsletter visit { $link }
+newsletter-unsubscribe = Unsubscribe
+newsletter-unsubscribe-question = Do you want to stop receiving our newsletter?
@@ -0,0 +1,136 @@
+## Problem types, see `ProblemType`.
+## The value is the title, `detail` explains a single occurrence and `description` is shown
+## in the documentation of the problem type.
+
+problem-auth-invalid-credentials = Credenciales inválidas
+    .detail = Autenticación requerida.
+    .description =
+        Las credenciales no se enviaron, no son válidas o la cuenta está desactivada.
+        Vuelve a autenticarte con un nombre de usuario y una contraseña válidos.
+problem-auth-invalid-token = Enlace inválido o caducado
+    .detail = El enlace no es válido o ya caducó.
+    .description =
+        El token o la firma del enlace no es válido, ya caducó o ya se usó, por ejemplo
+        un enlace de confirmación abierto dos veces. Solicita un enlace nuevo.
+problem-auth-forbidden = Acción no permitida
+    .detail = No tienes permiso para realizar esta acción.
+    .description =
+        Las credenciales son válidas, pero el usuario no tiene permiso para realizar
+        esta acción, por ejemplo porque requiere el rol de administrador.
+problem-resource-not-found = Recurso no encontrado
+    .detail = El recurso solicitado no existe.
+    .description = El recurso solicitado no existe o ya fue eliminado.
+problem-resource-conflict = Conflicto con un recurso existente
+    .detail = El recurso entra en conflicto con uno existente.
+    .description =
+        La petición entra en conflicto con un recurso existente.
+        Los campos en conflicto se listan en la respuesta.
+problem-user-username-taken = Nombre de usuario en uso
+    .detail = El nombre de usuario ya está en uso.
+    .description =
+        Otro usuario ya tiene este nombre de usuario, o uno que se le parece
+        (por ejemplo, con otras mayúsculas o con `0` en lugar de `o`). Elige otro.
+problem-user-email-taken = Correo electrónico registrado
+    .detail = El correo electrónico ya pertenece a otra cuenta.
+    .description =
+        El correo electrónico ya pertenece a otra cuenta.
+        Inicia sesión con esa cuenta o usa otro correo.
+problem-request-payload-too-large = Petición demasiado grande
+    .detail = El cuerpo de la petición es demasiado grande.
+    .description =
+        El cuerpo de la petición supera el tamaño máximo que acepta este recurso,
+        por ejemplo al subir una imagen demasiado pesada.
+problem-request-unsupported-media-type = Formato no soportado
+    .detail = El formato del cuerpo de la petición no está soportado.
+    .description =
+        El cuerpo de la petición tiene un formato que este recurso no acepta.
+        Revisa la cabecera `Content-Type`.
+problem-request-validation-failed = Campos inválidos
+    .detail = Uno o más campos de la petición no son válidos.
+    .description =
+        Uno o más campos de la petición no son válidos. Cada campo inválido aparece
+        en la respuesta con una lista de errores, cada uno con un `code` estable y
+        un `message` para mostrar al usuario.
+problem-request-too-many-requests = Demasiadas peticiones
+    .detail = Demasiadas peticiones, vuelve a intentarlo más tarde.
+    .description =
+        Se hicieron demasiadas peticiones en poco tiempo. Espera los segundos
+        indicados en la cabecera `Retry-After` antes de volver a intentarlo.
+problem-server-internal-error = Error interno
+    .detail = Un error interno ocurrió en el servidor.
+    .description =
+        Ocurrió un error inesperado en el servidor. Vuelve a intentarlo más tarde;
+        si el problema persiste, repórtalo incluyendo el valor de `instance`.
+
+## Documentation of the problem types.
+
+problems-index-title = Tipos de problema
+problems-index-intro = Los errores de la API siguen el formato de RFC 7807. Su campo <code>code</code> es uno de los siguientes:
+problems-index-link = Todos los tipos de problema
+
+## Field errors, see `FieldError`. Named after their code, with `-` instead of `_`.
+
+field-required = Este campo es obligatorio.
+field-too-short = Debe tener al menos { $min } caracteres.
+field-too-long = Debe tener como máximo { $max } caracteres.
+field-invalid-email = No es un correo electrónico válido.
+field-invalid-characters =
+    Solo puede contener letras sin tildes, números, puntos, guiones y guiones bajos,
+    y debe empezar y terminar con una letra o un número.
+field-reserved = Este nombre está reservado.
+field-already-registered = Ya está registrado.
+field-already-taken = Ya está en uso.
+field-already-exists = Ya existe.
+field-not-found = No existe.
+field-derived-from-user-data = No puede contener tu nombre de usuario, nombre ni correo electrónico.
+field-too-weak = Es demasiado fácil de adivinar, prueba con una frase de varias palabras.
+field-breached = Apareció en una filtración de datos, elige otra.
+field-invalid-body = El cuerpo de la petición no es válido.
+field-invalid = El valor de este campo no es válido.
+
+## Emails. The value is the plain text body, with `subject` and, when it differs from the
+## plain text one, the `html` body as attributes.
+
+email-email-change-confirmation =
+    Hola { $username },
+    Visita { $link } para confirmar tu nuevo correo electrónico.
+    El enlace expira en 24 horas.
+    .subject = Confirma tu nuevo correo electrónico
+    .html =
+        Hola { $username },<br />
+        Haz clic <a href="{ $link }">aquí</a> para confirmar tu nuevo correo electrónico.<br />
+        El enlace expira en 24 horas.
+email-email-change-notice =
+    Hola { $username },
+    Se solicitó cambiar el correo electrónico de tu cuenta a { $new_email }.
+    Si no fuiste tú, cambia tu contraseña lo antes posible.
+    .subject = Cambio de correo electrónico solicitado
+email-address-in-use =
+    Alguien intentó usar este correo electrónico en otra cuenta, pero ya pertenece a una cuenta existente.
+    Si no fuiste tú, puedes ignorar este mensaje.
+    .subject = Intento de cambio de correo electrónico
+email-account-deletion-scheduled =
+    Hola { $username },
+    Tu cuenta será eliminada el { $date }.
+    Si cambias de opinión, puedes cancelar la eliminación antes de esa fecha.
+    .subject = Eliminación de cuenta programada
+email-subscription-confirmation =
+    ¡Hola!
+    Visita { $link } para confirmar tu suscripción al boletín.
+    El enlace expira en 24 horas. Si no te suscribiste, puedes ignorar este mensaje.
+    .subject = Confirma tu suscripción
+    .html =
+        ¡Hola!<br />
+        Haz clic <a href="{ $link }">aquí</a> para confirmar tu suscripción al boletín.<br />
+        El enlace expira en 24 horas. Si no te suscribiste, puedes ignorar este mensaje.
+email-data-export-ready =
+    Hola { $username },
+    Tus datos están listos para ser descargados en { $link }
+    El archivo estará disponible durante 7 días.
+    .subject = Tus datos están listos
+
+## Footer of the newsletter issues and unsubscription form.
+
+newsletter-unsubscribe-text = Para dejar de recibir este boletín visita { $link }
+newsletter-unsubscribe = Cancelar suscripción
+newsletter-unsubscribe-question = ¿Quieres dejar de recibir nuestro boletín?
@@ -0,0 +1,16 @@
+-- requests to change the email address of a user
+-- the row is kept after confirmation for audit purposes
+CREATE TABLE email_changes (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- the address being replaced, its `emails` row is never deleted
+    old_email_id uuid NOT NULL REFERENCES emails(id) ON DELETE CASCADE,
+    new_email_id uuid NOT NULL REFERENCES emails(id) ON DELETE CASCADE,
+    -- sent to the new address inside the confirmation link
+    token text UNIQUE NOT NULL,
+    -- NULL means is not confirmed yet
+    confirmed_at timestamptz,
+    expires_at timestamptz NOT NULL,
+    -- `created_at` should be read only
+    created_at timestamptz DEFAULT transaction_timestamp() NOT NULL
+);
@@ -0,0 +1,5 @@
+DELETE FROM roles
+WHERE role_name = 'admin';
+
+ALTER TABLE users
+DROP COLUMN deletion_scheduled_at;
@@ -0,0 +1,13 @@
+-- NULL means no deletion was requested
+-- once reached, the user is deleted or anonymized if they authored posts
+ALTER TABLE users
+ADD COLUMN deletion_scheduled_at timestamptz,
+-- failed attempts at deleting the user, each one postpones the deletion a bit longer
+ADD COLUMN deletion_retries smallint DEFAULT 0 NOT NULL,
+-- set once the worker gives up, until an admin looks into it
+ADD COLUMN deletion_failed_at timestamptz;
+
+-- admins can reactivate accounts, among other things
+INSERT INTO roles (role_name)
+VALUES ('admin')
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,20 @@
+-- archives with all the personal data of a user, generated in the background
+CREATE TABLE data_exports (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- NULL until the archive is generated
+    file_path text UNIQUE,
+    completed_at timestamptz,
+    -- failed attempts at generating the archive, retried with an exponential backoff
+    n_retries smallint DEFAULT 0 NOT NULL,
+    execute_after timestamptz DEFAULT transaction_timestamp() NOT NULL,
+    -- set once the worker gives up, the user can then request another export
+    failed_at timestamptz,
+    -- `created_at` should be read only
+    created_at timestamptz DEFAULT transaction_timestamp() NOT NULL
+);
+
+-- a user can only have one export being generated at a time
+CREATE UNIQUE INDEX data_exports_pending_user_id_key
+ON data_exports (user_id)
+WHERE completed_at IS NULL AND failed_at IS NULL;
@@ -0,0 +1,3 @@
+DROP TABLE issue_delivery_queue;
+DROP TABLE newsletter_issues;
+DROP TABLE subscription_tokens;
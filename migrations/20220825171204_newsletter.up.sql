@@ -0,0 +1,28 @@
+-- pending double opt-in confirmations of newsletter subscriptions
+CREATE TABLE subscription_tokens (
+    token text PRIMARY KEY,
+    email_id uuid NOT NULL REFERENCES emails(id) ON DELETE CASCADE,
+    expires_at timestamptz NOT NULL,
+    -- `created_at` should be read only
+    created_at timestamptz DEFAULT transaction_timestamp() NOT NULL
+);
+
+-- newsletters composed by admins, kept after delivery for audit purposes
+CREATE TABLE newsletter_issues (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    author_id uuid REFERENCES users(id) ON DELETE SET NULL,
+    title text NOT NULL,
+    html_content text NOT NULL,
+    text_content text NOT NULL,
+    -- `created_at` should be read only
+    created_at timestamptz DEFAULT transaction_timestamp() NOT NULL
+);
+
+-- recipients still waiting for a newsletter issue, rows are deleted once delivered
+CREATE TABLE issue_delivery_queue (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues(id) ON DELETE CASCADE,
+    email_id uuid NOT NULL REFERENCES emails(id) ON DELETE CASCADE,
+    n_retries smallint DEFAULT 0 NOT NULL,
+    execute_after timestamptz DEFAULT transaction_timestamp() NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, email_id)
+);
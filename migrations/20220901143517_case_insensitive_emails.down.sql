@@ -0,0 +1,4 @@
+DROP INDEX emails_email_lower_key;
+
+ALTER TABLE emails
+ADD CONSTRAINT emails_email_key UNIQUE (email);
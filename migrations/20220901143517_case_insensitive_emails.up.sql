@@ -0,0 +1,76 @@
+-- addresses are unique regardless of their case, e.g. `John@Doe.com` and `john@doe.com`
+ALTER TABLE emails
+DROP CONSTRAINT emails_email_key;
+
+-- Existing addresses may already be stored in several cases. Each address keeps one row:
+-- the one of its oldest user, otherwise a confirmed one, otherwise the oldest.
+CREATE TEMPORARY TABLE ranked_emails ON COMMIT DROP AS
+SELECT emails.id, users.id AS user_id, row_number() OVER (
+    PARTITION BY lower(emails.email)
+    ORDER BY users.created_at NULLS LAST, emails.email_confirmed_at NULLS LAST,
+        emails.created_at, emails.id
+) AS position, first_value(emails.id) OVER (
+    PARTITION BY lower(emails.email)
+    ORDER BY users.created_at NULLS LAST, emails.email_confirmed_at NULLS LAST,
+        emails.created_at, emails.id
+) AS kept_id
+FROM emails
+LEFT JOIN users ON users.email_id = emails.id;
+
+-- Other users of the same address can't share the row: they get an address that's never
+-- delivered, and are left for an admin to sort out.
+UPDATE emails
+SET email = replace(emails.id::text, '-', '') || '@duplicate.invalid',
+    updated_at = transaction_timestamp()
+FROM ranked_emails
+WHERE ranked_emails.id = emails.id
+    AND ranked_emails.position > 1
+    AND ranked_emails.user_id IS NOT NULL;
+
+DELETE FROM ranked_emails WHERE position = 1 OR user_id IS NOT NULL;
+
+-- The kept row is confirmed and subscribed if any of the merged ones was
+UPDATE emails
+SET email_confirmed_at = COALESCE(emails.email_confirmed_at, merged.email_confirmed_at),
+    subscribed = emails.subscribed OR merged.subscribed,
+    updated_at = transaction_timestamp()
+FROM (
+    SELECT ranked_emails.kept_id,
+        min(emails.email_confirmed_at) AS email_confirmed_at,
+        bool_or(emails.subscribed AND emails.email_confirmed_at IS NOT NULL) AS subscribed
+    FROM ranked_emails
+    JOIN emails ON emails.id = ranked_emails.id
+    GROUP BY ranked_emails.kept_id
+) AS merged
+WHERE merged.kept_id = emails.id;
+
+UPDATE email_changes
+SET old_email_id = ranked_emails.kept_id
+FROM ranked_emails
+WHERE ranked_emails.id = email_changes.old_email_id;
+
+UPDATE email_changes
+SET new_email_id = ranked_emails.kept_id
+FROM ranked_emails
+WHERE ranked_emails.id = email_changes.new_email_id;
+
+UPDATE subscription_tokens
+SET email_id = ranked_emails.kept_id
+FROM ranked_emails
+WHERE ranked_emails.id = subscription_tokens.email_id;
+
+-- Each issue is still delivered once to the address
+INSERT INTO issue_delivery_queue (newsletter_issue_id, email_id, n_retries, execute_after)
+SELECT issue_delivery_queue.newsletter_issue_id, ranked_emails.kept_id,
+    min(issue_delivery_queue.n_retries), min(issue_delivery_queue.execute_after)
+FROM issue_delivery_queue
+JOIN ranked_emails ON ranked_emails.id = issue_delivery_queue.email_id
+GROUP BY issue_delivery_queue.newsletter_issue_id, ranked_emails.kept_id
+ON CONFLICT DO NOTHING;
+
+DELETE FROM emails
+USING ranked_emails
+WHERE ranked_emails.id = emails.id;
+
+CREATE UNIQUE INDEX emails_email_lower_key
+ON emails (lower(email));
@@ -0,0 +1,3 @@
+DROP INDEX users_username_skeleton_key;
+
+DROP FUNCTION username_skeleton(text);
@@ -0,0 +1,25 @@
+-- Usernames that only differ in case, separators or characters that look alike
+-- (e.g. `l`, `1` and `I`) have the same skeleton and can't coexist.
+-- Keep in sync with `username_skeleton` in `src/models/usernames.rs`.
+CREATE FUNCTION username_skeleton(username text) RETURNS text
+    LANGUAGE sql IMMUTABLE STRICT PARALLEL SAFE
+    AS $$
+        SELECT replace(replace(translate(lower(username), '01i._-', 'oll'), 'rn', 'm'), 'vv', 'w')
+    $$;
+
+-- Existing usernames may already collide: the oldest account of each skeleton keeps its
+-- username, and the others get the start of theirs followed by the start of their id,
+-- which they can change afterwards.
+WITH colliding AS (
+    SELECT id, row_number() OVER (
+        PARTITION BY username_skeleton(username)
+        ORDER BY created_at, id
+    ) AS position
+    FROM users
+)
+UPDATE users
+SET username = left(users.username, 22) || '-' || left(replace(users.id::text, '-', ''), 8)
+FROM colliding
+WHERE colliding.id = users.id AND colliding.position > 1;
+
+CREATE UNIQUE INDEX users_username_skeleton_key ON users (username_skeleton(username));
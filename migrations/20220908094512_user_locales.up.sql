@@ -0,0 +1,4 @@
+-- The language of the emails sent to each user.
+-- Keep in sync with `Locale` in `src/i18n.rs`.
+ALTER TABLE users
+    ADD COLUMN locale text NOT NULL DEFAULT 'es' CHECK (locale IN ('es', 'en'));
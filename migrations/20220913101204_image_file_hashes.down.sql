@@ -0,0 +1,4 @@
+ALTER TABLE image_files DROP COLUMN ref_count;
+
+DROP INDEX image_files_sha256_key;
+ALTER TABLE image_files DROP COLUMN sha256;
@@ -0,0 +1,13 @@
+-- Files with the same content are stored once, and shared by the images that use them.
+-- Files stored before aren't hashed, so they're never shared.
+ALTER TABLE image_files ADD COLUMN sha256 bytea CHECK (length(sha256) = 32);
+CREATE UNIQUE INDEX image_files_sha256_key ON image_files (sha256);
+
+-- The images using each file, whatever the sizes they use it for.
+-- A file is deleted along with its row once the last of them is.
+ALTER TABLE image_files ADD COLUMN ref_count integer NOT NULL DEFAULT 0 CHECK (ref_count >= 0);
+UPDATE image_files
+SET ref_count = (
+    SELECT count(*) FROM images
+    WHERE image_files.id IN (small_file_id, medium_file_id, large_file_id)
+);
@@ -0,0 +1,103 @@
+use axum::{
+    async_trait,
+    extract::{FromRequest, RequestParts, TypedHeader},
+    headers::{authorization::Basic, Authorization},
+    Extension,
+};
+use secrecy::SecretString;
+use uuid::Uuid;
+
+use super::{validate_credentials, AuthError, Credentials};
+use crate::{
+    client_ip::ClientIp, erro::AppError, metrics::METRICS, rate_limit::RateLimiter,
+    repositories::UserRepository,
+};
+
+/// The id of the user that sent the request.
+///
+/// Extracting it authenticates the request using the `Authorization` header,
+/// rejecting it with `401 Unauthorized` if the credentials are missing or invalid.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthenticatedUser(pub Uuid);
+
+#[async_trait]
+impl<B> FromRequest<B> for AuthenticatedUser
+where
+    B: Send,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let TypedHeader(Authorization(basic)) =
+            TypedHeader::<Authorization<Basic>>::from_request(req)
+                .await
+                .map_err(|_| AppError::Unauthorized)?;
+        let Extension(user_repository) = Extension::<UserRepository>::from_request(req)
+            .await
+            .map_err(|e| eyre::eyre!("user repository missing from extensions: {e}"))?;
+
+        let Extension(rate_limiter) = Extension::<RateLimiter>::from_request(req)
+            .await
+            .map_err(|e| eyre::eyre!("rate limiter missing from extensions: {e}"))?;
+        let client_ip = req
+            .extensions()
+            .get::<ClientIp>()
+            .map(|ClientIp(client_ip)| *client_ip);
+
+        // Rejected before checking the password, so guessing it is just as slow
+        rate_limiter
+            .check_login(basic.username(), client_ip)
+            .await?;
+
+        let credentials = Credentials {
+            username: basic.username().to_string(),
+            password: SecretString::new(basic.password().to_string()),
+        };
+
+        match validate_credentials(credentials, &user_repository).await {
+            Ok(user_id) => {
+                METRICS.record_auth_attempt(true);
+                rate_limiter
+                    .record_successful_login(basic.username(), client_ip)
+                    .await;
+                tracing::Span::current().record("user_id", &tracing::field::display(user_id));
+                Ok(Self(user_id))
+            }
+            Err(AuthError::InvalidCredentials(_)) => {
+                METRICS.record_auth_attempt(false);
+                rate_limiter
+                    .record_failed_login(basic.username(), client_ip)
+                    .await;
+                Err(AppError::Unauthorized)
+            }
+            Err(AuthError::UnexpectedError(e)) => Err(AppError::Eyre(e)),
+        }
+    }
+}
+
+/// The id of an authenticated user with the `admin` role.
+///
+/// Extracting it rejects the request with `403 Forbidden` if the user is not an admin.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthenticatedAdmin(pub Uuid);
+
+#[async_trait]
+impl<B> FromRequest<B> for AuthenticatedAdmin
+where
+    B: Send,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let AuthenticatedUser(user_id) = AuthenticatedUser::from_request(req).await?;
+        let Extension(user_repository) = Extension::<UserRepository>::from_request(req)
+            .await
+            .map_err(|e| eyre::eyre!("user repository missing from extensions: {e}"))?;
+
+        if user_repository.has_role(user_id, "admin").await? {
+            Ok(Self(user_id))
+        } else {
+            Err(AppError::Forbidden)
+        }
+    }
+}
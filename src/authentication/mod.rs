@@ -1 +1,7 @@
+mod extractors;
+mod password;
+mod password_policy;
 
+pub use extractors::*;
+pub use password::*;
+pub use password_policy::*;
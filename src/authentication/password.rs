@@ -0,0 +1,121 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+};
+use eyre::{Context, Result};
+use secrecy::{ExposeSecret, SecretString};
+use uuid::Uuid;
+
+use crate::{repositories::UserRepository, telemetry::spawn_blocking_with_tracing};
+
+/// A plain text password sent by a user, e.g. when registering.
+///
+/// Serializing it, e.g. as part of a validation error, never reveals its value.
+#[derive(serde::Deserialize)]
+#[serde(transparent)]
+pub struct Password(SecretString);
+
+impl Password {
+    #[must_use]
+    pub fn new(password: String) -> Self {
+        Self(SecretString::new(password))
+    }
+
+    #[must_use]
+    pub fn into_secret(self) -> SecretString {
+        self.0
+    }
+}
+
+impl ExposeSecret<String> for Password {
+    fn expose_secret(&self) -> &String {
+        self.0.expose_secret()
+    }
+}
+
+impl serde::Serialize for Password {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("[REDACTED]")
+    }
+}
+
+/// The username and password a user provides to authenticate.
+pub struct Credentials {
+    pub username: String,
+    pub password: SecretString,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("invalid credentials")]
+    InvalidCredentials(#[source] eyre::Report),
+    #[error(transparent)]
+    UnexpectedError(#[from] eyre::Report),
+}
+
+/// Check the provided credentials against the stored ones of an active user.
+///
+/// Returns the id of the authenticated user.
+#[tracing::instrument(name = "validate credentials", skip(credentials, user_repository))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    user_repository: &UserRepository,
+) -> Result<Uuid, AuthError> {
+    let mut user_id = None;
+    // Verify a hash even when the user does not exist so that the response
+    // time doesn't reveal which usernames are registered.
+    let mut expected_password_hash = SecretString::new(
+        "$argon2id$v=19$m=15000,t=2,p=1$\
+        gZiV/M1gPc22ElAH/Jh1Hw$\
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+            .to_string(),
+    );
+
+    if let Some((stored_user_id, stored_password_hash)) = user_repository
+        .get_credentials(&credentials.username)
+        .await
+        .wrap_err("failed to retrieve stored credentials")?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(&expected_password_hash, &credentials.password)
+    })
+    .await
+    .wrap_err("failed to spawn blocking task")??;
+
+    user_id
+        .ok_or_else(|| eyre::eyre!("unknown username"))
+        .map_err(AuthError::InvalidCredentials)
+}
+
+fn verify_password_hash(
+    expected_password_hash: &SecretString,
+    password_candidate: &SecretString,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .wrap_err("failed to parse hash in PHC string format")?;
+
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .wrap_err("invalid password")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+/// Hash a password with Argon2id, returning it in PHC string format.
+pub fn compute_password_hash(password: &SecretString) -> Result<SecretString> {
+    let salt = SaltString::generate(&mut OsRng);
+    let params = Params::new(15000, 2, 1, None)
+        .map_err(|e| eyre::eyre!("invalid password hashing parameters: {e}"))?;
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.expose_secret().as_bytes(), &salt)
+        .map_err(|e| eyre::eyre!("failed to hash password: {e}"))?
+        .to_string();
+
+    Ok(SecretString::new(password_hash))
+}
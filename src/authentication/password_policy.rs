@@ -0,0 +1,407 @@
+use std::path::PathBuf;
+
+use eyre::WrapErr;
+use secrecy::{ExposeSecret, SecretString};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    configuration::PasswordSettings,
+    erro::{AppError, FieldError},
+    telemetry::spawn_blocking_with_tracing,
+};
+
+/// The longest password allowed, so hashing it stays cheap.
+const PASSWORD_MAX_LENGTH: usize = 128;
+
+/// Only the start of longer passwords is analysed by [`estimate_guesses`], as in zxcvbn,
+/// since the cost grows with the cube of the length.
+const MAX_ANALYSED_LENGTH: usize = 100;
+
+/// Inputs shorter than this, e.g. the `jo` of `jo@doe.com`, can be part of any password.
+const MIN_USER_INPUT_LENGTH: usize = 4;
+
+/// Frequent base words of leaked passwords, most frequent first.
+const COMMON_WORDS: [&str; 48] = [
+    "password",
+    "123456",
+    "qwerty",
+    "contrasena",
+    "clave",
+    "teamo",
+    "iloveyou",
+    "admin",
+    "welcome",
+    "letmein",
+    "monkey",
+    "dragon",
+    "football",
+    "futbol",
+    "baseball",
+    "princesa",
+    "princess",
+    "sunshine",
+    "master",
+    "shadow",
+    "superman",
+    "batman",
+    "trustno1",
+    "hello",
+    "hola",
+    "amor",
+    "freedom",
+    "whatever",
+    "starwars",
+    "michael",
+    "charlie",
+    "secret",
+    "secreto",
+    "chocolate",
+    "barcelona",
+    "emelec",
+    "ecuador",
+    "guayaquil",
+    "quito",
+    "espol",
+    "kokoa",
+    "login",
+    "abc123",
+    "passw0rd",
+    "mustang",
+    "access",
+    "flower",
+    "computer",
+];
+
+/// Rows of a QWERTY keyboard, so walks like `asdfg` are cheap to guess.
+const KEYBOARD_ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+/// Guesses an attacker would need for each score, as in zxcvbn.
+const SCORE_THRESHOLDS: [f64; 4] = [1e3, 1e6, 1e8, 1e10];
+
+/// The rules passwords chosen by users must follow.
+#[derive(Clone, Debug)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    min_score: u8,
+    breached_passwords: Option<PathBuf>,
+}
+
+impl PasswordPolicy {
+    pub fn new(settings: PasswordSettings) -> Self {
+        if let Some(directory) = &settings.breached_passwords {
+            if !directory.is_dir() {
+                tracing::warn!(?directory, "breached passwords directory not found");
+            }
+        }
+
+        Self {
+            min_length: settings.min_length,
+            min_score: settings.min_score,
+            breached_passwords: settings.breached_passwords,
+        }
+    }
+
+    /// Check that `password` is long and hard enough to guess, that it doesn't contain
+    /// any of the `user_inputs` (e.g. the username or email), and that it hasn't been
+    /// found in a data breach.
+    ///
+    /// Violations are reported on the `password` field as `422 Unprocessable Entity`.
+    /// The check is CPU bound, so it runs on a blocking thread.
+    pub async fn check(
+        &self,
+        password: &SecretString,
+        user_inputs: &[&str],
+    ) -> Result<(), AppError> {
+        let policy = self.clone();
+        let password = password.clone();
+        let user_inputs: Vec<String> = user_inputs.iter().map(|input| input.to_string()).collect();
+        spawn_blocking_with_tracing(move || {
+            let user_inputs: Vec<&str> = user_inputs.iter().map(String::as_str).collect();
+            policy.check_blocking(password.expose_secret(), &user_inputs)
+        })
+        .await
+        .wrap_err("failed to spawn blocking task")?
+    }
+
+    fn check_blocking(&self, password: &str, user_inputs: &[&str]) -> Result<(), AppError> {
+        let length = password.chars().count();
+
+        let error = if length < self.min_length {
+            FieldError::new("too_short").with_param("min", self.min_length as u64)
+        } else if length > PASSWORD_MAX_LENGTH {
+            FieldError::new("too_long").with_param("max", PASSWORD_MAX_LENGTH as u64)
+        } else if is_derived_from(password, user_inputs) {
+            FieldError::new("derived_from_user_data")
+        } else if score(estimate_guesses(password)) < self.min_score {
+            FieldError::new("too_weak")
+        } else if self.is_breached(password)? {
+            FieldError::new("breached")
+        } else {
+            return Ok(());
+        };
+
+        Err(AppError::unprocessable_entity([("password", error)]))
+    }
+
+    /// Look for `password` in a local copy of the Pwned Passwords range files.
+    ///
+    /// Each file is named after the first 5 hex characters of the SHA-1 of the passwords
+    /// it lists, e.g. `21BD1.txt`, and has one `SUFFIX:COUNT` line per password.
+    fn is_breached(&self, password: &str) -> eyre::Result<bool> {
+        let directory = match &self.breached_passwords {
+            Some(directory) => directory,
+            None => return Ok(false),
+        };
+
+        let hash = format!("{:X}", Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(5);
+
+        let range = match std::fs::read_to_string(directory.join(format!("{prefix}.txt"))) {
+            Ok(range) => range,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(error).wrap_err("failed to read breached passwords"),
+        };
+
+        Ok(range.lines().any(|line| {
+            line.split(':').next().map_or(false, |candidate| {
+                candidate.trim().eq_ignore_ascii_case(suffix)
+            })
+        }))
+    }
+}
+
+/// Lowercase `value` keeping only its letters and digits.
+fn normalize(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Whether `password` contains one of the `user_inputs` or a word of them, or is part of
+/// one of them.
+///
+/// Both are compared once normalized, and short ones are skipped: a password of symbols
+/// normalizes to nothing, which is part of anything.
+fn is_derived_from(password: &str, user_inputs: &[&str]) -> bool {
+    let password = normalize(password);
+    let password_is_long = password.chars().count() >= MIN_USER_INPUT_LENGTH;
+
+    user_inputs
+        .iter()
+        .flat_map(|input| {
+            let words = input.split(|c: char| !c.is_alphanumeric()).map(normalize);
+            std::iter::once(normalize(input)).chain(words)
+        })
+        .filter(|input| input.chars().count() >= MIN_USER_INPUT_LENGTH)
+        .any(|input| password.contains(&input) || (password_is_long && input.contains(&password)))
+}
+
+/// The score of a password, from 0 (too guessable) to 4 (very unguessable), as in zxcvbn.
+fn score(guesses: f64) -> u8 {
+    SCORE_THRESHOLDS
+        .iter()
+        .take_while(|threshold| guesses >= **threshold)
+        .count() as u8
+}
+
+/// A part of a password that follows a pattern, and the guesses needed to find it.
+struct Match {
+    start: usize,
+    end: usize,
+    guesses: f64,
+}
+
+/// Estimate the guesses an attacker needs to find `password`, in the spirit of zxcvbn.
+///
+/// The password is split in the sequence of patterns (common words, repeats, sequences,
+/// keyboard walks, years and brute force) that is the cheapest to guess. Only its first
+/// [`MAX_ANALYSED_LENGTH`] characters are analysed.
+fn estimate_guesses(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().take(MAX_ANALYSED_LENGTH).collect();
+    let n = chars.len();
+    if n == 0 {
+        return 0.0;
+    }
+    let mut matches_by_end: Vec<Vec<Match>> = (0..=n).map(|_| Vec::new()).collect();
+    for m in find_matches(&chars) {
+        matches_by_end[m.end].push(m);
+    }
+
+    // `best[end][k]` is the fewest guesses of `k` patterns covering `chars[..end]`.
+    let mut best = vec![vec![f64::INFINITY; n + 1]; n + 1];
+    best[0][0] = 1.0;
+    for end in 1..=n {
+        for k in 1..=end {
+            let from_matches = matches_by_end[end]
+                .iter()
+                .map(|m| best[m.start][k - 1] * m.guesses);
+            let from_brute_force =
+                (0..end).map(|start| best[start][k - 1] * brute_force_guesses(end - start));
+            best[end][k] = from_matches
+                .chain(from_brute_force)
+                .fold(f64::INFINITY, f64::min);
+        }
+    }
+
+    // Guessing the patterns is not enough, the attacker also has to find how many
+    // there are and their order.
+    (1..=n)
+        .map(|k| {
+            let factorial: f64 = (1..=k).map(|i| i as f64).product();
+            factorial * best[n][k] + 1e4_f64.powi(k as i32 - 1)
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+fn brute_force_guesses(length: usize) -> f64 {
+    let guesses = 10_f64.powi(length as i32);
+    let min_guesses = if length == 1 { 11.0 } else { 51.0 };
+    guesses.max(min_guesses)
+}
+
+fn find_matches(chars: &[char]) -> Vec<Match> {
+    let lowercase: Vec<char> = chars.iter().flat_map(|c| c.to_lowercase()).collect();
+    // Lowercasing some characters, e.g. `İ`, produces more than one.
+    let lowercase = if lowercase.len() == chars.len() {
+        lowercase
+    } else {
+        chars.to_vec()
+    };
+    let unleet: Vec<char> = lowercase.iter().map(|c| unleet(*c)).collect();
+    let n = chars.len();
+    let mut matches = Vec::new();
+
+    for start in 0..n {
+        for end in start + 1..=n {
+            let length = end - start;
+            let uppercase = chars[start..end].iter().any(|c| c.is_uppercase());
+            let case_factor = if uppercase { 2.0 } else { 1.0 };
+
+            let word: String = lowercase[start..end].iter().collect();
+            let unleeted: String = unleet[start..end].iter().collect();
+            if let Some(rank) = COMMON_WORDS.iter().position(|w| *w == word) {
+                matches.push(Match {
+                    start,
+                    end,
+                    guesses: (rank + 1) as f64 * case_factor,
+                });
+            } else if let Some(rank) = COMMON_WORDS.iter().position(|w| *w == unleeted) {
+                matches.push(Match {
+                    start,
+                    end,
+                    guesses: (rank + 1) as f64 * case_factor * 2.0,
+                });
+            }
+
+            if length >= 3 && lowercase[start..end].iter().all(|c| *c == lowercase[start]) {
+                matches.push(Match {
+                    start,
+                    end,
+                    guesses: 10.0 * length as f64 * case_factor,
+                });
+            }
+
+            if length >= 3 && is_sequence(&lowercase[start..end]) {
+                let base = if lowercase[start].is_ascii_digit() {
+                    10.0
+                } else {
+                    26.0
+                };
+                matches.push(Match {
+                    start,
+                    end,
+                    guesses: base * length as f64 * case_factor,
+                });
+            }
+
+            if length >= 4 && is_keyboard_walk(&word) {
+                matches.push(Match {
+                    start,
+                    end,
+                    guesses: 40.0 * length as f64 * case_factor,
+                });
+            }
+
+            if length == 4 {
+                if let Ok(year) = word.parse::<u16>() {
+                    if (1900..=2049).contains(&year) {
+                        matches.push(Match {
+                            start,
+                            end,
+                            guesses: 150.0,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    matches
+}
+
+/// Undo common substitutions, e.g. `p4ssw0rd` for `password`.
+fn unleet(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' | '!' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' => 't',
+        c => c,
+    }
+}
+
+/// Whether `chars` are consecutive in the alphabet or the digits, e.g. `abcd` or `9876`.
+fn is_sequence(chars: &[char]) -> bool {
+    let alphanumeric = chars.iter().all(|c| c.is_ascii_alphanumeric());
+    let steps: Vec<i32> = chars
+        .windows(2)
+        .map(|pair| pair[1] as i32 - pair[0] as i32)
+        .collect();
+    alphanumeric && (steps.iter().all(|s| *s == 1) || steps.iter().all(|s| *s == -1))
+}
+
+fn is_keyboard_walk(word: &str) -> bool {
+    let reversed: String = word.chars().rev().collect();
+    KEYBOARD_ROWS
+        .iter()
+        .any(|row| row.contains(word) || row.contains(&reversed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_containing_a_user_input_are_derived_from_it() {
+        assert!(is_derived_from("Hunter-2022!", &["hunter"]));
+        assert!(is_derived_from("xx-jdoe-xx", &["jdoe@example.com"]));
+    }
+
+    #[test]
+    fn passwords_that_are_part_of_a_user_input_are_derived_from_it() {
+        assert!(is_derived_from("Doe.Example", &["jdoe@example.com"]));
+    }
+
+    #[test]
+    fn short_normalized_passwords_are_not_derived_from_anything() {
+        assert!(!is_derived_from(
+            "!@#$%^&*()_+",
+            &["jdoe", "jdoe@example.com"]
+        ));
+        assert!(!is_derived_from("..doe..", &["jdoe@example.com"]));
+    }
+
+    #[test]
+    fn long_passwords_are_only_analysed_up_to_the_limit() {
+        let long = "a".repeat(10 * MAX_ANALYSED_LENGTH);
+
+        assert_eq!(
+            estimate_guesses(&"a".repeat(MAX_ANALYSED_LENGTH)),
+            estimate_guesses(&long)
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use axum::{
+    body::Body,
+    extract::MatchedPath,
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+
+use crate::erro::AppError;
+
+/// The largest request body of each route.
+#[derive(Debug, Clone)]
+pub struct BodyLimits {
+    default: usize,
+    routes: Arc<HashMap<&'static str, usize>>,
+}
+
+impl BodyLimits {
+    /// Limit the body of every route to `default` bytes.
+    pub fn new(default: usize) -> Self {
+        Self {
+            default,
+            routes: Arc::default(),
+        }
+    }
+
+    /// Limit the body of the route at `path` to `limit` bytes instead.
+    #[must_use]
+    pub fn route(mut self, path: &'static str, limit: usize) -> Self {
+        Arc::make_mut(&mut self.routes).insert(path, limit);
+        self
+    }
+
+    fn limit(&self, path: Option<&str>) -> usize {
+        path.and_then(|path| self.routes.get(path))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// The error of a body cut off by [`limit_request_body`].
+#[derive(Debug)]
+struct BodyTooLarge;
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the request body is too large")
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+/// Return `413 Payload Too Large` if the body of the request is larger than the limit
+/// of its route.
+///
+/// A `Content-Length` above the limit is rejected right away. Otherwise the body is cut
+/// off once it goes over, which fails the handler reading it, and its response is
+/// replaced. It has to be a route layer, to know the route of the request.
+pub async fn limit_request_body(
+    limits: BodyLimits,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let path = request.extensions().get::<MatchedPath>();
+    let limit = limits.limit(path.map(MatchedPath::as_str));
+
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if content_length.map_or(false, |length| length > limit as u64) {
+        return AppError::PayloadTooLarge.into_response();
+    }
+
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let request = {
+        let exceeded = exceeded.clone();
+        let mut remaining = limit;
+        request.map(|body| {
+            Body::wrap_stream(body.map(move |chunk| {
+                let chunk = chunk?;
+                if chunk.len() > remaining {
+                    exceeded.store(true, Ordering::Relaxed);
+                    return Err(Box::new(BodyTooLarge) as axum::BoxError);
+                }
+                remaining -= chunk.len();
+                Ok(chunk)
+            }))
+        })
+    };
+
+    let response = next.run(request).await;
+    if exceeded.load(Ordering::Relaxed) {
+        return AppError::PayloadTooLarge.into_response();
+    }
+    response
+}
@@ -0,0 +1,80 @@
+use eyre::{eyre, Result, WrapErr};
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{compute_password_hash, PasswordPolicy},
+    configuration::Settings,
+    erro::AppError,
+    i18n::Locale,
+    models::{validate_username, EmailAddress, InsertableUserBuilder},
+    repositories::{UnitOfWork, UserRepository},
+    storage::BlobStore,
+    telemetry::spawn_blocking_with_tracing,
+};
+
+/// Create a user with the `admin` role, returning its id.
+///
+/// The password must follow the same policy as the ones chosen when registering, but
+/// reserved usernames are allowed, as they're meant for accounts like this one.
+pub async fn create_admin(
+    pool: &PgPool,
+    configuration: &Settings,
+    username: &str,
+    email: &str,
+    password: SecretString,
+) -> Result<Uuid> {
+    validate_username(username).map_err(|_| eyre!("`{username}` is not a valid username"))?;
+    let email =
+        EmailAddress::parse(email).ok_or_else(|| eyre!("`{email}` is not a valid email"))?;
+
+    PasswordPolicy::new(configuration.passwords.clone())
+        .check(&password, &[username, email.as_ref()])
+        .await
+        .map_err(describe)?;
+    let passwd_hash = spawn_blocking_with_tracing(move || compute_password_hash(&password))
+        .await
+        .wrap_err("failed to spawn blocking task")??;
+
+    let user = InsertableUserBuilder::new()
+        .with_username(username.to_string())
+        .with_email(email)
+        .with_password_hash(passwd_hash.expose_secret().to_string())
+        .with_locale(Locale::default())
+        .build()
+        .map_err(|_| eyre!("incomplete admin user"))?;
+
+    let user_repository = UserRepository::new(pool.clone());
+    let blob_store = BlobStore::new(configuration.storage.root.clone());
+    let mut uow = UnitOfWork::begin(pool, blob_store).await?;
+    let user = user_repository
+        .create_user(uow.connection(), user)
+        .await
+        .map_err(describe)?;
+    user_repository
+        .add_role(uow.connection(), user.id, "admin")
+        .await?;
+    uow.commit().await?;
+
+    Ok(user.id)
+}
+
+/// Turn the field errors of a rejected request into a message for the terminal.
+fn describe(error: AppError) -> eyre::Report {
+    match &error {
+        AppError::Conflict(_, errors) | AppError::UnprocessableEntity(errors) => {
+            let mut messages = errors
+                .iter()
+                .flat_map(|(field, errors)| {
+                    errors
+                        .iter()
+                        .map(move |error| format!("{field}: {}", error.message(Locale::En)))
+                })
+                .collect::<Vec<_>>();
+            messages.sort();
+            eyre!("{}", messages.join(", "))
+        }
+        _ => eyre::Report::new(error),
+    }
+}
@@ -0,0 +1,99 @@
+use std::{collections::HashSet, fmt, time::Duration};
+
+use eyre::{Result, WrapErr};
+use sqlx::PgPool;
+use time::OffsetDateTime;
+
+use crate::storage::BlobStore;
+
+/// What `collect_image_garbage` deleted, or would delete on a dry run.
+#[derive(Debug, Default)]
+pub struct GarbageReport {
+    /// Rows of `image_files` no image used.
+    pub deleted_rows: u64,
+    /// Keys of the stored files no row referenced.
+    pub deleted_files: Vec<String>,
+}
+
+impl fmt::Display for GarbageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} image file rows and {} stored files",
+            self.deleted_rows,
+            self.deleted_files.len()
+        )
+    }
+}
+
+/// Delete the image files no image uses, and the stored files no row references.
+///
+/// Files are left behind when a process dies between storing them and committing its
+/// transaction, or between committing and deleting them. Rows are deleted like in
+/// `ImageRepository::release_files`, once their `ref_count` is 0, so an image acquiring
+/// one at the same time keeps it. Stored files younger than `min_age` are kept, since
+/// they may belong to a transaction still in progress. On a `dry_run` the report lists
+/// what would be deleted, and nothing is.
+pub async fn collect_image_garbage(
+    pool: &PgPool,
+    blob_store: &BlobStore,
+    min_age: Duration,
+    dry_run: bool,
+) -> Result<GarbageReport> {
+    let mut report = GarbageReport::default();
+
+    let mut transaction = pool.begin().await?;
+    // The rows are locked, and checked again once the locks of other transactions are
+    // released, so a file acquired in the meantime is kept.
+    let released: HashSet<String> = sqlx::query!(
+        r#"
+        DELETE FROM image_files
+        WHERE ref_count = 0
+        RETURNING file_path
+        "#
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .wrap_err("failed to delete unused image files")?
+    .into_iter()
+    .map(|record| record.file_path)
+    .collect();
+    report.deleted_rows = released.len() as u64;
+
+    // The files of the rows deleted above aren't referenced anymore
+    let referenced: HashSet<String> = sqlx::query!(
+        r#"
+        SELECT file_path AS "file_path!" FROM image_files
+        UNION ALL
+        SELECT file_path AS "file_path!" FROM data_exports WHERE file_path IS NOT NULL
+        "#
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .wrap_err("failed to list referenced files")?
+    .into_iter()
+    .map(|record| record.file_path)
+    .collect();
+
+    let now = OffsetDateTime::now_utc();
+    for (key, modified) in blob_store.list().await? {
+        let age = now - OffsetDateTime::from(modified);
+        if !referenced.contains(&key) && (age >= min_age || released.contains(&key)) {
+            report.deleted_files.push(key);
+        }
+    }
+
+    if dry_run {
+        transaction.rollback().await?;
+        return Ok(report);
+    }
+
+    // Commit first, so a failure never leaves rows pointing to deleted files
+    transaction.commit().await?;
+    for key in &report.deleted_files {
+        blob_store.delete(key).await?;
+        tracing::info!(key, "deleted unused stored file");
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,100 @@
+use std::{collections::HashMap, fmt};
+
+use eyre::{Result, WrapErr};
+use sqlx::{migrate::Migrate, PgPool};
+
+use crate::startup::MIGRATOR;
+
+/// A migration of `migrations/` and whether it's applied to the database.
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Applied, but its file changed afterwards.
+    Modified,
+}
+
+impl fmt::Display for MigrationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self.state {
+            MigrationState::Applied => "applied",
+            MigrationState::Pending => "pending",
+            MigrationState::Modified => "modified",
+        };
+        write!(f, "{:<8} {} {}", state, self.version, self.description)
+    }
+}
+
+/// Apply the pending migrations, returning how many were applied.
+pub async fn migrate_up(pool: &PgPool) -> Result<usize> {
+    let before = count_applied(pool).await?;
+    MIGRATOR
+        .run(pool)
+        .await
+        .wrap_err("failed to migrate the database")?;
+    Ok(count_applied(pool).await? - before)
+}
+
+/// Revert the last `steps` applied migrations, returning how many were reverted.
+pub async fn migrate_down(pool: &PgPool, steps: usize) -> Result<usize> {
+    let mut applied = applied_versions(pool).await?;
+    applied.sort_unstable_by(|a, b| b.cmp(a));
+
+    // `undo` reverts every migration newer than the target
+    let target = applied.get(steps).copied().unwrap_or(0);
+    MIGRATOR
+        .undo(pool, target)
+        .await
+        .wrap_err("failed to revert migrations")?;
+    Ok(applied.len() - count_applied(pool).await?)
+}
+
+/// List the migrations of `migrations/`, oldest first, and whether they're applied.
+pub async fn migration_status(pool: &PgPool) -> Result<Vec<MigrationStatus>> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    let applied: HashMap<_, _> = conn
+        .list_applied_migrations()
+        .await
+        .wrap_err("failed to list applied migrations")?
+        .into_iter()
+        .map(|migration| (migration.version, migration.checksum))
+        .collect();
+
+    Ok(MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| MigrationStatus {
+            version: migration.version,
+            description: migration.description.to_string(),
+            state: match applied.get(&migration.version) {
+                Some(checksum) if *checksum == migration.checksum => MigrationState::Applied,
+                Some(_) => MigrationState::Modified,
+                None => MigrationState::Pending,
+            },
+        })
+        .collect())
+}
+
+async fn applied_versions(pool: &PgPool) -> Result<Vec<i64>> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    Ok(conn
+        .list_applied_migrations()
+        .await
+        .wrap_err("failed to list applied migrations")?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect())
+}
+
+async fn count_applied(pool: &PgPool) -> Result<usize> {
+    Ok(applied_versions(pool).await?.len())
+}
@@ -0,0 +1,179 @@
+//! The management commands of the `chocoapi` binary, e.g. `chocoapi migrate status`.
+//!
+//! Running the binary without a command serves the API, so deployments that only run
+//! `chocoapi` keep working.
+
+mod admin;
+mod images;
+mod migrate;
+mod seed;
+
+pub use admin::*;
+pub use images::*;
+pub use migrate::*;
+pub use seed::*;
+
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use eyre::{bail, Result, WrapErr};
+use secrecy::SecretString;
+
+use crate::{
+    configuration::{Environment, Settings},
+    startup::get_connection_pool,
+    storage::BlobStore,
+};
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "chocoapi",
+    version,
+    about = "API/Backend for projects of Kokoa club"
+)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Serve the API and run the background workers (the default)
+    Serve,
+    /// Manage the schema of the database
+    #[clap(subcommand)]
+    Migrate(MigrateCommand),
+    /// Create a user with the admin role, asking for its password
+    CreateAdmin {
+        #[clap(long)]
+        username: String,
+        #[clap(long)]
+        email: String,
+    },
+    /// Fill the database with demo users, posts and subscribers
+    Seed {
+        /// Seed the database even in production
+        #[clap(long)]
+        force: bool,
+    },
+    /// Inspect the configuration
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+    /// Maintain the stored images
+    #[clap(subcommand)]
+    Images(ImagesCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MigrateCommand {
+    /// Apply the pending migrations
+    Up,
+    /// Revert the last applied migrations
+    Down {
+        /// How many migrations to revert
+        #[clap(long, default_value_t = 1)]
+        steps: usize,
+    },
+    /// List the migrations and whether they're applied
+    Status,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Validate the configuration and print it, with its secrets redacted
+    Check,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ImagesCommand {
+    /// Delete the files and rows no image uses anymore
+    Gc {
+        /// List what would be deleted without deleting it
+        #[clap(long)]
+        dry_run: bool,
+        /// Keep files younger than this, as uploads in progress may not be in the database yet
+        #[clap(long, default_value_t = 3600)]
+        min_age_seconds: u64,
+    },
+}
+
+/// Run a management command. Serving the API is left to the binary, which owns the workers.
+pub async fn run(
+    command: Command,
+    environment: Environment,
+    configuration: Settings,
+) -> Result<()> {
+    match command {
+        Command::Serve => bail!("`serve` is run by the binary"),
+        Command::Migrate(command) => {
+            let pool = get_connection_pool(&configuration.database).await?;
+            match command {
+                MigrateCommand::Up => {
+                    let applied = migrate_up(&pool).await?;
+                    println!("applied {applied} migrations");
+                }
+                MigrateCommand::Down { steps } => {
+                    let reverted = migrate_down(&pool, steps).await?;
+                    println!("reverted {reverted} migrations");
+                }
+                MigrateCommand::Status => {
+                    for migration in migration_status(&pool).await? {
+                        println!("{migration}");
+                    }
+                }
+            }
+        }
+        Command::CreateAdmin { username, email } => {
+            let password = prompt_password()?;
+            let pool = get_connection_pool(&configuration.database).await?;
+            let id = create_admin(&pool, &configuration, &username, &email, password).await?;
+            println!("created admin {username} ({id})");
+        }
+        Command::Seed { force } => {
+            if matches!(environment, Environment::Production) && !force {
+                bail!("refusing to seed a production database, use --force to do it anyway");
+            }
+            let pool = get_connection_pool(&configuration.database).await?;
+            let blob_store = BlobStore::new(configuration.storage.root.clone());
+            let report = seed(&pool, &blob_store).await?;
+            println!("{report}");
+        }
+        Command::Config(ConfigCommand::Check) => {
+            println!("{configuration:#?}");
+            let problems = configuration.check();
+            if !problems.is_empty() {
+                for problem in &problems {
+                    eprintln!("error: {problem}");
+                }
+                bail!("found {} problems in the configuration", problems.len());
+            }
+            println!("the configuration is valid");
+        }
+        Command::Images(ImagesCommand::Gc {
+            dry_run,
+            min_age_seconds,
+        }) => {
+            let pool = get_connection_pool(&configuration.database).await?;
+            let blob_store = BlobStore::new(configuration.storage.root.clone());
+            let min_age = Duration::from_secs(min_age_seconds);
+            let report = collect_image_garbage(&pool, &blob_store, min_age, dry_run).await?;
+            for key in &report.deleted_files {
+                println!("{} {key}", if dry_run { "would delete" } else { "deleted" });
+            }
+            println!("{report}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Ask for a password twice on the terminal, without echoing it.
+fn prompt_password() -> Result<SecretString> {
+    let password = rpassword::prompt_password("Password: ").wrap_err("failed to read password")?;
+    let confirmation =
+        rpassword::prompt_password("Repeat password: ").wrap_err("failed to read password")?;
+    if password != confirmation {
+        bail!("the passwords don't match");
+    }
+    Ok(SecretString::new(password))
+}
@@ -0,0 +1,186 @@
+use std::{fmt, io::Cursor};
+
+use eyre::{Result, WrapErr};
+use image::{ImageBuffer, ImageOutputFormat, Rgb};
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::compute_password_hash,
+    i18n::Locale,
+    models::{EmailAddress, InsertableUserBuilder},
+    repositories::{ImageRepository, UnitOfWork, UserRepository},
+    storage::BlobStore,
+    telemetry::spawn_blocking_with_tracing,
+};
+
+/// The password of every demo user.
+pub const DEMO_PASSWORD: &str = "cacao-de-fino-aroma";
+
+/// Demo users, as username, email, locale and whether they're admins.
+const DEMO_USERS: [(&str, &str, Locale, bool); 3] = [
+    ("demo.admin", "demo.admin@example.com", Locale::Es, true),
+    ("ana.demo", "ana.demo@example.com", Locale::Es, false),
+    ("john.demo", "john.demo@example.com", Locale::En, false),
+];
+
+/// Demo posts of the first demo user, as title, short title, slug, description and color
+/// of their cover.
+const DEMO_POSTS: [(&str, &str, &str, &str, [u8; 3]); 3] = [
+    (
+        "Del árbol a la tableta: cómo se hace el chocolate",
+        "Del árbol a la tableta",
+        "del-arbol-a-la-tableta",
+        "Cosecha, fermentación, secado, tostado y conchado del cacao.",
+        [92, 51, 23],
+    ),
+    (
+        "El cacao fino de aroma del Ecuador",
+        "Cacao fino de aroma",
+        "cacao-fino-de-aroma",
+        "Por qué el cacao nacional es apreciado por los chocolateros de todo el mundo.",
+        [123, 63, 0],
+    ),
+    (
+        "Cómo templar chocolate en casa",
+        "Templar chocolate",
+        "templar-chocolate",
+        "Un método sencillo para conseguir un chocolate brillante y crujiente.",
+        [63, 31, 15],
+    ),
+];
+
+/// Demo addresses subscribed to the newsletter, without an account.
+const DEMO_SUBSCRIBERS: [&str; 2] = ["lector@example.com", "reader@example.com"];
+
+/// What `seed` created. Data created by a previous run isn't counted.
+#[derive(Debug, Default)]
+pub struct SeedReport {
+    pub users: usize,
+    pub posts: usize,
+    pub subscribers: usize,
+}
+
+impl fmt::Display for SeedReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "created {} users, {} posts and {} subscribers",
+            self.users, self.posts, self.subscribers
+        )
+    }
+}
+
+/// Fill the database with demo users, published posts and newsletter subscribers.
+///
+/// Running it again only creates what's missing. The demo users can log in with
+/// `DEMO_PASSWORD`.
+pub async fn seed(pool: &PgPool, blob_store: &BlobStore) -> Result<SeedReport> {
+    let mut report = SeedReport::default();
+    let user_repository = UserRepository::new(pool.clone());
+    let image_repository = ImageRepository::new(blob_store.clone());
+    let mut uow = UnitOfWork::begin(pool, blob_store.clone()).await?;
+
+    let password = SecretString::new(DEMO_PASSWORD.to_string());
+    let passwd_hash = spawn_blocking_with_tracing(move || compute_password_hash(&password))
+        .await
+        .wrap_err("failed to spawn blocking task")??;
+
+    let mut author_id = None;
+    for (username, email, locale, admin) in DEMO_USERS {
+        let existing = sqlx::query!("SELECT id FROM users WHERE username = $1", username)
+            .fetch_optional(uow.connection())
+            .await?;
+        let id = match existing {
+            Some(user) => user.id,
+            None => {
+                let email = EmailAddress::parse(email)
+                    .ok_or_else(|| eyre::eyre!("invalid demo email {email}"))?;
+                let user = InsertableUserBuilder::new()
+                    .with_username(username.to_string())
+                    .with_email(email)
+                    .with_password_hash(passwd_hash.expose_secret().to_string())
+                    .with_locale(locale)
+                    .build()
+                    .map_err(|_| eyre::eyre!("incomplete demo user"))?;
+                let user = user_repository.create_user(uow.connection(), user).await?;
+                if admin {
+                    user_repository
+                        .add_role(uow.connection(), user.id, "admin")
+                        .await?;
+                }
+                report.users += 1;
+                user.id
+            }
+        };
+        author_id.get_or_insert(id);
+    }
+    let author_id: Uuid = author_id.ok_or_else(|| eyre::eyre!("no demo users"))?;
+
+    for (title, short_title, slug, description, color) in DEMO_POSTS {
+        let exists = sqlx::query!(
+            r#"SELECT EXISTS (SELECT 1 FROM posts WHERE slug = $1) AS "exists!""#,
+            slug
+        )
+        .fetch_one(uow.connection())
+        .await?
+        .exists;
+        if exists {
+            continue;
+        }
+
+        let mut cover = blob_store.stage().await?;
+        cover.write(&demo_image(color)?).await?;
+        let cover = image_repository
+            .create_image(&mut uow, short_title, cover)
+            .await?;
+        let content = format!("# {title}\n\n{description}\n");
+        sqlx::query!(
+            r#"
+            INSERT INTO posts (title, short_title, slug, description, content, author_id,
+                cover_image_id, og_image_id, published_at, active)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $7, now(), true)
+            "#,
+            title,
+            short_title,
+            slug,
+            description,
+            content,
+            author_id,
+            cover
+        )
+        .execute(uow.connection())
+        .await
+        .wrap_err("failed to insert demo post")?;
+        report.posts += 1;
+    }
+
+    for email in DEMO_SUBSCRIBERS {
+        report.subscribers += sqlx::query!(
+            r#"
+            INSERT INTO emails (email, email_confirmed_at, subscribed)
+            VALUES ($1, now(), true)
+            ON CONFLICT ((lower(email))) DO NOTHING
+            "#,
+            email
+        )
+        .execute(uow.connection())
+        .await
+        .wrap_err("failed to insert demo subscriber")?
+        .rows_affected() as usize;
+    }
+
+    uow.commit().await?;
+    Ok(report)
+}
+
+/// A PNG of a single color, with the size of an Open Graph image.
+fn demo_image(color: [u8; 3]) -> Result<Vec<u8>> {
+    let image = ImageBuffer::from_pixel(1200, 630, Rgb(color));
+    let mut bytes = Cursor::new(Vec::new());
+    image
+        .write_to(&mut bytes, ImageOutputFormat::Png)
+        .wrap_err("failed to encode demo image")?;
+    Ok(bytes.into_inner())
+}
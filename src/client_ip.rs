@@ -0,0 +1,128 @@
+use std::{net::IpAddr, net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+
+/// The address of the client that sent the request, left in its extensions by
+/// [`resolve_client_ip`].
+///
+/// Behind a trusted reverse proxy it's the one the proxy received the request from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// The reverse proxies allowed to tell the address of the client in `X-Forwarded-For`.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Arc<Vec<IpNet>>);
+
+impl TrustedProxies {
+    pub fn new(networks: Vec<IpNet>) -> Self {
+        TrustedProxies(Arc::new(networks))
+    }
+
+    fn contains(&self, address: IpAddr) -> bool {
+        self.0.iter().any(|network| network.contains(&address))
+    }
+
+    /// Find the address of the client of a request received from `peer`.
+    ///
+    /// Each proxy appends the address it received the request from to `X-Forwarded-For`,
+    /// so the client is the last address not added by a trusted proxy. Anything before it
+    /// may have been sent by the client, and is ignored.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.contains(peer) {
+            return peer;
+        }
+
+        let forwarded_for = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>();
+        let mut client_ip = peer;
+        for address in forwarded_for.into_iter().rev() {
+            match address.parse() {
+                Ok(address) => {
+                    client_ip = address;
+                    if !self.contains(address) {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        client_ip
+    }
+}
+
+/// Leave the [`ClientIp`] of the request in its extensions.
+///
+/// Requests served without the address of the peer, e.g. from a `Router` used as a
+/// service directly, get none.
+pub async fn resolve_client_ip<B>(
+    trusted_proxies: TrustedProxies,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(ConnectInfo(peer)) = request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        let client_ip = trusted_proxies.client_ip(peer.ip(), request.headers());
+        request.extensions_mut().insert(ClientIp(client_ip));
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn trusted_proxies() -> TrustedProxies {
+        TrustedProxies::new(vec![
+            "10.0.0.0/8".parse().unwrap(),
+            "192.168.1.1/32".parse().unwrap(),
+        ])
+    }
+
+    fn forwarded_for(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn the_header_is_ignored_unless_the_peer_is_trusted() {
+        let client_ip = trusted_proxies().client_ip(
+            "203.0.113.7".parse().unwrap(),
+            &forwarded_for("198.51.100.1"),
+        );
+
+        assert_eq!("203.0.113.7".parse::<IpAddr>().unwrap(), client_ip);
+    }
+
+    #[test]
+    fn the_client_is_the_last_address_not_added_by_a_trusted_proxy() {
+        let client_ip = trusted_proxies().client_ip(
+            "10.1.2.3".parse().unwrap(),
+            &forwarded_for("1.1.1.1, 198.51.100.1, 192.168.1.1"),
+        );
+
+        assert_eq!("198.51.100.1".parse::<IpAddr>().unwrap(), client_ip);
+    }
+
+    #[test]
+    fn invalid_addresses_stop_the_search() {
+        let client_ip = trusted_proxies().client_ip(
+            "10.1.2.3".parse().unwrap(),
+            &forwarded_for("198.51.100.1, unknown, 10.0.0.2"),
+        );
+
+        assert_eq!("10.0.0.2".parse::<IpAddr>().unwrap(), client_ip);
+    }
+}
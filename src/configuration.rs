@@ -1,4 +1,6 @@
+use crate::{email_client::EmailClient, models::EmailAddress};
 use eyre::{Result, WrapErr};
+use ipnet::IpNet;
 use secrecy::{ExposeSecret, SecretString};
 use sqlx::{
     postgres::{PgConnectOptions, PgSslMode},
@@ -7,12 +9,186 @@ use sqlx::{
 use std::{
     convert::{TryFrom, TryInto},
     net::IpAddr,
+    path::PathBuf,
+    time::Duration,
 };
 
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
+    pub email_client: EmailClientSettings,
+    pub storage: StorageSettings,
+    pub uploads: UploadSettings,
+    pub accounts: AccountSettings,
+    pub usernames: UsernameSettings,
+    pub passwords: PasswordSettings,
+    pub newsletter: NewsletterSettings,
+    pub shutdown: ShutdownSettings,
+    pub health: HealthSettings,
+    pub metrics: MetricsSettings,
+    pub rate_limit: RateLimitSettings,
+    pub cors: CorsSettings,
+    pub security_headers: SecurityHeadersSettings,
+    pub body_limits: BodyLimitSettings,
+    /// Redis is optional, nothing is cached nor shared through it if unset.
+    pub redis: Option<RedisSettings>,
+    /// Spans are only exported to an OpenTelemetry collector if set.
+    pub otlp: Option<OtlpSettings>,
+}
+
+impl Settings {
+    /// Look for values that deserialize but can't work, e.g. an empty secret or a pool
+    /// whose minimum size is above its maximum. Returns a description of each one.
+    #[must_use]
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut expect = |ok: bool, problem: &str| {
+            if !ok {
+                problems.push(problem.to_string());
+            }
+        };
+
+        expect(
+            is_http_url(&self.application.base_url),
+            "application.base_url must be an http or https URL",
+        );
+        expect(
+            !self.database.password.expose_secret().is_empty(),
+            "database.password is empty",
+        );
+        let pool = &self.database.pool;
+        expect(
+            pool.max_connections > 0,
+            "database.pool.max_connections must be positive",
+        );
+        expect(
+            pool.min_connections <= pool.max_connections,
+            "database.pool.min_connections is greater than max_connections",
+        );
+        expect(
+            pool.retry_initial_delay_milliseconds <= pool.retry_max_delay_milliseconds,
+            "database.pool.retry_initial_delay_milliseconds is greater than retry_max_delay_milliseconds",
+        );
+        expect(
+            is_http_url(&self.email_client.base_url),
+            "email_client.base_url must be an http or https URL",
+        );
+        expect(
+            EmailAddress::parse(&self.email_client.sender_email).is_some(),
+            "email_client.sender_email is not a valid email address",
+        );
+        expect(
+            !self
+                .email_client
+                .authorization_token
+                .expose_secret()
+                .is_empty(),
+            "email_client.authorization_token is empty",
+        );
+        expect(
+            self.accounts.deletion_grace_period_days >= 0,
+            "accounts.deletion_grace_period_days can't be negative",
+        );
+        expect(
+            self.passwords.min_score <= 4,
+            "passwords.min_score must be between 0 and 4",
+        );
+        expect(
+            self.passwords
+                .breached_passwords
+                .as_ref()
+                .map_or(true, |directory| directory.is_dir()),
+            "passwords.breached_passwords is not a directory",
+        );
+        expect(
+            !self.newsletter.hmac_secret.expose_secret().is_empty(),
+            "newsletter.hmac_secret is empty",
+        );
+        expect(
+            self.newsletter.max_retries > 0,
+            "newsletter.max_retries must be positive",
+        );
+        expect(
+            self.redis.as_ref().map_or(true, |redis| {
+                redis::parse_redis_url(redis.uri.expose_secret()).is_some()
+            }),
+            "redis.uri must be a redis:// or rediss:// URL",
+        );
+        if let Some(otlp) = &self.otlp {
+            expect(
+                is_http_url(&otlp.endpoint),
+                "otlp.endpoint must be an http or https URL",
+            );
+            expect(
+                (0.0..=1.0).contains(&otlp.sampling_ratio),
+                "otlp.sampling_ratio must be between 0 and 1",
+            );
+            expect(!otlp.service_name.is_empty(), "otlp.service_name is empty");
+        }
+        expect(
+            self.metrics
+                .port
+                .map_or(true, |port| port == 0 || port != self.application.port),
+            "metrics.port must be different from application.port",
+        );
+        expect(
+            self.shutdown.drain_delay_seconds <= self.shutdown.drain_timeout_seconds,
+            "shutdown.drain_delay_seconds is greater than drain_timeout_seconds",
+        );
+        let rate_limit = &self.rate_limit;
+        expect(
+            rate_limit.backend == RateLimitBackend::Memory || self.redis.is_some(),
+            "rate_limit.backend is redis, but redis isn't configured",
+        );
+        for (name, bucket) in [
+            ("signup_per_ip", &rate_limit.signup_per_ip),
+            ("login_per_ip", &rate_limit.login_per_ip),
+            ("login_per_account", &rate_limit.login_per_account),
+        ] {
+            expect(
+                bucket.capacity > 0,
+                &format!("rate_limit.{name}.capacity must be positive"),
+            );
+        }
+        expect(
+            rate_limit.lockout.max_failures > 0,
+            "rate_limit.lockout.max_failures must be positive",
+        );
+        expect(
+            rate_limit.lockout.initial_lockout_seconds <= rate_limit.lockout.max_lockout_seconds,
+            "rate_limit.lockout.initial_lockout_seconds is greater than max_lockout_seconds",
+        );
+
+        for origin in &self.cors.allowed_origins {
+            expect(
+                is_origin(origin),
+                &format!("cors.allowed_origins has {origin}, which isn't an http or https origin"),
+            );
+        }
+        expect(
+            self.uploads.max_file_bytes <= self.body_limits.upload_bytes as u64,
+            "uploads.max_file_bytes is greater than body_limits.upload_bytes",
+        );
+        expect(
+            self.body_limits.default_bytes > 0 && self.body_limits.upload_bytes > 0,
+            "body_limits must be positive",
+        );
+
+        problems
+    }
+}
+
+fn is_http_url(url: &str) -> bool {
+    reqwest::Url::parse(url).map_or(false, |url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// Whether `origin` is what browsers send in the `Origin` header, e.g. `https://kokoa.app`
+/// without a trailing slash nor a path.
+fn is_origin(origin: &str) -> bool {
+    is_http_url(origin)
+        && reqwest::Url::parse(origin)
+            .map_or(false, |url| url.origin().ascii_serialization() == origin)
 }
 
 #[derive(serde::Deserialize, Clone, Debug)]
@@ -20,6 +196,35 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: IpAddr,
     pub base_url: String,
+    /// The reverse proxies whose `X-Forwarded-For` header is trusted to find the address
+    /// of the client, e.g. `10.0.0.0/8`. A comma separated list in environment variables.
+    #[serde(default, deserialize_with = "deserialize_list")]
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+/// Deserialize a list, or a comma separated one as environment variables can't hold lists.
+fn deserialize_list<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de> + std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum List<T> {
+        List(Vec<T>),
+        Text(String),
+    }
+
+    match <List<T> as serde::Deserialize>::deserialize(deserializer)? {
+        List::List(items) => Ok(items),
+        List::Text(text) => text
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| item.parse().map_err(serde::de::Error::custom))
+            .collect(),
+    }
 }
 
 #[derive(serde::Deserialize, Clone, Debug)]
@@ -30,6 +235,314 @@ pub struct DatabaseSettings {
     pub host: String,
     pub database_name: String,
     pub require_ssl: bool,
+    /// Run the pending migrations when the application starts. Disable it to run them
+    /// separately, e.g. as a step of the deployment.
+    pub migrate_on_startup: bool,
+    pub pool: PoolSettings,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct PoolSettings {
+    /// Connections kept at most by a process, in the pool shared by the API and the workers.
+    pub max_connections: u32,
+    /// Connections kept open even when idle.
+    pub min_connections: u32,
+    /// Time to wait for a free connection before failing the request.
+    pub acquire_timeout_milliseconds: u64,
+    /// Idle connections above `min_connections` are closed after this time.
+    pub idle_timeout_seconds: Option<u64>,
+    /// Connections are replaced after this time, even if they're in use regularly.
+    pub max_lifetime_seconds: Option<u64>,
+    /// Statements running longer than this are cancelled by Postgres.
+    pub statement_timeout_milliseconds: Option<u64>,
+    /// Attempts to connect again when the database isn't reachable at startup.
+    pub connect_retries: u32,
+    /// Pause before the first retry, doubled on each one and randomized.
+    pub retry_initial_delay_milliseconds: u64,
+    /// The longest pause between two retries.
+    pub retry_max_delay_milliseconds: u64,
+}
+
+impl PoolSettings {
+    #[must_use]
+    pub fn acquire_timeout(&self) -> Duration {
+        Duration::from_millis(self.acquire_timeout_milliseconds)
+    }
+
+    #[must_use]
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_seconds.map(Duration::from_secs)
+    }
+
+    #[must_use]
+    pub fn max_lifetime(&self) -> Option<Duration> {
+        self.max_lifetime_seconds.map(Duration::from_secs)
+    }
+
+    #[must_use]
+    pub fn retry_initial_delay(&self) -> Duration {
+        Duration::from_millis(self.retry_initial_delay_milliseconds)
+    }
+
+    #[must_use]
+    pub fn retry_max_delay(&self) -> Duration {
+        Duration::from_millis(self.retry_max_delay_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct StorageSettings {
+    /// Directory where uploaded files are kept.
+    pub root: PathBuf,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+pub struct UploadSettings {
+    /// The largest uploaded file, e.g. a profile picture, in bytes.
+    pub max_file_bytes: u64,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct AccountSettings {
+    /// Days between a deletion request and the actual deletion of the account.
+    pub deletion_grace_period_days: i32,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct UsernameSettings {
+    /// Usernames nobody can register, nor anything that looks like them.
+    pub reserved: Vec<String>,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct PasswordSettings {
+    /// The shortest password allowed.
+    pub min_length: usize,
+    /// The lowest strength allowed, from 0 (too guessable) to 4 (very unguessable).
+    pub min_score: u8,
+    /// Directory with the Pwned Passwords range files, e.g. `21BD1.txt`.
+    /// Passwords aren't checked against known breaches if unset.
+    pub breached_passwords: Option<PathBuf>,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct NewsletterSettings {
+    /// Key used to sign the unsubscribe links.
+    pub hmac_secret: SecretString,
+    /// Pause between two deliveries, to stay under the rate limits of the email API.
+    pub delivery_interval_milliseconds: u64,
+    /// Attempts to deliver an issue to an address before giving up.
+    pub max_retries: i16,
+    /// Time before another confirmation link can be sent to the same address, so the
+    /// subscription form can't be used to flood someone's inbox.
+    pub confirmation_cooldown_seconds: u64,
+}
+
+impl NewsletterSettings {
+    #[must_use]
+    pub fn confirmation_cooldown(&self) -> Duration {
+        Duration::from_secs(self.confirmation_cooldown_seconds)
+    }
+
+    #[must_use]
+    pub fn delivery_interval(&self) -> Duration {
+        Duration::from_millis(self.delivery_interval_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct ShutdownSettings {
+    /// Time the API keeps serving requests after a shutdown signal, while the health check
+    /// reports it's draining, so load balancers stop routing requests to it first.
+    pub drain_delay_seconds: u64,
+    /// Time the requests in flight and the background workers have to finish after
+    /// a shutdown signal, before they're dropped.
+    pub drain_timeout_seconds: u64,
+}
+
+impl ShutdownSettings {
+    #[must_use]
+    pub fn drain_delay(&self) -> Duration {
+        Duration::from_secs(self.drain_delay_seconds)
+    }
+
+    #[must_use]
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain_timeout_seconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct HealthSettings {
+    /// Time each dependency has to answer the readiness check before it's reported as down.
+    pub check_timeout_milliseconds: u64,
+}
+
+impl HealthSettings {
+    #[must_use]
+    pub fn check_timeout(&self) -> Duration {
+        Duration::from_millis(self.check_timeout_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct MetricsSettings {
+    /// Serve `/metrics` at all. It has no authentication, so without a `port` anyone who
+    /// can reach the API can read it.
+    pub enabled: bool,
+    /// Serve `/metrics` on this port instead of the one of the API, e.g. to keep it off
+    /// the public network. It listens on the same host as the API.
+    pub port: Option<u16>,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct RateLimitSettings {
+    /// Apply the limits and lockouts at all.
+    pub enabled: bool,
+    /// Where the buckets and failed logins are kept. `redis` shares them between replicas.
+    pub backend: RateLimitBackend,
+    /// Registrations and newsletter subscriptions from the same address.
+    pub signup_per_ip: BucketSettings,
+    /// Failed logins from the same address, whatever the account.
+    pub login_per_ip: BucketSettings,
+    /// Failed logins to the same account, whatever the address.
+    pub login_per_account: BucketSettings,
+    pub lockout: LockoutSettings,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackend {
+    Memory,
+    Redis,
+}
+
+/// A token bucket: requests take a token, and are rejected once the bucket is empty.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+pub struct BucketSettings {
+    /// The tokens of a full bucket, i.e. the requests allowed in a burst.
+    pub capacity: u32,
+    /// Time to add a token back to the bucket.
+    pub refill_interval_milliseconds: u64,
+}
+
+impl BucketSettings {
+    #[must_use]
+    pub fn refill_interval(&self) -> Duration {
+        Duration::from_millis(self.refill_interval_milliseconds)
+    }
+}
+
+/// Accounts are locked after repeated failed logins from an address, for that address
+/// only, and for longer each time.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+pub struct LockoutSettings {
+    /// Failed logins in a row from the same address that lock the account.
+    pub max_failures: u32,
+    /// Failures are forgotten after this long without another one.
+    pub failure_window_seconds: u64,
+    /// The first lockout, doubled with each failure after it.
+    pub initial_lockout_seconds: u64,
+    pub max_lockout_seconds: u64,
+}
+
+impl LockoutSettings {
+    #[must_use]
+    pub fn failure_window(&self) -> Duration {
+        Duration::from_secs(self.failure_window_seconds)
+    }
+
+    /// How long an account is locked after `failures` failed logins in a row, if at all.
+    #[must_use]
+    pub fn lockout(&self, failures: u32) -> Option<Duration> {
+        let exponent = failures.checked_sub(self.max_failures)?;
+        let seconds = 2_u64
+            .checked_pow(exponent)
+            .and_then(|factor| factor.checked_mul(self.initial_lockout_seconds))
+            .map_or(self.max_lockout_seconds, |seconds| {
+                seconds.min(self.max_lockout_seconds)
+            });
+        Some(Duration::from_secs(seconds))
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct CorsSettings {
+    /// The origins of the front-ends allowed to call the API from a browser, e.g.
+    /// `https://kokoa.app`. A comma separated list in environment variables.
+    #[serde(default, deserialize_with = "deserialize_list")]
+    pub allowed_origins: Vec<String>,
+    /// Let those origins send cookies and credentials, for cookie sessions.
+    pub allow_credentials: bool,
+    /// Time browsers may cache the answer to a preflight request.
+    pub max_age_seconds: u64,
+}
+
+impl CorsSettings {
+    #[must_use]
+    pub fn max_age(&self) -> Duration {
+        Duration::from_secs(self.max_age_seconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SecurityHeadersSettings {
+    /// Time browsers must only use HTTPS for the API, sent when `application.base_url`
+    /// is an https URL.
+    pub hsts_max_age_seconds: u64,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+pub struct BodyLimitSettings {
+    /// The largest request body, in bytes.
+    pub default_bytes: usize,
+    /// The largest request body of the routes that upload files, in bytes.
+    pub upload_bytes: usize,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct RedisSettings {
+    /// E.g. `redis://127.0.0.1:6379`. It may include a password.
+    pub uri: SecretString,
+    /// Whether the API isn't ready while Redis is down.
+    pub required: bool,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct OtlpSettings {
+    /// The traces endpoint of the collector, e.g. `http://otel-collector:4318/v1/traces`.
+    /// Spans are sent as protobuf over HTTP.
+    pub endpoint: String,
+    /// The fraction of the traces recorded, from 0 to 1. Requests that continue a trace
+    /// follow the decision of their caller instead.
+    pub sampling_ratio: f64,
+    /// The `service.name` of the exported spans.
+    pub service_name: String,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct EmailClientSettings {
+    pub base_url: String,
+    pub sender_email: String,
+    pub authorization_token: SecretString,
+    pub timeout_milliseconds: u64,
+}
+
+impl EmailClientSettings {
+    #[must_use]
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+
+    pub fn client(self) -> Result<EmailClient> {
+        let timeout = self.timeout();
+        EmailClient::new(
+            self.base_url,
+            self.sender_email,
+            self.authorization_token,
+            timeout,
+        )
+    }
 }
 
 impl DatabaseSettings {
@@ -51,6 +564,9 @@ impl DatabaseSettings {
     #[must_use]
     pub fn with_db(&self) -> PgConnectOptions {
         let mut options = self.without_db().database(&self.database_name);
+        if let Some(timeout) = self.pool.statement_timeout_milliseconds {
+            options = options.options([("statement_timeout", timeout.to_string())]);
+        }
         options.log_statements(tracing::log::LevelFilter::Trace);
         options
     }
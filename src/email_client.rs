@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use eyre::WrapErr;
+use reqwest::Client;
+use secrecy::{ExposeSecret, SecretString};
+
+use crate::metrics::METRICS;
+
+/// A client for the transactional email API (Postmark compatible).
+#[derive(Clone, Debug)]
+pub struct EmailClient {
+    http_client: Client,
+    base_url: String,
+    sender: String,
+    authorization_token: SecretString,
+}
+
+impl EmailClient {
+    /// Create a new `EmailClient` that sends emails on behalf of `sender`.
+    pub fn new(
+        base_url: String,
+        sender: String,
+        authorization_token: SecretString,
+        timeout: Duration,
+    ) -> eyre::Result<Self> {
+        let http_client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .wrap_err("failed to build the HTTP client of the email API")?;
+
+        Ok(Self {
+            http_client,
+            base_url,
+            sender,
+            authorization_token,
+        })
+    }
+
+    /// Send an email to `recipient`.
+    pub async fn send_email(
+        &self,
+        recipient: &str,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), reqwest::Error> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    /// Send an email to `recipient` with additional headers, given as `(name, value)` pairs.
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: &str,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), reqwest::Error> {
+        let url = format!("{}/email", self.base_url);
+        let request_body = SendEmailRequest {
+            from: &self.sender,
+            to: recipient,
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            headers: headers
+                .iter()
+                .map(|&(name, value)| EmailHeader { name, value })
+                .collect(),
+        };
+
+        let outcome = self
+            .http_client
+            .post(&url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        METRICS.record_email(outcome.is_ok());
+
+        outcome.map(|_| ())
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<EmailHeader<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
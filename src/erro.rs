@@ -1,16 +1,25 @@
-// TODO: delete this once constructed an Unauthorized Error
-#![allow(dead_code)]
-
+use std::any::Any;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::ops::Deref;
+use std::time::Duration;
 
 use axum::{
-    http::{header, HeaderValue, StatusCode},
+    http::{header, HeaderValue},
     response::{IntoResponse, Response},
 };
 use http_api_problem::HttpApiProblem;
-use sqlx::error::DatabaseError;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use sqlx::{error::DatabaseError, postgres::PgDatabaseError};
+use time::OffsetDateTime;
+use validator::{ValidationError, ValidationErrors};
+
+use crate::{
+    i18n::{FluentValue, Locale},
+    problems::ProblemType,
+    telemetry::panic_message,
+};
 
 /// A type to be used for listing errors during request processing.
 #[derive(Debug)]
@@ -30,13 +39,7 @@ where
         T: Into<K> + Clone,
         U: Into<V>,
     {
-        let key = key.into();
-
-        if !self.0.contains_key(&key) {
-            self.0.insert(key.clone(), Vec::new());
-        }
-
-        self.0.get_mut(&key).unwrap().push(value.into());
+        self.0.entry(key.into()).or_default().push(value.into());
         self
     }
 
@@ -73,6 +76,108 @@ impl<K, V> Deref for ErrorMap<K, V> {
     }
 }
 
+/// An error in a single field of a request.
+///
+/// The `code` is stable and meant for machines, e.g. `too_long`, while the message
+/// for humans is derived from it, in the locale of the client, when the error is serialized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    code: Cow<'static, str>,
+    params: Vec<(&'static str, u64)>,
+}
+
+impl FieldError {
+    pub fn new(code: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            code: code.into(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Add a numeric parameter used in the message, e.g. the `max` length of a field.
+    #[must_use]
+    pub fn with_param(mut self, name: &'static str, value: u64) -> Self {
+        self.params.push((name, value));
+        self
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// The message for humans describing this error in `locale`.
+    ///
+    /// Codes without a message in the catalogues get a generic one.
+    pub fn message(&self, locale: Locale) -> String {
+        let id = format!("field-{}", self.code().replace('_', "-"));
+        let id = if locale.contains(&id) {
+            id
+        } else {
+            "field-invalid".to_string()
+        };
+        let args: Vec<_> = self
+            .params
+            .iter()
+            .map(|(name, value)| (*name, FluentValue::from(*value)))
+            .collect();
+        locale.format(&id, &args)
+    }
+}
+
+impl From<&'static str> for FieldError {
+    fn from(code: &'static str) -> Self {
+        Self::new(code)
+    }
+}
+
+/// Map the errors of the `validator` crate to our own codes.
+impl From<&ValidationError> for FieldError {
+    fn from(error: &ValidationError) -> Self {
+        let param = |name| error.params.get(name).and_then(serde_json::Value::as_u64);
+        let min = param("min");
+        let max = param("max");
+
+        let code: Cow<'static, str> = match error.code.as_ref() {
+            "length" => {
+                let length = error
+                    .params
+                    .get("value")
+                    .and_then(serde_json::Value::as_str)
+                    .map(|value| value.chars().count() as u64);
+                match (min, length) {
+                    (Some(min), Some(length)) if length < min => "too_short".into(),
+                    (Some(_), _) if max.is_none() => "too_short".into(),
+                    _ => "too_long".into(),
+                }
+            }
+            "email" => "invalid_email".into(),
+            _ => error.code.clone(),
+        };
+
+        let mut field_error = Self::new(code);
+        if let Some(min) = min {
+            field_error = field_error.with_param("min", min);
+        }
+        if let Some(max) = max {
+            field_error = field_error.with_param("max", max);
+        }
+        field_error
+    }
+}
+
+/// A `FieldError` serialized with its message in a locale.
+struct LocalizedFieldError<'a>(&'a FieldError, Locale);
+
+impl Serialize for LocalizedFieldError<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Self(error, locale) = self;
+        let mut state = serializer.serialize_struct("FieldError", 2)?;
+        state.serialize_field("code", error.code())?;
+        state.serialize_field("message", &error.message(*locale))?;
+        state.end()
+    }
+}
+
 // A common error type that can be used throughout the API.
 ///
 /// Can be returned in a `Result` from an API handler function.
@@ -80,22 +185,46 @@ impl<K, V> Deref for ErrorMap<K, V> {
 /// For convenience, this represents both API errors as well as internal recoverable errors,
 /// and maps them to appropriate status codes along with at least a minimally useful error
 /// message in a JSON body.
+///
+/// The `Display` messages are meant for logs; clients get the localized ones of `ProblemType`.
 #[derive(thiserror::Error, Debug)]
 pub enum AppError {
     /// Return `401 Unauthorized`
-    #[error("Autenticación Requerida")]
+    #[error("authentication required")]
     Unauthorized,
 
-    // /// Return `403 Forbidden`
-    // #[error("user may not perform that action")]
-    // Forbidden,
+    /// Return `400 Bad Request` for a token or signed link that is unknown, expired or
+    /// already used, e.g. a confirmation link.
+    #[error("invalid or expired token")]
+    InvalidToken,
+
+    /// Return `403 Forbidden`
+    #[error("action not allowed")]
+    Forbidden,
+
+    /// Return `404 Not Found`
+    #[error("resource not found")]
+    NotFound,
+
+    /// Return `409 Conflict`
+    #[error("conflict with an existing resource")]
+    Conflict(ProblemType, ErrorMap<String, FieldError>),
+
+    /// Return `413 Payload Too Large`
+    #[error("request body too large")]
+    PayloadTooLarge,
+
+    /// Return `415 Unsupported Media Type`
+    #[error("unsupported request body format")]
+    UnsupportedMediaType,
 
-    // /// Return `404 Not Found`
-    // #[error("request path not found")]
-    // NotFound,
     /// Return `422 Unprocessable Entity`
     #[error("error in the request body")]
-    UnprocessableEntity(ErrorMap<String, String>),
+    UnprocessableEntity(ErrorMap<String, FieldError>),
+
+    /// Return `429 Too Many Requests`, with a `Retry-After` header
+    #[error("too many requests, retry after {retry_after:?}")]
+    TooManyRequests { retry_after: Duration },
 
     /// Automatically return `500 Internal Server Error` on a `sqlx::Error`.
     ///
@@ -105,10 +234,10 @@ pub enum AppError {
     /// The actual error message isn't returned to the client for security reasons.
     /// It should be logged instead.
     ///
-    /// Note that this could also contain database constraint errors, which should usually
-    /// be transformed into client errors (e.g. `422 Unprocessable Entity` or `409 Conflict`).
-    /// See `ResultExt` below for a convenient way to do this.
-    #[error("Un error ocurrió con la base de datos.")]
+    /// Violations of the constraints in `CLIENT_CONSTRAINTS` are the exception: they're
+    /// returned as client errors by `AppError::constraint_violation`, unless the query
+    /// maps them to something more specific with `ResultExt::on_constraint` below.
+    #[error("database error")]
     Sqlx(#[from] sqlx::Error),
 
     /// Return `500 Internal Server Error` on a `eyre::Report`.
@@ -124,50 +253,187 @@ pub enum AppError {
     /// Like with `Error::Sqlx`, the actual error message is not returned to the client
     /// for security reasons.
     // TODO: show how to report error to developers
-    #[error("Un error interno ocurrió en el servidor.")]
+    #[error("internal error")]
     Eyre(#[from] eyre::Report),
 }
 
 impl AppError {
-    fn status_code(&self) -> StatusCode {
+    /// Return `409 Conflict` listing the conflicting fields.
+    pub fn conflict<K, V>(
+        problem_type: ProblemType,
+        errors: impl IntoIterator<Item = (K, V)>,
+    ) -> Self
+    where
+        K: Into<String> + Clone,
+        V: Into<FieldError>,
+    {
+        let mut error_map = ErrorMap::new();
+        for (key, value) in errors {
+            error_map.add_error(key, value);
+        }
+        Self::Conflict(problem_type, error_map)
+    }
+
+    /// Return `422 Unprocessable Entity` listing the invalid fields.
+    pub fn unprocessable_entity<K, V>(errors: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String> + Clone,
+        V: Into<FieldError>,
+    {
+        let mut error_map = ErrorMap::new();
+        for (key, value) in errors {
+            error_map.add_error(key, value);
+        }
+        Self::UnprocessableEntity(error_map)
+    }
+
+    /// The documented kind of this error, which also determines its status code.
+    pub fn problem_type(&self) -> ProblemType {
         match self {
-            Self::Unauthorized => StatusCode::UNAUTHORIZED,
-            // Self::Forbidden => StatusCode::FORBIDDEN,
-            // Self::NotFound => StatusCode::NOT_FOUND,
-            Self::UnprocessableEntity { .. } => StatusCode::UNPROCESSABLE_ENTITY,
-            Self::Sqlx(_) | Self::Eyre(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Unauthorized => ProblemType::InvalidCredentials,
+            Self::InvalidToken => ProblemType::InvalidToken,
+            Self::Forbidden => ProblemType::Forbidden,
+            Self::NotFound => ProblemType::NotFound,
+            Self::Conflict(problem_type, _) => *problem_type,
+            Self::PayloadTooLarge => ProblemType::PayloadTooLarge,
+            Self::UnsupportedMediaType => ProblemType::UnsupportedMediaType,
+            Self::UnprocessableEntity { .. } => ProblemType::ValidationFailed,
+            Self::TooManyRequests { .. } => ProblemType::TooManyRequests,
+            Self::Sqlx(_) | Self::Eyre(_) => ProblemType::InternalError,
+        }
+    }
+
+    /// The client error for a violated constraint: unique violations are returned as
+    /// `409 Conflict`, and
+    /// foreign key and check violations as `422 Unprocessable Entity`, on the field named
+    /// in the constraint, e.g. `username` for `users_username_key`.
+    ///
+    /// Other errors are kept as internal errors.
+    pub fn constraint_violation(error: Box<dyn DatabaseError>) -> Self {
+        let table = error
+            .try_downcast_ref::<PgDatabaseError>()
+            .and_then(PgDatabaseError::table);
+        let field = error
+            .constraint()
+            .map(|constraint| constraint_field(table, constraint));
+
+        match (error.code().as_deref(), field) {
+            // unique_violation
+            (Some("23505"), Some(field)) => {
+                Self::conflict(ProblemType::Conflict, [(field, "already_exists")])
+            }
+            // foreign_key_violation
+            (Some("23503"), Some(field)) => Self::unprocessable_entity([(field, "not_found")]),
+            // check_violation
+            (Some("23514"), Some(field)) => Self::unprocessable_entity([(field, "invalid")]),
+            _ => Self::Sqlx(sqlx::Error::Database(error)),
         }
     }
 }
 
+/// The constraints clients can violate, e.g. two requests racing for the same address,
+/// and that aren't mapped by every query writing them. Violations of the others, e.g.
+/// the foreign keys between rows the API creates itself, are bugs and stay internal errors.
+const CLIENT_CONSTRAINTS: &[&str] = &[
+    "emails_email_key",
+    "emails_email_lower_key",
+    "users_profile_pic_id_fkey",
+    "users_locale_check",
+    "posts_title_key",
+    "posts_short_title_key",
+    "posts_slug_key",
+    "tags_title_key",
+    "tags_slug_key",
+];
+
+/// The column checked by a constraint following the naming convention of Postgres,
+/// `{table}_{column}_{suffix}`, e.g. `profile_pic_id` for `users_profile_pic_id_fkey`.
+///
+/// Indexes on `lower(column)` are named `{table}_{column}_lower_key`, and checked on the
+/// column too.
+fn constraint_field(table: Option<&str>, constraint: &str) -> String {
+    let field = table
+        .and_then(|table| constraint.strip_prefix(table))
+        .and_then(|field| field.strip_prefix('_'))
+        .unwrap_or(constraint);
+    let field = ["_key", "_fkey", "_check", "_excl"]
+        .iter()
+        .find_map(|suffix| field.strip_suffix(suffix))
+        .unwrap_or(field);
+
+    field.strip_suffix("_lower").unwrap_or(field).to_string()
+}
+
+/// An `AppError` as reported to clients, which can be rendered in any locale.
+#[derive(Debug)]
+pub struct Problem {
+    problem_type: ProblemType,
+    field_errors: ErrorMap<String, FieldError>,
+    timestamp: OffsetDateTime,
+}
+
+impl Problem {
+    /// The problem details with their messages in `locale`.
+    ///
+    /// The `type` is a path until `complete_problem_details` prepends the base URL.
+    pub fn to_http_api_problem(&self, locale: Locale) -> HttpApiProblem {
+        let problem_type = self.problem_type;
+        let mut details_7807 = HttpApiProblem::new(problem_type.status())
+            .title(problem_type.title(locale))
+            .type_url(problem_type.path())
+            .detail(problem_type.detail(locale))
+            .value("code", &problem_type.code())
+            .value("timestamp", &self.timestamp);
+
+        // add errors to response
+        for (field, errors) in self.field_errors.iter() {
+            let errors: Vec<_> = errors
+                .iter()
+                .map(|error| LocalizedFieldError(error, locale))
+                .collect();
+            details_7807.set_value(field, &errors);
+        }
+
+        details_7807
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let status = self.status_code();
+        let error = match self {
+            AppError::Sqlx(sqlx::Error::Database(error))
+                if error
+                    .constraint()
+                    .map_or(false, |name| CLIENT_CONSTRAINTS.contains(&name)) =>
+            {
+                tracing::debug!(?error, "constraint violation");
+                AppError::constraint_violation(error)
+            }
+            error => error,
+        };
 
-        // TODO: test this error structure
-        let mut details_7807 = HttpApiProblem::with_title(status)
-            // TODO: self hosted page explaining the error
-            .type_url(format!("https://httpstatuses.io/{}", status.as_u16()))
-            .detail(self.to_string())
-            .value("timestamp", &time::OffsetDateTime::now_utc());
+        let problem_type = error.problem_type();
+        let mut field_errors = ErrorMap::new();
+        let mut headers = Vec::new();
 
-        match self {
+        match error {
             AppError::Unauthorized => {
                 // Include the `WWW-Authenticate` challenge required in the specification
                 // for the `401 Unauthorized` response code:
                 // https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/401
-                let mut hyper_response = details_7807.to_hyper_response();
-                hyper_response.headers_mut().append(header::WWW_AUTHENTICATE, HeaderValue::from_static("Token"));
-                return hyper_response.into_response();
+                headers.push((
+                    header::WWW_AUTHENTICATE,
+                    HeaderValue::from_static(r#"Basic realm="chocoapi", charset="UTF-8""#),
+                ));
+            }
+            AppError::UnprocessableEntity(errors_map) | AppError::Conflict(_, errors_map) => {
+                field_errors = errors_map;
+            }
+            AppError::TooManyRequests { retry_after } => {
+                // Whole seconds, rounded up so clients don't retry too early
+                let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                headers.push((header::RETRY_AFTER, HeaderValue::from(seconds)));
             }
-            // add errors to response
-            AppError::UnprocessableEntity(errors_map) => {
-                errors_map
-                    .iter()
-                    .for_each(|(key, errors)| {
-                        details_7807.set_value(key, errors);
-                    });
-            },
             AppError::Sqlx(ref error) => {
                 tracing::error!(?error, "SQLx error");
             }
@@ -175,10 +441,54 @@ impl IntoResponse for AppError {
                 tracing::error!(?error, "generic error");
             }
             // handle normally
-            // AppError::Forbidden | AppError::NotFound => (),
+            AppError::InvalidToken
+            | AppError::Forbidden
+            | AppError::NotFound
+            | AppError::PayloadTooLarge
+            | AppError::UnsupportedMediaType => (),
+        };
+
+        let problem = Problem {
+            problem_type,
+            field_errors,
+            timestamp: OffsetDateTime::now_utc(),
         };
 
-        details_7807.to_hyper_response().into_response()
+        // `complete_problem_details` renders it again in the locale of the client
+        let mut response = problem
+            .to_http_api_problem(Locale::default())
+            .to_hyper_response()
+            .into_response();
+        for (name, value) in headers {
+            response.headers_mut().append(name, value);
+        }
+        response.extensions_mut().insert(problem);
+        response
+    }
+}
+
+/// Answer a request whose handler panicked with `500 Internal Server Error`.
+///
+/// Meant for `CatchPanicLayer`. The panic itself is logged with its backtrace by the hook
+/// installed in `init_subscriber`.
+pub fn handle_panic(panic: Box<dyn Any + Send + 'static>) -> Response {
+    AppError::Eyre(eyre::eyre!(
+        "request handler panicked: {}",
+        panic_message(panic.as_ref())
+    ))
+    .into_response()
+}
+
+/// Return `422 Unprocessable Entity` listing the fields that failed validation.
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut error_map = ErrorMap::new();
+        for (field, errors) in errors.field_errors() {
+            for error in errors {
+                error_map.add_error(field, FieldError::from(error));
+            }
+        }
+        Self::UnprocessableEntity(error_map)
     }
 }
 
@@ -193,7 +503,9 @@ impl IntoResponse for AppError {
 /// )
 ///     .fetch_one(&ctxt.db)
 ///     .await
-///     .on_constraint("user_username_key", |_| Error::unprocessable_entity([("username", "already taken")]))?;
+///     .on_constraint("user_username_key", |_| {
+///         AppError::conflict(ProblemType::UsernameTaken, [("username", "already_taken")])
+///     })?;
 /// ```
 ///
 /// Something like this would ideally live in a `sqlx-axum` crate if it made sense to author one,
@@ -232,3 +544,147 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::Value;
+
+    use super::*;
+
+    async fn render(error: AppError) -> (StatusCode, axum::http::HeaderMap, Value) {
+        render_response(error.into_response()).await
+    }
+
+    async fn render_response(response: Response) -> (StatusCode, axum::http::HeaderMap, Value) {
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, headers, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn every_error_is_an_rfc_7807_problem() {
+        let errors = [
+            (AppError::Unauthorized, StatusCode::UNAUTHORIZED),
+            (AppError::Forbidden, StatusCode::FORBIDDEN),
+            (AppError::NotFound, StatusCode::NOT_FOUND),
+            (
+                AppError::conflict(ProblemType::Conflict, [("slug", "already_exists")]),
+                StatusCode::CONFLICT,
+            ),
+            (AppError::PayloadTooLarge, StatusCode::PAYLOAD_TOO_LARGE),
+            (
+                AppError::UnsupportedMediaType,
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ),
+            (
+                AppError::unprocessable_entity([("title", "required")]),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                AppError::TooManyRequests {
+                    retry_after: Duration::from_secs(1),
+                },
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (
+                AppError::Eyre(eyre::eyre!("secret details")),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                AppError::Sqlx(sqlx::Error::RowNotFound),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+
+        for (error, expected_status) in errors {
+            let problem_type = error.problem_type();
+
+            let (status, headers, problem) = render(error).await;
+
+            assert_eq!(expected_status, status);
+            assert_eq!(problem_type.status(), status);
+            assert_eq!("application/problem+json", headers[header::CONTENT_TYPE]);
+            assert_eq!(status.as_u16(), problem["status"]);
+            assert_eq!(problem_type.title(Locale::default()), problem["title"]);
+            assert_eq!(problem_type.code(), problem["code"]);
+            assert_eq!(problem_type.path(), problem["type"]);
+            assert_eq!(problem_type.detail(Locale::default()), problem["detail"]);
+            assert!(problem["timestamp"].is_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn internal_errors_are_not_revealed() {
+        let (_, _, problem) = render(AppError::Eyre(eyre::eyre!("secret details"))).await;
+
+        assert!(!problem.to_string().contains("secret details"));
+    }
+
+    #[tokio::test]
+    async fn field_errors_are_listed_by_field() {
+        let error = AppError::unprocessable_entity([
+            (
+                "username",
+                FieldError::new("too_long").with_param("max", 31),
+            ),
+            ("username", FieldError::new("invalid_characters")),
+        ]);
+
+        let (_, _, problem) = render(error).await;
+
+        assert_eq!("too_long", problem["username"][0]["code"]);
+        assert_eq!(
+            "Debe tener como máximo 31 caracteres.",
+            problem["username"][0]["message"]
+        );
+        assert_eq!("invalid_characters", problem["username"][1]["code"]);
+    }
+
+    #[tokio::test]
+    async fn panics_are_internal_errors() {
+        let response = handle_panic(Box::new("secret details"));
+        let (status, _, problem) = render_response(response).await;
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert_eq!("server/internal-error", problem["code"]);
+        assert!(!problem.to_string().contains("secret details"));
+    }
+
+    #[tokio::test]
+    async fn unauthorized_includes_a_challenge() {
+        let (_, headers, _) = render(AppError::Unauthorized).await;
+
+        assert_eq!(
+            r#"Basic realm="chocoapi", charset="UTF-8""#,
+            headers[header::WWW_AUTHENTICATE]
+        );
+    }
+
+    #[tokio::test]
+    async fn too_many_requests_includes_when_to_retry_rounded_up() {
+        let error = AppError::TooManyRequests {
+            retry_after: Duration::from_millis(2500),
+        };
+
+        let (_, headers, _) = render(error).await;
+
+        assert_eq!("3", headers[header::RETRY_AFTER]);
+    }
+
+    #[test]
+    fn constraint_names_are_mapped_to_fields() {
+        let cases = [
+            (Some("users"), "users_username_key", "username"),
+            (Some("users"), "users_profile_pic_id_fkey", "profile_pic_id"),
+            (Some("posts"), "posts_slug_check", "slug"),
+            (Some("emails"), "emails_email_lower_key", "email"),
+            (None, "users_email_id_key", "users_email_id"),
+        ];
+
+        for (table, constraint, field) in cases {
+            assert_eq!(field, constraint_field(table, constraint));
+        }
+    }
+}
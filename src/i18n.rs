@@ -0,0 +1,262 @@
+use std::convert::Infallible;
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, RequestParts},
+    http::{header, HeaderMap},
+};
+use fluent_bundle::{concurrent::FluentBundle, FluentArgs, FluentResource};
+use fluent_langneg::{negotiate_languages, NegotiationStrategy};
+use once_cell::sync::Lazy;
+use unic_langid::{langid, LanguageIdentifier};
+
+pub use fluent_bundle::FluentValue;
+
+static ES: Lazy<FluentBundle<FluentResource>> =
+    Lazy::new(|| load_bundle(Locale::Es, include_str!("../locales/es.ftl")));
+static EN: Lazy<FluentBundle<FluentResource>> =
+    Lazy::new(|| load_bundle(Locale::En, include_str!("../locales/en.ftl")));
+
+/// The languages messages for humans are available in, from the catalogues in `locales/`.
+///
+/// Responses use the one negotiated from the `Accept-Language` header of the request,
+/// and emails the one chosen by their recipient, stored in `users.locale`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    Es,
+    En,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Self::Es, Self::En];
+
+    /// The language tag of this locale, as in `Content-Language` and `users.locale`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Es => "es",
+            Self::En => "en",
+        }
+    }
+
+    /// Find a locale by its language tag, e.g. the `locale` of a user.
+    pub fn parse(tag: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|locale| locale.as_str() == tag)
+    }
+
+    fn language_identifier(self) -> LanguageIdentifier {
+        match self {
+            Self::Es => langid!("es"),
+            Self::En => langid!("en"),
+        }
+    }
+
+    fn bundle(self) -> &'static FluentBundle<FluentResource> {
+        match self {
+            Self::Es => &ES,
+            Self::En => &EN,
+        }
+    }
+
+    /// Choose the locale preferred by a client from an `Accept-Language` header,
+    /// e.g. `en` for `en-GB,en;q=0.9,es;q=0.8`. The default one is chosen if none fits.
+    pub fn negotiate(accept_language: &str) -> Self {
+        let requested = fluent_langneg::accepted_languages::parse(accept_language);
+        let available = Self::ALL.map(Self::language_identifier);
+        let default = Self::default().language_identifier();
+
+        negotiate_languages(
+            &requested,
+            &available,
+            Some(&default),
+            NegotiationStrategy::Lookup,
+        )
+        .first()
+        .and_then(|language| Self::parse(&language.to_string()))
+        .unwrap_or_default()
+    }
+
+    /// The locale negotiated from the `Accept-Language` header of a request, if any.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .map_or_else(Self::default, Self::negotiate)
+    }
+
+    /// Whether the catalogue of this locale has the message `id`.
+    pub fn contains(self, id: &str) -> bool {
+        self.bundle().has_message(id)
+    }
+
+    /// Format the message `id` of the catalogue, or one of its attributes as `id.attribute`.
+    ///
+    /// Missing messages are logged and replaced by their `id`, so a mistake in a catalogue
+    /// never turns into an internal error.
+    pub fn format(self, id: &str, args: &[(&str, FluentValue)]) -> String {
+        let bundle = self.bundle();
+        let (message_id, attribute) = match id.split_once('.') {
+            Some((message_id, attribute)) => (message_id, Some(attribute)),
+            None => (id, None),
+        };
+
+        let pattern = bundle
+            .get_message(message_id)
+            .and_then(|message| match attribute {
+                Some(attribute) => message.get_attribute(attribute).map(|a| a.value()),
+                None => message.value(),
+            });
+        let pattern = match pattern {
+            Some(pattern) => pattern,
+            None => {
+                tracing::error!(locale = self.as_str(), id, "missing localized message");
+                return id.to_string();
+            }
+        };
+
+        let args: FluentArgs = args.iter().cloned().collect();
+        let mut errors = Vec::new();
+        let message = bundle.format_pattern(pattern, Some(&args), &mut errors);
+        if !errors.is_empty() {
+            tracing::error!(
+                locale = self.as_str(),
+                id,
+                ?errors,
+                "failed to format message"
+            );
+        }
+        message.into_owned()
+    }
+
+    /// Format the email `id` of the catalogue.
+    ///
+    /// Its value is the plain text body, and its `subject` and optional `html` attributes
+    /// are the subject and the HTML body. Without the latter, the plain text body is used
+    /// with its line breaks as `<br />`.
+    pub fn email(self, id: &str, args: &[(&str, FluentValue)]) -> LocalizedEmail {
+        let text_body = self.format(id, args);
+        let has_html = self
+            .bundle()
+            .get_message(id)
+            .and_then(|message| message.get_attribute("html"))
+            .is_some();
+        let html_body = if has_html {
+            self.format(&format!("{id}.html"), args)
+        } else {
+            text_body.replace('\n', "<br />")
+        };
+
+        LocalizedEmail {
+            subject: self.format(&format!("{id}.subject"), args),
+            html_body,
+            text_body,
+        }
+    }
+}
+
+/// Negotiate the locale of the response from the `Accept-Language` header.
+#[async_trait]
+impl<B: Send> FromRequest<B> for Locale {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_headers(req.headers()))
+    }
+}
+
+/// An email ready to be sent, in the locale of its recipient.
+#[derive(Debug, Clone)]
+pub struct LocalizedEmail {
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+fn load_bundle(locale: Locale, source: &'static str) -> FluentBundle<FluentResource> {
+    let resource = FluentResource::try_new(source.to_string()).unwrap_or_else(|(_, errors)| {
+        panic!("invalid catalogue for {}: {errors:?}", locale.as_str())
+    });
+
+    let mut bundle = FluentBundle::new_concurrent(vec![locale.language_identifier()]);
+    // The Unicode isolation marks around arguments would end up in headers and emails
+    bundle.set_use_isolating(false);
+    bundle
+        .add_resource(resource)
+        .unwrap_or_else(|errors| panic!("invalid catalogue for {}: {errors:?}", locale.as_str()));
+    bundle
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::problems::ProblemType;
+
+    /// The ids of the messages of a catalogue and their attributes, as `id.attribute`.
+    fn message_ids(source: &str) -> BTreeSet<String> {
+        let mut ids = BTreeSet::new();
+        let mut current = String::new();
+        for line in source.lines() {
+            if line.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                current = line.split('=').next().unwrap().trim().to_string();
+                ids.insert(current.clone());
+            } else if let Some(attribute) = line.trim_start().strip_prefix('.') {
+                let attribute = attribute.split('=').next().unwrap().trim();
+                ids.insert(format!("{current}.{attribute}"));
+            }
+        }
+        ids
+    }
+
+    #[test]
+    fn every_locale_has_the_same_messages() {
+        let es = message_ids(include_str!("../locales/es.ftl"));
+        let en = message_ids(include_str!("../locales/en.ftl"));
+
+        assert_eq!(es, en);
+    }
+
+    #[test]
+    fn every_problem_type_is_localized() {
+        for locale in Locale::ALL {
+            for problem_type in ProblemType::ALL {
+                let id = format!("problem-{}", problem_type.code().replace('/', "-"));
+                assert!(locale.contains(&id), "{id} missing in {locale:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn the_preferred_available_locale_is_negotiated() {
+        let cases = [
+            ("en", Locale::En),
+            ("en-US,en;q=0.9", Locale::En),
+            ("fr-FR,fr;q=0.9,en;q=0.8", Locale::En),
+            ("es-EC,es;q=0.9,en;q=0.8", Locale::Es),
+            ("fr", Locale::Es),
+            ("", Locale::Es),
+        ];
+
+        for (accept_language, locale) in cases {
+            assert_eq!(
+                locale,
+                Locale::negotiate(accept_language),
+                "{accept_language}"
+            );
+        }
+    }
+
+    #[test]
+    fn arguments_are_not_isolated() {
+        let email = Locale::En.email(
+            "email-data-export-ready",
+            &[("username", "jo".into()), ("link", "https://x".into())],
+        );
+
+        assert_eq!("Your data is ready", email.subject);
+        assert!(email.text_body.starts_with("Hi jo,\nYour data"));
+        assert!(email.html_body.starts_with("Hi jo,<br />Your data"));
+    }
+}
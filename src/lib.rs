@@ -1,11 +1,25 @@
 #![allow(clippy::missing_errors_doc)]
 
 pub mod authentication;
+pub mod body_limit;
+pub mod cli;
+pub mod client_ip;
 pub mod configuration;
+pub mod email_client;
 pub(crate) mod erro;
+pub(crate) mod i18n;
+pub mod metrics;
 pub mod models;
+pub mod newsletter;
+pub(crate) mod problems;
+pub mod rate_limit;
+pub mod redis_connection;
 pub mod repositories;
 pub(crate) mod routes;
+pub mod security;
 pub mod startup;
+pub mod storage;
 pub mod telemetry;
 pub mod utils;
+pub(crate) mod validation;
+pub mod workers;
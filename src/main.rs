@@ -1,18 +1,33 @@
+use std::fmt::{Debug, Display};
+
 use chocoapi::{
-    configuration::{self, Environment},
-    startup::Application,
-    telemetry::{get_subscriber, init_subscriber},
+    cli::{self, Cli, Command},
+    configuration::{self, Environment, Settings},
+    startup::{get_connection_pool, shutdown_signal, Application},
+    telemetry::{get_subscriber, init_otlp_tracer, init_subscriber, otlp_layer},
+    workers::{account_deletion, data_export, newsletter_delivery},
 };
+use clap::Parser;
 use eyre::{Result, WrapErr};
-use tracing_subscriber::EnvFilter;
+use tokio::task::JoinError;
+use tracing_subscriber::{layer::SubscriberExt, EnvFilter};
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
     // This returns an error if the `.env` file doesn't exist, but that's not what we want
     // since we're not going to use a `.env` file if we deploy this application.
     dotenv::dotenv().ok();
 
     let environment = configuration::get_environment()?;
+    let configuration =
+        configuration::extract(environment).wrap_err("failed to read configuration")?;
+    let tracer = configuration
+        .otlp
+        .as_ref()
+        .map(init_otlp_tracer)
+        .transpose()?;
 
     // change log format on local environment to improve human readibility
     match environment {
@@ -22,7 +37,8 @@ async fn main() -> Result<()> {
                 .from_env_lossy();
             let subscriber = tracing_subscriber::fmt()
                 .with_env_filter(env_filter)
-                .finish();
+                .finish()
+                .with(tracer.map(otlp_layer));
             init_subscriber(subscriber)?;
         }
         Environment::Production => {
@@ -30,17 +46,125 @@ async fn main() -> Result<()> {
                 "chocoapi".to_string(),
                 tracing::Level::INFO,
                 std::io::stdout,
+                tracer,
             );
             init_subscriber(subscriber)?;
         }
     };
 
-    let configuration =
-        configuration::extract(environment).wrap_err("failed to read configuration")?;
+    let outcome = match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(configuration).await,
+        command => cli::run(command, environment, configuration).await,
+    };
 
-    let application = Application::build(configuration).await?;
+    // Export the spans that are still waiting for the next batch
+    opentelemetry::global::shutdown_tracer_provider();
+    outcome
+}
+
+/// Serve the API and run the background workers until a shutdown signal, or until any
+/// of them stops. The others are then given the drain timeout to finish.
+///
+/// They all share a single connection pool, so `database.pool.max_connections` is the
+/// limit of the whole process.
+async fn serve(configuration: Settings) -> Result<()> {
+    let drain_timeout = configuration.shutdown.drain_timeout();
+    let connection_pool = get_connection_pool(&configuration.database).await?;
+    let application = Application::build(configuration.clone(), connection_pool.clone()).await?;
+    let shutdown = application.shutdown_token();
+
+    let mut application_task = tokio::spawn(application.run_until_stopped());
+    let mut account_deletion_task = tokio::spawn(account_deletion::run_worker_until_stopped(
+        configuration.clone(),
+        connection_pool.clone(),
+        shutdown.clone(),
+    ));
+    let mut data_export_task = tokio::spawn(data_export::run_worker_until_stopped(
+        configuration.clone(),
+        connection_pool.clone(),
+        shutdown.clone(),
+    ));
+    let mut newsletter_delivery_task = tokio::spawn(newsletter_delivery::run_worker_until_stopped(
+        configuration,
+        connection_pool.clone(),
+        shutdown.clone(),
+    ));
+
+    let stopped = tokio::select! {
+        outcome = shutdown_signal() => {
+            match outcome {
+                Ok(()) => tracing::info!("shutdown signal received"),
+                Err(e) => tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "failed to wait for a shutdown signal"
+                ),
+            }
+            None
+        }
+        outcome = &mut application_task => {
+            report_exit("API", outcome);
+            Some("API")
+        }
+        outcome = &mut account_deletion_task => {
+            report_exit("Account deletion worker", outcome);
+            Some("Account deletion worker")
+        }
+        outcome = &mut data_export_task => {
+            report_exit("Data export worker", outcome);
+            Some("Data export worker")
+        }
+        outcome = &mut newsletter_delivery_task => {
+            report_exit("Newsletter delivery worker", outcome);
+            Some("Newsletter delivery worker")
+        }
+    };
 
-    application.run_until_stopped().await?;
+    shutdown.cancel();
+    let remaining_tasks = [
+        ("API", application_task),
+        ("Account deletion worker", account_deletion_task),
+        ("Data export worker", data_export_task),
+        ("Newsletter delivery worker", newsletter_delivery_task),
+    ]
+    .into_iter()
+    .filter(|(task_name, _)| Some(*task_name) != stopped);
+    let drain = async {
+        for (task_name, task) in remaining_tasks {
+            report_exit(task_name, task.await);
+        }
+        connection_pool.close().await;
+    };
+    if tokio::time::timeout(drain_timeout, drain).await.is_err() {
+        tracing::warn!(
+            ?drain_timeout,
+            "drain timeout elapsed, dropping the remaining tasks"
+        );
+    }
 
     Ok(())
 }
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name);
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            );
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            );
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::time::Instant;
+
+use axum::{body::Body, extract::MatchedPath, http::Request, middleware::Next, response::Response};
+use once_cell::sync::Lazy;
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use sqlx::PgPool;
+
+/// The metrics of the application, exposed in the Prometheus text format at `/metrics`.
+///
+/// They're global so they can be recorded anywhere, e.g. in the email client, without
+/// passing them around.
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    /// Requests answered, by method, matched route and status.
+    pub http_requests: IntCounterVec,
+    /// Time to answer requests, by method, matched route and status.
+    pub http_request_duration: HistogramVec,
+    /// Connections of the pool of the API, by state: `idle` or `in_use`.
+    pub db_pool_connections: IntGaugeVec,
+    /// The most connections the pool of the API opens, set when the API starts.
+    pub db_pool_max_connections: IntGauge,
+    /// Time waited for a connection when beginning a transaction.
+    pub db_pool_acquire_duration: Histogram,
+    /// Size of the uploaded images.
+    pub image_upload_bytes: Histogram,
+    /// Time to decode and store an uploaded image.
+    pub image_processing_duration: Histogram,
+    /// Users registered.
+    pub registrations: IntCounter,
+    /// Requests authenticated with credentials, by outcome: `success` or `failure`. The
+    /// API has no sessions, so it counts every authenticated request, not logins.
+    pub auth_attempts: IntCounterVec,
+    /// Emails sent through the email API, by outcome: `sent` or `failed`.
+    pub emails: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("chocoapi".to_string()), None)
+            .expect("invalid metrics prefix");
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Requests answered"),
+            &["method", "route", "status"],
+        )
+        .expect("invalid metric");
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time to answer requests"),
+            &["method", "route", "status"],
+        )
+        .expect("invalid metric");
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Connections of the database pool"),
+            &["state"],
+        )
+        .expect("invalid metric");
+        let db_pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "The most connections the database pool opens",
+        )
+        .expect("invalid metric");
+        let db_pool_acquire_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "db_pool_acquire_duration_seconds",
+                "Time waited for a connection when beginning a transaction",
+            )
+            .buckets(exponential_buckets(0.0005, 4.0, 8).expect("invalid buckets")),
+        )
+        .expect("invalid metric");
+        let image_upload_bytes = Histogram::with_opts(
+            HistogramOpts::new("image_upload_bytes", "Size of the uploaded images")
+                .buckets(exponential_buckets(16_384.0, 4.0, 8).expect("invalid buckets")),
+        )
+        .expect("invalid metric");
+        let image_processing_duration = Histogram::with_opts(HistogramOpts::new(
+            "image_processing_duration_seconds",
+            "Time to decode and store an uploaded image",
+        ))
+        .expect("invalid metric");
+        let registrations =
+            IntCounter::new("registrations_total", "Users registered").expect("invalid metric");
+        let auth_attempts = IntCounterVec::new(
+            Opts::new(
+                "auth_attempts_total",
+                "Requests authenticated with credentials",
+            ),
+            &["outcome"],
+        )
+        .expect("invalid metric");
+        let emails = IntCounterVec::new(
+            Opts::new("emails_total", "Emails sent through the email API"),
+            &["outcome"],
+        )
+        .expect("invalid metric");
+
+        let collectors: [Box<dyn prometheus::core::Collector>; 10] = [
+            Box::new(http_requests.clone()),
+            Box::new(http_request_duration.clone()),
+            Box::new(db_pool_connections.clone()),
+            Box::new(db_pool_max_connections.clone()),
+            Box::new(db_pool_acquire_duration.clone()),
+            Box::new(image_upload_bytes.clone()),
+            Box::new(image_processing_duration.clone()),
+            Box::new(registrations.clone()),
+            Box::new(auth_attempts.clone()),
+            Box::new(emails.clone()),
+        ];
+        for collector in collectors {
+            registry
+                .register(collector)
+                .expect("metric registered twice");
+        }
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_pool_connections,
+            db_pool_max_connections,
+            db_pool_acquire_duration,
+            image_upload_bytes,
+            image_processing_duration,
+            registrations,
+            auth_attempts,
+            emails,
+        }
+    }
+
+    /// Render the metrics in the Prometheus text format, with the current stats of `db_pool`.
+    pub fn render(&self, db_pool: &PgPool) -> prometheus::Result<String> {
+        let idle = i64::try_from(db_pool.num_idle()).unwrap_or(i64::MAX);
+        let size = i64::from(db_pool.size());
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        self.db_pool_connections
+            .with_label_values(&["in_use"])
+            .set(size - idle);
+
+        let mut body = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut body)?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Record the outcome of checking the credentials of a request.
+    pub fn record_auth_attempt(&self, success: bool) {
+        let outcome = if success { "success" } else { "failure" };
+        self.auth_attempts.with_label_values(&[outcome]).inc();
+    }
+
+    /// Record the outcome of sending an email.
+    pub fn record_email(&self, sent: bool) {
+        let outcome = if sent { "sent" } else { "failed" };
+        self.emails.with_label_values(&[outcome]).inc();
+    }
+}
+
+/// Record the count and duration of the requests to the route the request matched.
+///
+/// It must be added with `Router::route_layer`, so the route is already matched. Requests
+/// that match no route aren't recorded, so random paths can't create new series.
+pub async fn track_requests(request: Request<Body>, next: Next<Body>) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    METRICS.http_requests.with_label_values(&labels).inc();
+    METRICS
+        .http_request_duration
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+
+    response
+}
@@ -0,0 +1,48 @@
+use std::fmt;
+
+use validator::validate_email;
+
+/// Longest address that fits in the `emails` table.
+const MAX_LENGTH: usize = 127;
+
+/// A syntactically valid and normalized email address.
+///
+/// The domain is converted to its lowercase ASCII form (IDNA), so `Jose@Bücher.example`
+/// becomes `Jose@xn--bcher-kva.example`. The local part is kept as is, although addresses
+/// are compared case-insensitively by the database.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmailAddress(String);
+
+impl EmailAddress {
+    /// Parse and normalize an email address, returning `None` if it's malformed.
+    #[must_use]
+    pub fn parse(email: &str) -> Option<Self> {
+        let (local_part, domain) = email.trim().rsplit_once('@')?;
+        let domain = idna::domain_to_ascii(domain).ok()?;
+        let email = format!("{local_part}@{domain}");
+
+        if email.len() <= MAX_LENGTH && validate_email(&email) {
+            Some(Self(email))
+        } else {
+            None
+        }
+    }
+}
+
+impl AsRef<str> for EmailAddress {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for EmailAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<EmailAddress> for String {
+    fn from(email: EmailAddress) -> Self {
+        email.0
+    }
+}
@@ -1,3 +1,7 @@
+mod emails;
+mod usernames;
 mod users;
 
+pub use emails::*;
+pub use usernames::*;
 pub use users::*;
@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+
+use validator::ValidationError;
+
+/// The shortest username allowed.
+pub const USERNAME_MIN_LENGTH: u64 = 3;
+
+/// The longest username allowed, the size of the `users.username` column.
+pub const USERNAME_MAX_LENGTH: u64 = 31;
+
+/// The accounts anonymized by the account deletion worker are renamed to this prefix
+/// followed by the first `DELETED_ACCOUNT_ID_LENGTH` hex digits of their id.
+const DELETED_ACCOUNT_PREFIX: &str = "deleted-";
+const DELETED_ACCOUNT_ID_LENGTH: usize = 23;
+
+/// Check the rules every username must follow, regardless of the configuration.
+///
+/// Usernames are between `USERNAME_MIN_LENGTH` and `USERNAME_MAX_LENGTH` characters long,
+/// made of ASCII letters, digits, `.`, `_` and `-`, and start and end with a letter or a digit.
+pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+    let length = username.chars().count() as u64;
+    if length < USERNAME_MIN_LENGTH {
+        let mut error = ValidationError::new("too_short");
+        error.add_param("min".into(), &USERNAME_MIN_LENGTH);
+        return Err(error);
+    }
+    if length > USERNAME_MAX_LENGTH {
+        let mut error = ValidationError::new("too_long");
+        error.add_param("max".into(), &USERNAME_MAX_LENGTH);
+        return Err(error);
+    }
+
+    let is_separator = |c: char| matches!(c, '.' | '_' | '-');
+    let valid_characters = username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || is_separator(c));
+    let valid_ends = username.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && username.ends_with(|c: char| c.is_ascii_alphanumeric());
+    if !valid_characters || !valid_ends {
+        return Err(ValidationError::new("invalid_characters"));
+    }
+
+    Ok(())
+}
+
+/// The form of a username used to compare it with others.
+///
+/// Case and separators are ignored, and characters that look alike are replaced by
+/// a single one, so `John.Doe`, `johndoe` and `j0hnd0e` all have the same skeleton.
+///
+/// It must match the `username_skeleton` function of the database, which enforces
+/// the uniqueness of skeletons.
+pub fn username_skeleton(username: &str) -> String {
+    username
+        .chars()
+        .filter(|c| !matches!(c, '.' | '_' | '-'))
+        .map(|c| match c.to_ascii_lowercase() {
+            '0' => 'o',
+            '1' | 'i' => 'l',
+            c => c,
+        })
+        .collect::<String>()
+        .replace("rn", "m")
+        .replace("vv", "w")
+}
+
+/// The rules usernames must follow, including the names reserved in the configuration.
+#[derive(Clone, Debug)]
+pub struct UsernamePolicy {
+    /// Skeletons of the reserved usernames.
+    reserved: HashSet<String>,
+}
+
+impl UsernamePolicy {
+    pub fn new<T: AsRef<str>>(reserved: impl IntoIterator<Item = T>) -> Self {
+        Self {
+            reserved: reserved
+                .into_iter()
+                .map(|username| username_skeleton(username.as_ref()))
+                .collect(),
+        }
+    }
+
+    /// Check that `username`, already checked by [`validate_username`], isn't reserved.
+    ///
+    /// Names that look like a reserved one, e.g. `Adm1n` for `admin`, are reserved too,
+    /// and so are the ones that look like the name of a deleted account.
+    /// Whether it's already taken is left to the database.
+    pub fn check(&self, username: &str) -> Result<(), ValidationError> {
+        let skeleton = username_skeleton(username);
+        if self.reserved.contains(&skeleton) || is_deleted_account(&skeleton) {
+            return Err(ValidationError::new("reserved"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `skeleton` is the one of a name given to a deleted account.
+fn is_deleted_account(skeleton: &str) -> bool {
+    let hex_digits = username_skeleton("0123456789abcdef");
+    skeleton
+        .strip_prefix(&username_skeleton(DELETED_ACCOUNT_PREFIX))
+        .map_or(false, |id| {
+            id.chars().count() == DELETED_ACCOUNT_ID_LENGTH
+                && id.chars().all(|c| hex_digits.contains(c))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_like_the_ones_of_deleted_accounts_are_reserved() {
+        let policy = UsernamePolicy::new::<&str>([]);
+
+        for username in [
+            "deleted-0123456789abcdef0123456",
+            "Deleted.0I23456789ABCDEF0123456",
+        ] {
+            assert!(
+                policy.check(username).is_err(),
+                "{username} wasn't reserved"
+            );
+        }
+    }
+
+    #[test]
+    fn other_names_starting_with_deleted_are_allowed() {
+        let policy = UsernamePolicy::new::<&str>([]);
+
+        for username in ["deleted-scenes", "deletedfan", "deleted-0123"] {
+            assert!(policy.check(username).is_ok(), "{username} was reserved");
+        }
+    }
+}
@@ -2,7 +2,11 @@ use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 use uuid::Uuid;
 
-use crate::erro::ErrorMap;
+use super::EmailAddress;
+use crate::{
+    erro::{ErrorMap, FieldError},
+    i18n::Locale,
+};
 
 /// A domain user.
 #[derive(Serialize, Deserialize)]
@@ -16,6 +20,21 @@ pub struct User {
     pub active: bool,
     pub created_at: OffsetDateTime,
     pub updated_at: OffsetDateTime,
+    pub deletion_scheduled_at: Option<OffsetDateTime>,
+    /// Failed attempts at deleting the user, which postpone `deletion_scheduled_at`.
+    pub deletion_retries: i16,
+    /// When the deletion was given up on, after too many failed attempts.
+    pub deletion_failed_at: Option<OffsetDateTime>,
+    /// The language tag of the `Locale` of the emails sent to this user.
+    pub locale: String,
+}
+
+impl User {
+    /// The locale of the emails sent to this user.
+    #[must_use]
+    pub fn locale(&self) -> Locale {
+        Locale::parse(&self.locale).unwrap_or_default()
+    }
 }
 
 /// Represents a user to be inserted in the database.
@@ -23,8 +42,9 @@ pub struct InsertableUser {
     username: String,
     full_name: Option<String>,
     profile_pic_id: Option<Uuid>,
-    email_id: Uuid,
+    email: EmailAddress,
     passwd_hash: String,
+    locale: Locale,
 }
 
 impl InsertableUser {
@@ -44,14 +64,19 @@ impl InsertableUser {
     }
 
     #[must_use]
-    pub fn email_id(&self) -> Uuid {
-        self.email_id
+    pub fn email(&self) -> EmailAddress {
+        self.email.clone()
     }
 
     #[must_use]
     pub fn passwd_hash(&self) -> String {
         self.passwd_hash.clone()
     }
+
+    #[must_use]
+    pub fn locale(&self) -> Locale {
+        self.locale
+    }
 }
 
 /// Build a new `InsertableUser`.
@@ -59,8 +84,9 @@ pub struct InsertableUserBuilder {
     username: String,
     full_name: Option<String>,
     profile_pic_id: Option<Uuid>,
-    email_id: Option<Uuid>,
+    email: Option<EmailAddress>,
     passwd_hash: String,
+    locale: Locale,
 }
 
 impl InsertableUserBuilder {
@@ -70,8 +96,9 @@ impl InsertableUserBuilder {
             username: String::default(),
             full_name: None,
             profile_pic_id: None,
-            email_id: None,
+            email: None,
             passwd_hash: String::default(),
+            locale: Locale::default(),
         }
     }
 
@@ -88,15 +115,20 @@ impl InsertableUserBuilder {
     }
 
     #[must_use]
-    pub fn with_password(mut self, passwd: String) -> Self {
-        // TODO: hash password
-        self.passwd_hash = passwd;
+    pub fn with_password_hash(mut self, passwd_hash: String) -> Self {
+        self.passwd_hash = passwd_hash;
+        self
+    }
+
+    #[must_use]
+    pub fn with_email(mut self, email: EmailAddress) -> Self {
+        self.email = Some(email);
         self
     }
 
     #[must_use]
-    pub fn with_email_id(mut self, email_id: Uuid) -> Self {
-        self.email_id = Some(email_id);
+    pub fn with_locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
         self
     }
 
@@ -107,33 +139,31 @@ impl InsertableUserBuilder {
     }
 
     /// Build a new `InsertableUser` from this `InsertableUserBuilder`.
-    /// # Panics
-    /// - never
-    pub fn build(self) -> Result<InsertableUser, ErrorMap<&'static str, &'static str>> {
+    pub fn build(self) -> Result<InsertableUser, ErrorMap<&'static str, FieldError>> {
         let mut errors = ErrorMap::new();
 
         if self.username.is_empty() {
-            errors.add_error("username", "Missing field");
+            errors.add_error("username", "required");
         }
 
         if self.passwd_hash.is_empty() {
-            errors.add_error("password", "Missing field");
+            errors.add_error("password", "required");
         }
 
-        if self.email_id.is_none() {
-            errors.add_error("email", "Missing field");
+        if self.email.is_none() {
+            errors.add_error("email", "required");
         }
 
-        if errors.len() == 0 {
-            Ok(InsertableUser {
+        match self.email {
+            Some(email) if errors.is_empty() => Ok(InsertableUser {
                 username: self.username,
                 full_name: self.full_name,
                 profile_pic_id: self.profile_pic_id,
-                email_id: self.email_id.unwrap(),
+                email,
                 passwd_hash: self.passwd_hash,
-            })
-        } else {
-            Err(errors)
+                locale: self.locale,
+            }),
+            _ => Err(errors),
         }
     }
 }
@@ -0,0 +1,53 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Builds and verifies the signed links used to unsubscribe from the newsletter.
+///
+/// The signature lets recipients unsubscribe with a single click, without logging in,
+/// while preventing anyone else from unsubscribing arbitrary addresses.
+#[derive(Clone, Debug)]
+pub struct UnsubscribeLinks {
+    base_url: String,
+    hmac_secret: SecretString,
+}
+
+impl UnsubscribeLinks {
+    #[must_use]
+    pub fn new(base_url: String, hmac_secret: SecretString) -> Self {
+        Self {
+            base_url,
+            hmac_secret,
+        }
+    }
+
+    /// The unsubscribe link of an email address.
+    #[must_use]
+    pub fn link(&self, email_id: Uuid) -> String {
+        let signature = self.mac(email_id).finalize().into_bytes();
+        let signature = base32::encode(base32::Alphabet::Crockford, &signature).to_lowercase();
+        format!(
+            "{}/newsletter/unsubscribe?email_id={email_id}&signature={signature}",
+            self.base_url
+        )
+    }
+
+    /// Check that `signature` was produced for `email_id`, in constant time.
+    #[must_use]
+    pub fn verify(&self, email_id: Uuid, signature: &str) -> bool {
+        base32::decode(base32::Alphabet::Crockford, signature).map_or(false, |signature| {
+            self.mac(email_id).verify_slice(&signature).is_ok()
+        })
+    }
+
+    fn mac(&self, email_id: Uuid) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(b"unsubscribe:");
+        mac.update(email_id.as_bytes());
+        mac
+    }
+}
@@ -0,0 +1,149 @@
+use axum::{
+    body::{boxed, Full},
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use tower_http::request_id::RequestId;
+
+use crate::{erro::Problem, i18n::Locale};
+
+/// Where the documentation of each problem type is served, followed by its code.
+pub const PROBLEMS_PATH: &str = "/problems";
+
+/// The kinds of problems reported by the API in RFC 7807 responses.
+///
+/// Their codes are stable, so clients can rely on them instead of the `detail` message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProblemType {
+    InvalidCredentials,
+    InvalidToken,
+    Forbidden,
+    NotFound,
+    Conflict,
+    UsernameTaken,
+    EmailTaken,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    ValidationFailed,
+    TooManyRequests,
+    InternalError,
+}
+
+impl ProblemType {
+    pub const ALL: [ProblemType; 12] = [
+        Self::InvalidCredentials,
+        Self::InvalidToken,
+        Self::Forbidden,
+        Self::NotFound,
+        Self::Conflict,
+        Self::UsernameTaken,
+        Self::EmailTaken,
+        Self::PayloadTooLarge,
+        Self::UnsupportedMediaType,
+        Self::ValidationFailed,
+        Self::TooManyRequests,
+        Self::InternalError,
+    ];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::InvalidCredentials => "auth/invalid-credentials",
+            Self::InvalidToken => "auth/invalid-token",
+            Self::Forbidden => "auth/forbidden",
+            Self::NotFound => "resource/not-found",
+            Self::Conflict => "resource/conflict",
+            Self::UsernameTaken => "user/username-taken",
+            Self::EmailTaken => "user/email-taken",
+            Self::PayloadTooLarge => "request/payload-too-large",
+            Self::UnsupportedMediaType => "request/unsupported-media-type",
+            Self::ValidationFailed => "request/validation-failed",
+            Self::TooManyRequests => "request/too-many-requests",
+            Self::InternalError => "server/internal-error",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|problem_type| problem_type.code() == code)
+    }
+
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            Self::InvalidToken => StatusCode::BAD_REQUEST,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Conflict | Self::UsernameTaken | Self::EmailTaken => StatusCode::CONFLICT,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::ValidationFailed => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            Self::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The id of the message of this problem type in the catalogues, e.g.
+    /// `problem-auth-forbidden` for `auth/forbidden`.
+    fn message_id(self) -> String {
+        format!("problem-{}", self.code().replace('/', "-"))
+    }
+
+    pub fn title(self, locale: Locale) -> String {
+        locale.format(&self.message_id(), &[])
+    }
+
+    /// A short explanation of an occurrence of the problem.
+    pub fn detail(self, locale: Locale) -> String {
+        locale.format(&format!("{}.detail", self.message_id()), &[])
+    }
+
+    /// What the problem means and how a client can solve it.
+    pub fn description(self, locale: Locale) -> String {
+        locale.format(&format!("{}.description", self.message_id()), &[])
+    }
+
+    /// The path of the documentation of this problem type.
+    pub fn path(self) -> String {
+        format!("{PROBLEMS_PATH}/{}", self.code())
+    }
+}
+
+/// Complete the problem details of error responses with what `AppError` doesn't know:
+/// the language of the client, negotiated from its `Accept-Language` header, the absolute
+/// URI of their `type`, under `base_url`, and the request ID as `instance`.
+///
+/// `AppError` leaves its `Problem` in the extensions of the response for this.
+pub async fn complete_problem_details<B>(
+    base_url: String,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let locale = Locale::from_headers(request.headers());
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|request_id| request_id.header_value().to_str().ok())
+        .map(ToOwned::to_owned);
+
+    let mut response = next.run(request).await;
+
+    let problem = match response.extensions_mut().remove::<Problem>() {
+        Some(problem) => problem,
+        None => return response,
+    };
+    let mut problem = problem.to_http_api_problem(locale);
+    problem.type_url = problem.type_url.map(|path| format!("{base_url}{path}"));
+    problem.instance = request_id;
+
+    let body = problem.json_bytes();
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+    headers.insert(
+        header::CONTENT_LANGUAGE,
+        HeaderValue::from_static(locale.as_str()),
+    );
+    *response.body_mut() = boxed(Full::from(body));
+    response
+}
@@ -0,0 +1,478 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use eyre::{Result, WrapErr};
+use redis::aio::ConnectionManager;
+
+use crate::{
+    client_ip::ClientIp,
+    configuration::{BucketSettings, LockoutSettings, RateLimitBackend, RateLimitSettings},
+    erro::AppError,
+    redis_connection::RedisConnection,
+};
+
+/// The most entries of each kind the memory backend keeps, so it can't grow forever.
+const MAX_MEMORY_ENTRIES: usize = 10_000;
+
+/// The token buckets requests are limited by.
+#[derive(Debug, Clone, Copy)]
+pub enum Bucket {
+    SignupPerIp,
+    LoginPerIp,
+    LoginPerAccount,
+}
+
+impl Bucket {
+    fn name(self) -> &'static str {
+        match self {
+            Self::SignupPerIp => "signup-ip",
+            Self::LoginPerIp => "login-ip",
+            Self::LoginPerAccount => "login-account",
+        }
+    }
+}
+
+/// Limits the rate of requests with token buckets, and locks accounts after repeated
+/// failed logins.
+///
+/// The buckets are kept in memory, or in Redis so they're shared between replicas. If Redis
+/// fails, requests are allowed: an outage shouldn't lock everyone out.
+#[derive(Clone)]
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    backend: Backend,
+}
+
+#[derive(Clone)]
+enum Backend {
+    Memory(Arc<MemoryStore>),
+    Redis(ConnectionManager),
+}
+
+impl RateLimiter {
+    /// Create a limiter with the backend chosen in `settings`. The Redis one connects
+    /// to `redis` right away, and fails if it can't.
+    pub async fn new(settings: RateLimitSettings, redis: Option<&RedisConnection>) -> Result<Self> {
+        let backend = match (settings.backend, redis) {
+            (RateLimitBackend::Memory, _) => Backend::Memory(Arc::default()),
+            (RateLimitBackend::Redis, Some(redis)) => Backend::Redis(redis.get().await?),
+            (RateLimitBackend::Redis, None) => {
+                eyre::bail!("the Redis rate limit backend needs Redis to be configured")
+            }
+        };
+        Ok(Self { settings, backend })
+    }
+
+    /// Take a token from the bucket of `key`, e.g. the address of the client.
+    ///
+    /// Returns `429 Too Many Requests` once the bucket is empty.
+    pub async fn check(&self, bucket: Bucket, key: &str) -> Result<(), AppError> {
+        self.take(bucket, key, true).await
+    }
+
+    /// Return `429 Too Many Requests` if `account` can't log in from `client_ip` right now:
+    /// while it's locked after failed logins from that address, or once the buckets of
+    /// failed logins from the address or to the account are empty.
+    ///
+    /// Only failures are counted, see [`Self::record_failed_login`], so clients that know
+    /// the password aren't limited by these.
+    pub async fn check_login(
+        &self,
+        account: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(), AppError> {
+        self.check_lockout(account, client_ip).await?;
+        if let Some(client_ip) = client_ip {
+            self.take(Bucket::LoginPerIp, &client_ip.to_string(), false)
+                .await?;
+        }
+        self.take(Bucket::LoginPerAccount, account, false).await
+    }
+
+    /// Take a token from the bucket of `key`, or just check that it isn't empty if
+    /// `consume` is false.
+    async fn take(&self, bucket: Bucket, key: &str, consume: bool) -> Result<(), AppError> {
+        if !self.settings.enabled {
+            return Ok(());
+        }
+
+        let limit = match bucket {
+            Bucket::SignupPerIp => self.settings.signup_per_ip,
+            Bucket::LoginPerIp => self.settings.login_per_ip,
+            Bucket::LoginPerAccount => self.settings.login_per_account,
+        };
+        let key = format!("chocoapi:rate:{}:{key}", bucket.name());
+        let outcome = match &self.backend {
+            Backend::Memory(store) => Ok(store.take(key, limit, consume)),
+            Backend::Redis(connection) => {
+                take_from_redis(connection.clone(), &key, limit, consume).await
+            }
+        };
+
+        match outcome {
+            Ok(None) => Ok(()),
+            Ok(Some(retry_after)) => {
+                tracing::info!(bucket = bucket.name(), ?retry_after, "rate limited");
+                Err(AppError::TooManyRequests { retry_after })
+            }
+            Err(error) => {
+                tracing::warn!(
+                    ?error,
+                    "failed to check the rate limit, allowing the request"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    async fn check_lockout(
+        &self,
+        account: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(), AppError> {
+        if !self.settings.enabled {
+            return Ok(());
+        }
+
+        let key = lockout_key(account, client_ip);
+        let outcome = match &self.backend {
+            Backend::Memory(store) => Ok(store.locked_for(&key)),
+            Backend::Redis(connection) => {
+                let mut connection = connection.clone();
+                redis::cmd("PTTL")
+                    .arg(&key)
+                    .query_async::<_, i64>(&mut connection)
+                    .await
+                    .map(|ttl| u64::try_from(ttl).ok().map(Duration::from_millis))
+                    .wrap_err("failed to read the lockout")
+            }
+        };
+
+        match outcome {
+            Ok(None) => Ok(()),
+            Ok(Some(retry_after)) => Err(AppError::TooManyRequests { retry_after }),
+            Err(error) => {
+                tracing::warn!(?error, "failed to check the lockout, allowing the request");
+                Ok(())
+            }
+        }
+    }
+
+    /// Count a failed login of `account` from `client_ip` in the login buckets, and lock
+    /// the account for that address once the failures are too many in a row.
+    ///
+    /// The lockout is per address, so someone guessing passwords can't lock the owner
+    /// of the account out.
+    pub async fn record_failed_login(&self, account: &str, client_ip: Option<IpAddr>) {
+        if !self.settings.enabled {
+            return;
+        }
+
+        // An empty bucket was already rejected by `check_login`
+        if let Some(client_ip) = client_ip {
+            let _ = self
+                .take(Bucket::LoginPerIp, &client_ip.to_string(), true)
+                .await;
+        }
+        let _ = self.take(Bucket::LoginPerAccount, account, true).await;
+
+        let lockout = self.settings.lockout;
+        let failures_key = failures_key(account, client_ip);
+        let lock_key = lockout_key(account, client_ip);
+        let outcome = match &self.backend {
+            Backend::Memory(store) => {
+                let failures = store.count_failure(&failures_key, lockout.failure_window());
+                if let Some(duration) = lockout.lockout(failures) {
+                    store.lock(lock_key, duration);
+                }
+                Ok(failures)
+            }
+            Backend::Redis(connection) => {
+                let mut connection = connection.clone();
+                count_failure_in_redis(&mut connection, &failures_key, &lock_key, lockout).await
+            }
+        };
+
+        match outcome {
+            Ok(failures) if failures >= lockout.max_failures => {
+                tracing::warn!(failures, "account locked after repeated failed logins");
+            }
+            Ok(_) => {}
+            Err(error) => tracing::warn!(?error, "failed to record a failed login"),
+        }
+    }
+
+    /// Forget the failed logins of `account` from `client_ip`.
+    pub async fn record_successful_login(&self, account: &str, client_ip: Option<IpAddr>) {
+        if !self.settings.enabled {
+            return;
+        }
+
+        let keys = [
+            failures_key(account, client_ip),
+            lockout_key(account, client_ip),
+        ];
+        match &self.backend {
+            Backend::Memory(store) => store.forget(&keys),
+            Backend::Redis(connection) => {
+                let mut connection = connection.clone();
+                if let Err(error) = redis::cmd("DEL")
+                    .arg(&keys)
+                    .query_async::<_, ()>(&mut connection)
+                    .await
+                {
+                    tracing::warn!(?error, "failed to forget the failed logins");
+                }
+            }
+        }
+    }
+}
+
+/// The failed logins to `account` from `client_ip`, or from anywhere if it's unknown.
+fn failures_key(account: &str, client_ip: Option<IpAddr>) -> String {
+    match client_ip {
+        Some(client_ip) => format!("chocoapi:login-failures:{account}:{client_ip}"),
+        None => format!("chocoapi:login-failures:{account}"),
+    }
+}
+
+fn lockout_key(account: &str, client_ip: Option<IpAddr>) -> String {
+    match client_ip {
+        Some(client_ip) => format!("chocoapi:lockout:{account}:{client_ip}"),
+        None => format!("chocoapi:lockout:{account}"),
+    }
+}
+
+/// Limit registrations and newsletter subscriptions by the address of the client.
+///
+/// It needs the [`RateLimiter`] and [`ClientIp`] in the extensions of the request.
+pub async fn limit_signups<B>(request: Request<B>, next: Next<B>) -> Response {
+    let limiter = request.extensions().get::<RateLimiter>();
+    let client_ip = request.extensions().get::<ClientIp>();
+    if let (Some(limiter), Some(ClientIp(client_ip))) = (limiter, client_ip) {
+        if let Err(error) = limiter
+            .check(Bucket::SignupPerIp, &client_ip.to_string())
+            .await
+        {
+            return error.into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// The buckets, failures and lockouts of a single replica.
+#[derive(Default)]
+struct MemoryStore {
+    /// The theoretical arrival time of the next request of each bucket, see `take`.
+    arrivals: Mutex<HashMap<String, Instant>>,
+    /// Failed logins in a row, and when they're forgotten.
+    failures: Mutex<HashMap<String, (u32, Instant)>>,
+    /// When each lockout ends.
+    lockouts: Mutex<HashMap<String, Instant>>,
+}
+
+impl MemoryStore {
+    /// Take a token from a bucket, returning how long to wait for one if it's empty.
+    /// The token is left in the bucket unless `consume` is true.
+    ///
+    /// This is the generic cell rate algorithm: instead of counting tokens, it keeps when
+    /// the bucket would be full again, which is a single value to store.
+    fn take(&self, key: String, limit: BucketSettings, consume: bool) -> Option<Duration> {
+        let now = Instant::now();
+        let interval = limit.refill_interval();
+        let mut arrivals = self.arrivals.lock().unwrap();
+        let arrival = arrivals
+            .get(&key)
+            .copied()
+            .filter(|arrival| *arrival > now)
+            .unwrap_or(now);
+        let next_arrival = arrival + interval;
+        let debt = next_arrival - now;
+        let burst = interval * limit.capacity;
+        if debt > burst {
+            return Some(debt - burst);
+        }
+
+        if consume {
+            make_room(&mut arrivals, now, |arrival| *arrival);
+            arrivals.insert(key, next_arrival);
+        }
+        None
+    }
+
+    /// Count a failure, returning the failures in a row.
+    fn count_failure(&self, key: &str, window: Duration) -> u32 {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        if !failures.contains_key(key) {
+            make_room(&mut failures, now, |(_, forgotten_at)| *forgotten_at);
+        }
+        let entry = failures.entry(key.to_string()).or_insert((0, now));
+        if entry.1 <= now {
+            entry.0 = 0;
+        }
+        entry.0 += 1;
+        entry.1 = now + window;
+        entry.0
+    }
+
+    fn lock(&self, key: String, duration: Duration) {
+        let now = Instant::now();
+        let mut lockouts = self.lockouts.lock().unwrap();
+        if !lockouts.contains_key(&key) {
+            make_room(&mut lockouts, now, |until| *until);
+        }
+        lockouts.insert(key, now + duration);
+    }
+
+    fn locked_for(&self, key: &str) -> Option<Duration> {
+        let now = Instant::now();
+        self.lockouts
+            .lock()
+            .unwrap()
+            .get(key)
+            .and_then(|until| until.checked_duration_since(now))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    fn forget(&self, keys: &[String; 2]) {
+        let [failures_key, lockout_key] = keys;
+        self.failures.lock().unwrap().remove(failures_key);
+        self.lockouts.lock().unwrap().remove(lockout_key);
+    }
+}
+
+/// Make room for a new entry once there are `MAX_MEMORY_ENTRIES`: drop the expired ones
+/// and, if that's not enough, the tenth that expire the soonest.
+///
+/// Evicting a lockout early only lets a few more guesses through, while letting anyone
+/// fill the memory with made up usernames would take the API down.
+fn make_room<V>(
+    entries: &mut HashMap<String, V>,
+    now: Instant,
+    expires_at: impl Fn(&V) -> Instant,
+) {
+    if entries.len() < MAX_MEMORY_ENTRIES {
+        return;
+    }
+    entries.retain(|_, value| expires_at(value) > now);
+    if entries.len() < MAX_MEMORY_ENTRIES {
+        return;
+    }
+
+    let mut expiries: Vec<Instant> = entries.values().map(&expires_at).collect();
+    let (_, threshold, _) = expiries.select_nth_unstable(MAX_MEMORY_ENTRIES / 10);
+    let threshold = *threshold;
+    entries.retain(|_, value| expires_at(value) > threshold);
+}
+
+/// The generic cell rate algorithm of `MemoryStore::take`, run atomically by Redis with
+/// its own clock, so replicas agree. Returns the milliseconds to wait, or 0 if allowed.
+/// The token is only taken if `ARGV[3]` is 1.
+const TAKE_SCRIPT: &str = r"
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local interval = tonumber(ARGV[1])
+local burst = tonumber(ARGV[2])
+local arrival = tonumber(redis.call('GET', KEYS[1]) or now)
+if arrival < now then
+    arrival = now
+end
+local next_arrival = arrival + interval
+local debt = next_arrival - now
+if debt > burst then
+    return debt - burst
+end
+if ARGV[3] == '1' then
+    redis.call('SET', KEYS[1], next_arrival, 'PX', debt)
+end
+return 0
+";
+
+async fn take_from_redis(
+    mut connection: ConnectionManager,
+    key: &str,
+    limit: BucketSettings,
+    consume: bool,
+) -> Result<Option<Duration>> {
+    let interval = limit.refill_interval_milliseconds;
+    let burst = interval.saturating_mul(limit.capacity.into());
+    let wait: u64 = redis::Script::new(TAKE_SCRIPT)
+        .key(key)
+        .arg(interval)
+        .arg(burst)
+        .arg(u8::from(consume))
+        .invoke_async(&mut connection)
+        .await
+        .wrap_err("failed to take a token")?;
+    Ok((wait > 0).then(|| Duration::from_millis(wait)))
+}
+
+async fn count_failure_in_redis(
+    connection: &mut ConnectionManager,
+    failures_key: &str,
+    lock_key: &str,
+    lockout: LockoutSettings,
+) -> Result<u32> {
+    let (failures, ()): (u32, ()) = redis::pipe()
+        .atomic()
+        .incr(failures_key, 1)
+        .expire(failures_key, lockout.failure_window_seconds.try_into()?)
+        .query_async(connection)
+        .await
+        .wrap_err("failed to count the failed login")?;
+    if let Some(duration) = lockout.lockout(failures) {
+        redis::cmd("SET")
+            .arg(lock_key)
+            .arg(1)
+            .arg("PX")
+            .arg(u64::try_from(duration.as_millis()).unwrap_or(u64::MAX))
+            .query_async::<_, ()>(connection)
+            .await
+            .wrap_err("failed to lock the account")?;
+    }
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: BucketSettings = BucketSettings {
+        capacity: 1,
+        refill_interval_milliseconds: 60_000,
+    };
+
+    #[test]
+    fn checking_a_bucket_leaves_its_token() {
+        let store = MemoryStore::default();
+
+        assert_eq!(None, store.take("key".to_string(), LIMIT, false));
+        assert_eq!(None, store.take("key".to_string(), LIMIT, true));
+        assert!(store.take("key".to_string(), LIMIT, false).is_some());
+    }
+
+    #[test]
+    fn the_memory_store_is_bounded() {
+        let store = MemoryStore::default();
+
+        for i in 0..2 * MAX_MEMORY_ENTRIES {
+            store.take(format!("key-{i}"), LIMIT, true);
+            store.count_failure(&format!("key-{i}"), Duration::from_secs(60));
+            store.lock(format!("key-{i}"), Duration::from_secs(60));
+        }
+
+        assert!(store.arrivals.lock().unwrap().len() <= MAX_MEMORY_ENTRIES);
+        assert!(store.failures.lock().unwrap().len() <= MAX_MEMORY_ENTRIES);
+        assert!(store.lockouts.lock().unwrap().len() <= MAX_MEMORY_ENTRIES);
+    }
+}
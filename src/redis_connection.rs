@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use eyre::{Result, WrapErr};
+use redis::aio::ConnectionManager;
+use secrecy::ExposeSecret;
+use tokio::sync::OnceCell;
+
+use crate::configuration::RedisSettings;
+
+/// The connection to Redis shared by the rate limiter and the readiness check.
+///
+/// It's opened on first use, so the API can start while Redis is down, and then kept:
+/// `ConnectionManager` reconnects by itself when the connection drops.
+#[derive(Clone)]
+pub struct RedisConnection {
+    client: redis::Client,
+    manager: Arc<OnceCell<ConnectionManager>>,
+}
+
+impl RedisConnection {
+    pub fn new(settings: &RedisSettings) -> Result<Self> {
+        let client = redis::Client::open(settings.uri.expose_secret().as_str())
+            .wrap_err("invalid Redis URI")?;
+        Ok(Self {
+            client,
+            manager: Arc::default(),
+        })
+    }
+
+    /// The shared connection, opening it if it isn't yet.
+    pub async fn get(&self) -> Result<ConnectionManager> {
+        self.manager
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await
+            .cloned()
+            .wrap_err("failed to connect to Redis")
+    }
+}
@@ -0,0 +1,67 @@
+use sqlx::postgres::PgPool;
+use uuid::Uuid;
+
+use crate::erro::AppError;
+
+/// The state of a data export.
+pub enum DataExportStatus {
+    Pending,
+    Ready { file_path: String },
+}
+
+/// A repository for managing the personal data exports of users.
+#[derive(Clone)]
+pub struct DataExportRepository(PgPool);
+
+impl DataExportRepository {
+    pub fn new(pool: PgPool) -> Self {
+        DataExportRepository(pool)
+    }
+
+    /// Get the latest data export of a user, unless it expired or failed.
+    ///
+    /// Archives are available for 7 days once generated.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_latest(&self, user_id: Uuid) -> Result<Option<DataExportStatus>, AppError> {
+        sqlx::query!(
+            r#"
+            SELECT file_path
+            FROM data_exports
+            WHERE user_id = $1
+                AND failed_at IS NULL
+                AND (completed_at IS NULL OR completed_at > transaction_timestamp() - interval '7 days')
+            ORDER BY created_at DESC
+            LIMIT 1
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.0)
+        .await
+        .map(|record| {
+            record.map(|r| match r.file_path {
+                Some(file_path) => DataExportStatus::Ready { file_path },
+                None => DataExportStatus::Pending,
+            })
+        })
+        .map_err(AppError::Sqlx)
+    }
+
+    /// Request a new data export for a user.
+    ///
+    /// Nothing happens if the user already has an export being generated.
+    #[tracing::instrument(skip(self))]
+    pub async fn create(&self, user_id: Uuid) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO data_exports (user_id)
+            VALUES ($1)
+            ON CONFLICT DO NOTHING
+            "#,
+            user_id
+        )
+        .execute(&self.0)
+        .await
+        .map(|_| ())
+        .map_err(AppError::Sqlx)
+    }
+}
@@ -1,7 +1,17 @@
 use sqlx::postgres::PgPool;
 use uuid::Uuid;
 
-use crate::erro::AppError;
+use crate::{
+    erro::{AppError, ResultExt},
+    models::EmailAddress,
+    problems::ProblemType,
+};
+
+/// An email address stored in the database and the user it belongs to, if any.
+pub struct EmailOwner {
+    pub email_id: Uuid,
+    pub user_id: Option<Uuid>,
+}
 
 #[derive(Clone)]
 pub struct EmailRepository(PgPool);
@@ -12,18 +22,185 @@ impl EmailRepository {
     }
 
     /// Create a new email in the database.
-    pub async fn create_email(&self, email: String) -> Result<Uuid, AppError> {
+    ///
+    /// Returns the id of the existing one if the address was stored meanwhile, in any case.
+    #[tracing::instrument(skip_all)]
+    pub async fn create_email(&self, email: &EmailAddress) -> Result<Uuid, AppError> {
         sqlx::query!(
             r#"
             INSERT INTO emails (email)
             VALUES ($1)
+            ON CONFLICT ((lower(email))) DO UPDATE SET email = emails.email
             RETURNING id
             "#,
-            email
+            email.as_ref()
         )
         .fetch_one(&self.0)
         .await
         .map(|record| record.id)
         .map_err(AppError::Sqlx)
     }
+
+    /// Get the address of an email by its id.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_address(&self, id: Uuid) -> Result<Option<String>, AppError> {
+        sqlx::query!(r#"SELECT email FROM emails WHERE id = $1"#, id)
+            .fetch_optional(&self.0)
+            .await
+            .map(|record| record.map(|r| r.email))
+            .map_err(AppError::Sqlx)
+    }
+
+    /// Find an email address, regardless of its case, and the user that owns it.
+    #[tracing::instrument(skip_all)]
+    pub async fn find_owner(&self, email: &EmailAddress) -> Result<Option<EmailOwner>, AppError> {
+        sqlx::query_as!(
+            EmailOwner,
+            r#"
+            SELECT emails.id AS email_id, users.id AS "user_id?"
+            FROM emails
+            LEFT JOIN users ON users.email_id = emails.id
+            WHERE lower(emails.email) = lower($1)
+            "#,
+            email.as_ref()
+        )
+        .fetch_optional(&self.0)
+        .await
+        .map_err(AppError::Sqlx)
+    }
+
+    /// Store a request to change the email address of a user.
+    ///
+    /// Any previous unconfirmed request of the same user is discarded.
+    #[tracing::instrument(skip(self, token))]
+    pub async fn create_email_change(
+        &self,
+        user_id: Uuid,
+        old_email_id: Uuid,
+        new_email_id: Uuid,
+        token: &str,
+    ) -> Result<(), AppError> {
+        let mut transaction = self.0.begin().await?;
+
+        sqlx::query!(
+            r#"DELETE FROM email_changes WHERE user_id = $1 AND confirmed_at IS NULL"#,
+            user_id
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO email_changes (user_id, old_email_id, new_email_id, token, expires_at)
+            VALUES ($1, $2, $3, $4, transaction_timestamp() + interval '1 day')
+            "#,
+            user_id,
+            old_email_id,
+            new_email_id,
+            token
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// Confirm a pending email change, switching the email of its user to the new address.
+    ///
+    /// Returns the id of the user whose email changed or `None` if the token is unknown,
+    /// expired or already used, and `409 Conflict` if another account took the address
+    /// since the change was requested. The other pending changes to the address are
+    /// discarded.
+    #[tracing::instrument(skip_all)]
+    pub async fn confirm_email_change(&self, token: &str) -> Result<Option<Uuid>, AppError> {
+        let mut transaction = self.0.begin().await?;
+
+        let email_change = sqlx::query!(
+            r#"
+            SELECT id, user_id, new_email_id
+            FROM email_changes
+            WHERE token = $1 AND confirmed_at IS NULL AND expires_at > transaction_timestamp()
+            FOR UPDATE
+            "#,
+            token
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+
+        let email_change = match email_change {
+            Some(email_change) => email_change,
+            None => return Ok(None),
+        };
+
+        // Locked so no one else can take the address until the switch is committed
+        let taken = sqlx::query!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM users WHERE users.email_id = emails.id AND users.id <> $2
+            ) AS "taken!"
+            FROM emails
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            email_change.new_email_id,
+            email_change.user_id
+        )
+        .fetch_one(&mut transaction)
+        .await?
+        .taken;
+        if taken {
+            return Err(email_taken());
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE emails
+            SET email_confirmed_at = transaction_timestamp(), updated_at = transaction_timestamp()
+            WHERE id = $1
+            "#,
+            email_change.new_email_id
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET email_id = $1, updated_at = transaction_timestamp()
+            WHERE id = $2
+            "#,
+            email_change.new_email_id,
+            email_change.user_id
+        )
+        .execute(&mut transaction)
+        .await
+        .on_constraint("users_email_id_key", |_| email_taken())?;
+
+        sqlx::query!(
+            r#"UPDATE email_changes SET confirmed_at = transaction_timestamp() WHERE id = $1"#,
+            email_change.id
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM email_changes
+            WHERE new_email_id = $1 AND confirmed_at IS NULL
+            "#,
+            email_change.new_email_id
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(Some(email_change.user_id))
+    }
+}
+
+fn email_taken() -> AppError {
+    AppError::conflict(ProblemType::EmailTaken, [("email", "already_registered")])
 }
@@ -1,81 +1,305 @@
-use std::io::Cursor;
+use std::time::Instant;
 
-use eyre::Context;
-use image::io::Reader as ImageReader;
-use sqlx::postgres::PgPool;
-use std::path::PathBuf;
+use eyre::{Context, ContextCompat};
+use image::{io::Reader as ImageReader, ImageFormat};
+use sqlx::PgConnection;
 use uuid::Uuid;
 
-use crate::erro::AppError;
+use super::UnitOfWork;
+use crate::{
+    erro::AppError,
+    metrics::METRICS,
+    storage::{BlobStore, StagedFile},
+    telemetry::spawn_blocking_with_tracing,
+};
 
+/// A repository for managing images and their files.
+///
+/// Images are always created as part of a `UnitOfWork`, so their files are deleted
+/// if the transaction is rolled back.
 #[derive(Clone)]
-pub struct ImageRepository(PgPool);
+pub struct ImageRepository(BlobStore);
 
 impl ImageRepository {
-    pub fn new(pool: PgPool) -> Self {
-        ImageRepository(pool)
+    pub fn new(blob_store: BlobStore) -> Self {
+        ImageRepository(blob_store)
     }
 
-    /// Create a new image mime type in the database.
-    pub async fn create_image_mime_type(&self, mime_type: &str) -> i16 {
-        sqlx::query!(
+    /// Get the id of an image mime type, creating it if it doesn't exist yet.
+    ///
+    /// Concurrent uploads of a new mime type don't conflict: the insert is skipped if
+    /// another transaction already created it, which is then visible to the select.
+    #[tracing::instrument(skip(self, conn))]
+    pub async fn get_or_create_mime_type(
+        &self,
+        conn: &mut PgConnection,
+        mime_type: &str,
+    ) -> Result<i16, AppError> {
+        let inserted = sqlx::query!(
             r#"
             INSERT INTO image_mime_types (mime)
             VALUES ($1)
+            ON CONFLICT (mime) DO NOTHING
             RETURNING id
             "#,
             mime_type
         )
-        .fetch_one(&self.0)
-        .await
-        .unwrap()
-        .id
-    }
+        .fetch_optional(&mut *conn)
+        .await?;
 
-    /// Create a new image in the database.
-    pub async fn create_image(&self, mime_type: &str, bytes: Vec<u8>) -> Result<Uuid, AppError> {
-        let img = ImageReader::new(Cursor::new(&bytes))
-            .with_guessed_format()
-            .wrap_err("failed to guess image format")?
-            .decode()
-            .wrap_err("failed to decode image")?;
+        if let Some(record) = inserted {
+            return Ok(record.id);
+        }
 
-        let width: i32 = img.width().try_into().unwrap();
-        let height: i32 = img.height().try_into().unwrap();
-        let image_id = Uuid::new_v4();
-        let img_size: i32 = bytes.len().try_into().unwrap();
-
-        // TODO: Check that the directory exists and all that.
-        let file_path: PathBuf = ["image_uuid", &format!("image_file_{image_id}")]
-            .iter()
-            .collect();
-
-        // Try to fetch an existing mime_type id or create a new one if it does not
-        // already exist.
-        let mime_id = match sqlx::query!(
+        let id = sqlx::query!(
             r#"SELECT id FROM image_mime_types WHERE mime = $1"#,
             mime_type
         )
-        .fetch_optional(&self.0)
-        .await
-        .wrap_err("failed to fetch image mime type")?
-        .map(|r| r.id)
-        {
-            Some(id) => id,
-            None => self.create_image_mime_type(mime_type).await,
+        .fetch_one(conn)
+        .await?
+        .id;
+        Ok(id)
+    }
+
+    /// Create a new image in the database, moving its staged file to the blob store.
+    ///
+    /// Files are addressed by their content: if one with the same SHA-256 hash is already
+    /// stored, the image shares it and the staged file is discarded. The same file is used
+    /// for all the sizes of the image.
+    #[tracing::instrument(
+        skip(self, uow, alt_text, file),
+        fields(size_bytes = file.size(), deduplicated = false)
+    )]
+    pub async fn create_image(
+        &self,
+        uow: &mut UnitOfWork,
+        alt_text: &str,
+        file: StagedFile,
+    ) -> Result<Uuid, AppError> {
+        let start = Instant::now();
+        METRICS.image_upload_bytes.observe(file.size() as f64);
+
+        let image_id = Uuid::new_v4();
+        let file_id = match self.acquire_file(uow.connection(), &file.sha256()).await? {
+            Some(file_id) => {
+                tracing::Span::current().record("deduplicated", &true);
+                file_id
+            }
+            None => self.store_file(uow, image_id, file).await?,
         };
 
         let id = sqlx::query!(r#"
-                     INSERT INTO image_files (id, width_px, height_px, file_path, size_bytes, mime_id)
-                     VALUES ($1, $2, $3, $4, $5, $6)
+                     INSERT INTO images (id, title, alt_text, small_file_id, medium_file_id, large_file_id)
+                     VALUES ($1, $2, $3, $4, $4, $4)
                      RETURNING id
                      "#,
-                     image_id, width, height, file_path.to_str(), img_size, mime_id)
-            .fetch_one(&self.0)
+                     image_id, image_id.simple().to_string(), alt_text, file_id)
+            .fetch_one(uow.connection())
             .await
-            .wrap_err("failed to insert image file in database")?
+            .wrap_err("failed to insert image in database")?
             .id;
 
+        METRICS
+            .image_processing_duration
+            .observe(start.elapsed().as_secs_f64());
         Ok(id)
     }
+
+    /// Add a reference to the stored file with the given hash, if there's one.
+    ///
+    /// The row stays locked until the transaction ends, so it can't be released meanwhile.
+    async fn acquire_file(
+        &self,
+        conn: &mut PgConnection,
+        sha256: &[u8; 32],
+    ) -> Result<Option<Uuid>, AppError> {
+        let file_id = sqlx::query!(
+            r#"
+            UPDATE image_files
+            SET ref_count = ref_count + 1
+            WHERE sha256 = $1
+            RETURNING id
+            "#,
+            &sha256[..]
+        )
+        .fetch_optional(conn)
+        .await?
+        .map(|record| record.id);
+        Ok(file_id)
+    }
+
+    /// Store a file whose content isn't stored yet, referenced once by the image `image_id`.
+    ///
+    /// The file is only decoded now that it's fully staged, to check it's an image and get
+    /// its dimensions. Its mime type and extension are the ones of the format detected
+    /// from its content, whatever the client claimed: `415 Unsupported Media Type` if
+    /// none is.
+    async fn store_file(
+        &self,
+        uow: &mut UnitOfWork,
+        image_id: Uuid,
+        mut file: StagedFile,
+    ) -> Result<Uuid, AppError> {
+        file.flush().await?;
+        let path = file.path().to_owned();
+        let image = spawn_blocking_with_tracing(move || {
+            let reader = ImageReader::open(&path)
+                .wrap_err("failed to open image")?
+                .with_guessed_format()
+                .wrap_err("failed to guess image format")?;
+            let format = match reader.format() {
+                Some(format) => format,
+                None => return Ok(None),
+            };
+            let img = reader.decode().wrap_err("failed to decode image")?;
+            Ok::<_, eyre::Report>(Some((format, img.width(), img.height())))
+        })
+        .await
+        .wrap_err("failed to spawn blocking task")??;
+        let (format, width, height) = image.ok_or(AppError::UnsupportedMediaType)?;
+        let extension = format.extensions_str().first().copied().unwrap_or("bin");
+
+        // The columns are `integer`, images that don't fit are too large anyway
+        let width: i32 = width.try_into().map_err(|_| AppError::PayloadTooLarge)?;
+        let height: i32 = height.try_into().map_err(|_| AppError::PayloadTooLarge)?;
+        let img_size: i32 = file
+            .size()
+            .try_into()
+            .map_err(|_| AppError::PayloadTooLarge)?;
+        let sha256 = file.sha256();
+        let file_id = Uuid::new_v4();
+        let file_path = format!("{image_id}/{file_id}.{extension}");
+
+        let mime_id = self
+            .get_or_create_mime_type(uow.connection(), mime_type(format))
+            .await?;
+
+        // A concurrent upload of the same content makes this wait until it's committed
+        let inserted = sqlx::query!(r#"
+                     INSERT INTO image_files (id, width_px, height_px, file_path, size_bytes, mime_id, sha256, ref_count)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, 1)
+                     ON CONFLICT (sha256) DO NOTHING
+                     RETURNING id
+                     "#,
+                     file_id, width, height, file_path, img_size, mime_id, &sha256[..])
+            .fetch_optional(uow.connection())
+            .await
+            .wrap_err("failed to insert image file in database")?;
+
+        if inserted.is_none() {
+            let file_id = self
+                .acquire_file(uow.connection(), &sha256)
+                .await?
+                .wrap_err("the stored file of the same content was deleted meanwhile")?;
+            tracing::Span::current().record("deduplicated", &true);
+            return Ok(file_id);
+        }
+
+        self.0.persist(file, &file_path).await?;
+        uow.track_blob(&file_path);
+        Ok(file_id)
+    }
+
+    /// Delete an image unless a post or a user still uses it.
+    ///
+    /// Its files are deleted once `uow` is committed, unless other images share them.
+    #[tracing::instrument(skip(self, uow))]
+    pub async fn delete_image(&self, uow: &mut UnitOfWork, id: Uuid) -> Result<(), AppError> {
+        let image = sqlx::query!(
+            r#"
+            DELETE FROM images
+            WHERE id = $1
+                AND NOT EXISTS (
+                    SELECT 1 FROM posts WHERE cover_image_id = $1 OR og_image_id = $1
+                )
+                AND NOT EXISTS (SELECT 1 FROM users WHERE profile_pic_id = $1)
+            RETURNING small_file_id, medium_file_id, large_file_id
+            "#,
+            id
+        )
+        .fetch_optional(uow.connection())
+        .await?;
+
+        let image = match image {
+            Some(image) => image,
+            None => return Ok(()),
+        };
+
+        let file_paths = self
+            .release_files(
+                uow.connection(),
+                &[
+                    image.small_file_id,
+                    image.medium_file_id,
+                    image.large_file_id,
+                ],
+            )
+            .await?;
+        for file_path in file_paths {
+            uow.delete_blob_after_commit(file_path);
+        }
+
+        Ok(())
+    }
+
+    /// Drop the reference of a deleted image to each of its files, and delete the rows of
+    /// the ones no image references anymore.
+    ///
+    /// Returns the paths of their stored files, to be deleted once the transaction is
+    /// committed.
+    #[tracing::instrument(skip(self, conn))]
+    pub async fn release_files(
+        &self,
+        conn: &mut PgConnection,
+        file_ids: &[Uuid],
+    ) -> Result<Vec<String>, AppError> {
+        // Each file is counted once, even if the image uses it for several sizes
+        sqlx::query!(
+            r#"
+            UPDATE image_files
+            SET ref_count = ref_count - 1
+            WHERE id = ANY($1)
+            "#,
+            file_ids
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        let file_paths = sqlx::query!(
+            r#"
+            DELETE FROM image_files
+            WHERE id = ANY($1) AND ref_count = 0
+            RETURNING file_path
+            "#,
+            file_ids
+        )
+        .fetch_all(conn)
+        .await?
+        .into_iter()
+        .map(|record| record.file_path)
+        .collect();
+
+        Ok(file_paths)
+    }
+}
+
+/// The mime type of the images of `format`.
+fn mime_type(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "image/png",
+        ImageFormat::Jpeg => "image/jpeg",
+        ImageFormat::Gif => "image/gif",
+        ImageFormat::WebP => "image/webp",
+        ImageFormat::Pnm => "image/x-portable-anymap",
+        ImageFormat::Tiff => "image/tiff",
+        ImageFormat::Tga => "image/x-tga",
+        ImageFormat::Dds => "image/vnd-ms.dds",
+        ImageFormat::Bmp => "image/bmp",
+        ImageFormat::Ico => "image/x-icon",
+        ImageFormat::Hdr => "image/vnd.radiance",
+        ImageFormat::OpenExr => "image/x-exr",
+        ImageFormat::Farbfeld => "image/x-farbfeld",
+        ImageFormat::Avif => "image/avif",
+        _ => "application/octet-stream",
+    }
 }
@@ -1,7 +1,13 @@
+mod data_export_repository;
 mod email_repository;
 mod image_repository;
+mod newsletter_repository;
+mod unit_of_work;
 mod user_repository;
 
+pub(crate) use data_export_repository::*;
 pub(crate) use email_repository::*;
 pub(crate) use image_repository::*;
+pub(crate) use newsletter_repository::*;
+pub(crate) use unit_of_work::*;
 pub(crate) use user_repository::*;
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use sqlx::postgres::{types::PgInterval, PgPool};
+use uuid::Uuid;
+
+use crate::erro::AppError;
+
+/// A repository for managing newsletter subscriptions and issues.
+#[derive(Clone)]
+pub struct NewsletterRepository(PgPool);
+
+impl NewsletterRepository {
+    pub fn new(pool: PgPool) -> Self {
+        NewsletterRepository(pool)
+    }
+
+    /// Check whether an email address is confirmed and subscribed to the newsletter.
+    #[tracing::instrument(skip(self))]
+    pub async fn is_subscribed(&self, email_id: Uuid) -> Result<bool, AppError> {
+        sqlx::query!(
+            r#"
+            SELECT subscribed AND email_confirmed_at IS NOT NULL AS "subscribed!"
+            FROM emails
+            WHERE id = $1
+            "#,
+            email_id
+        )
+        .fetch_optional(&self.0)
+        .await
+        .map(|record| record.map_or(false, |r| r.subscribed))
+        .map_err(AppError::Sqlx)
+    }
+
+    /// Store the token sent to an email address to confirm its subscription, unless
+    /// another one was created for it less than `cooldown` ago and is still valid.
+    ///
+    /// Returns whether the token was stored, i.e. whether it should be sent.
+    #[tracing::instrument(skip(self, token))]
+    pub async fn create_subscription_token(
+        &self,
+        email_id: Uuid,
+        token: &str,
+        cooldown: Duration,
+    ) -> Result<bool, AppError> {
+        let cooldown = PgInterval::try_from(cooldown)
+            .map_err(|e| eyre::eyre!("invalid confirmation cooldown: {e}"))?;
+        sqlx::query!(
+            r#"
+            INSERT INTO subscription_tokens (token, email_id, expires_at)
+            SELECT $1, $2, transaction_timestamp() + interval '1 day'
+            WHERE NOT EXISTS (
+                SELECT 1 FROM subscription_tokens
+                WHERE email_id = $2
+                    AND expires_at > transaction_timestamp()
+                    AND created_at > transaction_timestamp() - $3::interval
+            )
+            "#,
+            token,
+            email_id,
+            cooldown
+        )
+        .execute(&self.0)
+        .await
+        .map(|result| result.rows_affected() > 0)
+        .map_err(AppError::Sqlx)
+    }
+
+    /// Confirm a subscription using the token sent to its email address.
+    ///
+    /// Following the link also proves the ownership of the address, so it's marked as confirmed.
+    /// Returns the id of the subscribed email or `None` if the token is unknown or expired.
+    #[tracing::instrument(skip_all)]
+    pub async fn confirm_subscription(&self, token: &str) -> Result<Option<Uuid>, AppError> {
+        let mut transaction = self.0.begin().await?;
+
+        let email_id = sqlx::query!(
+            r#"
+            SELECT email_id
+            FROM subscription_tokens
+            WHERE token = $1 AND expires_at > transaction_timestamp()
+            "#,
+            token
+        )
+        .fetch_optional(&mut transaction)
+        .await?
+        .map(|r| r.email_id);
+
+        let email_id = match email_id {
+            Some(email_id) => email_id,
+            None => return Ok(None),
+        };
+
+        sqlx::query!(
+            r#"
+            UPDATE emails
+            SET email_confirmed_at = COALESCE(email_confirmed_at, transaction_timestamp()),
+                subscribed = TRUE,
+                updated_at = transaction_timestamp()
+            WHERE id = $1
+            "#,
+            email_id
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query!(
+            r#"DELETE FROM subscription_tokens WHERE email_id = $1"#,
+            email_id
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(Some(email_id))
+    }
+
+    /// Unsubscribe an email address from the newsletter.
+    ///
+    /// Returns `false` if the address doesn't exist.
+    #[tracing::instrument(skip(self))]
+    pub async fn unsubscribe(&self, email_id: Uuid) -> Result<bool, AppError> {
+        sqlx::query!(
+            r#"
+            UPDATE emails
+            SET subscribed = FALSE, updated_at = transaction_timestamp()
+            WHERE id = $1
+            "#,
+            email_id
+        )
+        .execute(&self.0)
+        .await
+        .map(|result| result.rows_affected() > 0)
+        .map_err(AppError::Sqlx)
+    }
+
+    /// Store a newsletter issue and queue its delivery to every confirmed, subscribed
+    /// and active email address.
+    #[tracing::instrument(skip(self, title, html_content, text_content))]
+    pub async fn create_issue(
+        &self,
+        author_id: Uuid,
+        title: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<Uuid, AppError> {
+        let mut transaction = self.0.begin().await?;
+
+        let issue_id = sqlx::query!(
+            r#"
+            INSERT INTO newsletter_issues (author_id, title, html_content, text_content)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#,
+            author_id,
+            title,
+            html_content,
+            text_content
+        )
+        .fetch_one(&mut transaction)
+        .await?
+        .id;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO issue_delivery_queue (newsletter_issue_id, email_id)
+            SELECT $1, id
+            FROM emails
+            WHERE email_confirmed_at IS NOT NULL AND subscribed AND active
+            "#,
+            issue_id
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(issue_id)
+    }
+}
@@ -0,0 +1,122 @@
+use std::time::Instant;
+
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+
+use crate::{erro::AppError, metrics::METRICS, storage::BlobStore};
+
+/// A database transaction spanning several repositories, along with the files stored during it.
+///
+/// Files can't be part of the transaction, so the ones stored through
+/// [`UnitOfWork::track_blob`] are deleted unless the transaction is committed, while the
+/// ones passed to [`UnitOfWork::delete_blob_after_commit`] are only deleted once it is.
+pub struct UnitOfWork {
+    transaction: Transaction<'static, Postgres>,
+    stored_blobs: StoredBlobs,
+    unreferenced_blobs: Vec<String>,
+}
+
+impl UnitOfWork {
+    /// Begin a new transaction.
+    #[tracing::instrument(skip_all)]
+    pub async fn begin(pool: &PgPool, blob_store: BlobStore) -> Result<Self, AppError> {
+        let start = Instant::now();
+        let transaction = pool.begin().await?;
+        METRICS
+            .db_pool_acquire_duration
+            .observe(start.elapsed().as_secs_f64());
+
+        Ok(Self {
+            transaction,
+            stored_blobs: StoredBlobs {
+                blob_store,
+                keys: Vec::new(),
+            },
+            unreferenced_blobs: Vec::new(),
+        })
+    }
+
+    /// The connection to run queries on, as part of the transaction.
+    pub fn connection(&mut self) -> &mut PgConnection {
+        &mut self.transaction
+    }
+
+    /// Delete the file stored with `key` unless the transaction is committed.
+    pub fn track_blob(&mut self, key: impl Into<String>) {
+        self.stored_blobs.keys.push(key.into());
+    }
+
+    /// Delete the file stored with `key` once the transaction is committed.
+    pub fn delete_blob_after_commit(&mut self, key: impl Into<String>) {
+        self.unreferenced_blobs.push(key.into());
+    }
+
+    /// Commit the transaction, keeping the stored files.
+    #[tracing::instrument(skip_all)]
+    pub async fn commit(self) -> Result<(), AppError> {
+        let Self {
+            transaction,
+            mut stored_blobs,
+            unreferenced_blobs,
+        } = self;
+
+        transaction.commit().await?;
+        stored_blobs.keys.clear();
+
+        // The database no longer references these files, failing to delete them is harmless.
+        for key in unreferenced_blobs {
+            if let Err(error) = stored_blobs.blob_store.delete(&key).await {
+                tracing::warn!(?error, key, "failed to delete file");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Roll back the transaction and delete the stored files.
+    ///
+    /// Dropping a `UnitOfWork` has the same effect, but the files are deleted in the background.
+    #[tracing::instrument(skip_all)]
+    pub async fn rollback(self) -> Result<(), AppError> {
+        let Self {
+            transaction,
+            mut stored_blobs,
+            ..
+        } = self;
+
+        transaction.rollback().await?;
+        while let Some(key) = stored_blobs.keys.pop() {
+            stored_blobs.blob_store.delete(&key).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compensates the files stored during a transaction that was never committed.
+struct StoredBlobs {
+    blob_store: BlobStore,
+    keys: Vec<String>,
+}
+
+impl Drop for StoredBlobs {
+    fn drop(&mut self) {
+        if self.keys.is_empty() {
+            return;
+        }
+
+        let blob_store = self.blob_store.clone();
+        let keys = std::mem::take(&mut self.keys);
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    for key in keys {
+                        if let Err(error) = blob_store.delete(&key).await {
+                            tracing::warn!(?error, key, "failed to delete file");
+                        }
+                    }
+                });
+            }
+            Err(_) => tracing::warn!(?keys, "files of a rolled back transaction were left behind"),
+        }
+    }
+}
@@ -1,8 +1,13 @@
-use sqlx::postgres::PgPool;
+use secrecy::SecretString;
+use sqlx::{error::DatabaseError, postgres::PgPool, PgConnection};
+use time::OffsetDateTime;
+use uuid::Uuid;
 
 use crate::{
-    erro::AppError,
+    erro::{AppError, ResultExt},
+    i18n::Locale,
     models::{InsertableUser, User},
+    problems::ProblemType,
 };
 
 /// A repository for managing users.
@@ -15,32 +20,268 @@ impl UserRepository {
         UserRepository(pool)
     }
 
-    /// Create a new user in the database.
-    pub async fn create_user(&self, user: InsertableUser) -> Result<User, AppError> {
+    /// Create a new user in the database, along with its email address.
+    ///
+    /// An address that is already stored but doesn't belong to any user, e.g. the one of
+    /// a newsletter subscriber, is reused. Returns `409 Conflict` if it belongs to another user,
+    /// or if the username is taken.
+    ///
+    /// Both inserts run on `conn`, usually the connection of a `UnitOfWork`.
+    #[tracing::instrument(skip_all)]
+    pub async fn create_user(
+        &self,
+        conn: &mut PgConnection,
+        user: InsertableUser,
+    ) -> Result<User, AppError> {
+        let email = user.email();
+
+        // The no-op update makes the existing row available to `RETURNING`.
+        let email_id = sqlx::query!(
+            r#"
+            INSERT INTO emails (email)
+            VALUES ($1)
+            ON CONFLICT ((lower(email))) DO UPDATE SET email = emails.email
+            RETURNING id
+            "#,
+            email.as_ref()
+        )
+        .fetch_one(&mut *conn)
+        .await?
+        .id;
+
         sqlx::query_as!(
             User,
             r#"
-            INSERT INTO users (username, full_name, profile_pic_id, email_id, passwd_hash)
-            VALUES ($1, $2, $3, $4, $5)
+            INSERT INTO users (username, full_name, profile_pic_id, email_id, passwd_hash, locale)
+            VALUES ($1, $2, $3, $4, $5, $6)
             RETURNING *
             "#,
             user.username(),
             user.full_name(),
             user.profile_pic_id(),
-            user.email_id(),
-            user.passwd_hash()
+            email_id,
+            user.passwd_hash(),
+            user.locale().as_str()
+        )
+        .fetch_one(conn)
+        .await
+        .on_constraint("users_email_id_key", |_| {
+            AppError::conflict(ProblemType::EmailTaken, [("email", "already_registered")])
+        })
+        .on_constraint("users_username_key", username_taken)
+        .on_constraint("users_username_skeleton_key", username_taken)
+    }
+
+    /// Get a single `User` by its id.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_by_id(&self, id: Uuid) -> Result<Option<User>, AppError> {
+        sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", id)
+            .fetch_optional(&self.0)
+            .await
+            .map_err(AppError::Sqlx)
+    }
+
+    /// Get the id and password hash of an active user by its username.
+    #[tracing::instrument(skip_all)]
+    pub async fn get_credentials(
+        &self,
+        username: &str,
+    ) -> Result<Option<(Uuid, SecretString)>, AppError> {
+        sqlx::query!(
+            r#"
+            SELECT id, passwd_hash
+            FROM users
+            WHERE username = $1 AND active
+            "#,
+            username
+        )
+        .fetch_optional(&self.0)
+        .await
+        .map(|record| record.map(|r| (r.id, SecretString::new(r.passwd_hash))))
+        .map_err(AppError::Sqlx)
+    }
+
+    /// Replace the profile picture of a user.
+    ///
+    /// Returns the id of the previous profile picture, if any.
+    #[tracing::instrument(skip(self, conn))]
+    pub async fn set_profile_pic(
+        &self,
+        conn: &mut PgConnection,
+        id: Uuid,
+        profile_pic_id: Uuid,
+    ) -> Result<Option<Uuid>, AppError> {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET profile_pic_id = $2, updated_at = transaction_timestamp()
+            FROM (SELECT id, profile_pic_id FROM users WHERE id = $1 FOR UPDATE) AS previous
+            WHERE users.id = previous.id
+            RETURNING previous.profile_pic_id
+            "#,
+            id,
+            profile_pic_id
+        )
+        .fetch_optional(conn)
+        .await
+        .map(|record| record.and_then(|r| r.profile_pic_id))
+        .map_err(AppError::Sqlx)
+    }
+
+    /// Change the username of a user.
+    ///
+    /// Returns `409 Conflict` if it's taken, or looks like one that is.
+    #[tracing::instrument(skip(self, username))]
+    pub async fn set_username(&self, id: Uuid, username: &str) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET username = $2, updated_at = transaction_timestamp()
+            WHERE id = $1
+            "#,
+            id,
+            username
+        )
+        .execute(&self.0)
+        .await
+        .on_constraint("users_username_key", username_taken)
+        .on_constraint("users_username_skeleton_key", username_taken)?;
+        Ok(())
+    }
+
+    /// Change the locale of the emails sent to a user.
+    #[tracing::instrument(skip(self))]
+    pub async fn set_locale(&self, id: Uuid, locale: Locale) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET locale = $2, updated_at = transaction_timestamp()
+            WHERE id = $1
+            "#,
+            id,
+            locale.as_str()
+        )
+        .execute(&self.0)
+        .await?;
+        Ok(())
+    }
+
+    /// Activate or deactivate a user. Inactive users can't authenticate.
+    ///
+    /// Returns `false` if the user doesn't exist.
+    #[tracing::instrument(skip(self))]
+    pub async fn set_active(&self, id: Uuid, active: bool) -> Result<bool, AppError> {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET active = $2, updated_at = transaction_timestamp()
+            WHERE id = $1
+            "#,
+            id,
+            active
+        )
+        .execute(&self.0)
+        .await
+        .map(|result| result.rows_affected() > 0)
+        .map_err(AppError::Sqlx)
+    }
+
+    /// Schedule the deletion of a user after a grace period.
+    ///
+    /// Returns the moment the user will be deleted.
+    #[tracing::instrument(skip(self))]
+    pub async fn schedule_deletion(
+        &self,
+        id: Uuid,
+        grace_period_days: i32,
+    ) -> Result<OffsetDateTime, AppError> {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET deletion_scheduled_at = COALESCE(
+                    deletion_scheduled_at,
+                    transaction_timestamp() + make_interval(days => $2)
+                ),
+                updated_at = transaction_timestamp()
+            WHERE id = $1
+            RETURNING deletion_scheduled_at AS "deletion_scheduled_at!"
+            "#,
+            id,
+            grace_period_days
+        )
+        .fetch_one(&self.0)
+        .await
+        .map(|record| record.deletion_scheduled_at)
+        .map_err(AppError::Sqlx)
+    }
+
+    /// Cancel the scheduled deletion of a user.
+    ///
+    /// Returns `false` if no deletion was scheduled.
+    #[tracing::instrument(skip(self))]
+    pub async fn cancel_deletion(&self, id: Uuid) -> Result<bool, AppError> {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET deletion_scheduled_at = NULL,
+                deletion_retries = 0,
+                deletion_failed_at = NULL,
+                updated_at = transaction_timestamp()
+            WHERE id = $1 AND deletion_scheduled_at IS NOT NULL
+            "#,
+            id
+        )
+        .execute(&self.0)
+        .await
+        .map(|result| result.rows_affected() > 0)
+        .map_err(AppError::Sqlx)
+    }
+
+    /// Check if a user has the role with the given name.
+    #[tracing::instrument(skip(self))]
+    pub async fn has_role(&self, id: Uuid, role_name: &str) -> Result<bool, AppError> {
+        sqlx::query!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1
+                FROM users_roles
+                JOIN roles ON roles.id = users_roles.role_id
+                WHERE users_roles.user_id = $1 AND roles.role_name = $2
+            ) AS "exists!"
+            "#,
+            id,
+            role_name
         )
         .fetch_one(&self.0)
         .await
+        .map(|record| record.exists)
         .map_err(AppError::Sqlx)
     }
 
-    // /// Get a single `User` by its id.
-    // pub async fn get_by_id(&self, id: Uuid) -> Option<User> {
-    //     sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", id)
-    //         .fetch_optional(&self.0)
-    //         .await
-    //         .wrap_err("failed to fetch user from database")
-    //         .unwrap()
-    // }
+    /// Grant the role with the given name to a user, on `conn`. Granting it twice is a no-op.
+    #[tracing::instrument(skip(self, conn))]
+    pub async fn add_role(
+        &self,
+        conn: &mut PgConnection,
+        id: Uuid,
+        role_name: &str,
+    ) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO users_roles (user_id, role_id)
+            SELECT $1, roles.id FROM roles WHERE roles.role_name = $2
+            ON CONFLICT DO NOTHING
+            "#,
+            id,
+            role_name
+        )
+        .execute(conn)
+        .await
+        .map(|_| ())
+        .map_err(AppError::Sqlx)
+    }
+}
+
+fn username_taken(_: Box<dyn DatabaseError>) -> AppError {
+    AppError::conflict(ProblemType::UsernameTaken, [("username", "already_taken")])
 }
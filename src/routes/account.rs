@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use axum::{extract::Path, http::StatusCode, Extension};
+use eyre::{Context, ContextCompat};
+use uuid::Uuid;
+
+use crate::{
+    authentication::{AuthenticatedAdmin, AuthenticatedUser},
+    configuration::AccountSettings,
+    email_client::EmailClient,
+    erro::AppError,
+    repositories::{EmailRepository, UserRepository},
+};
+
+/// Deactivate the account of the authenticated user.
+///
+/// Inactive users can't authenticate until an admin reactivates them.
+pub async fn deactivate_account(
+    AuthenticatedUser(user_id): AuthenticatedUser,
+    Extension(user_repository): Extension<UserRepository>,
+) -> Result<StatusCode, AppError> {
+    user_repository.set_active(user_id, false).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Reactivate the account of any user.
+pub async fn reactivate_account(
+    _: AuthenticatedAdmin,
+    Path(user_id): Path<Uuid>,
+    Extension(user_repository): Extension<UserRepository>,
+) -> Result<StatusCode, AppError> {
+    if user_repository.set_active(user_id, true).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+/// Schedule the deletion of the account of the authenticated user.
+///
+/// The account is deleted once the grace period is over, unless the deletion is cancelled.
+pub async fn delete_account(
+    AuthenticatedUser(user_id): AuthenticatedUser,
+    Extension(user_repository): Extension<UserRepository>,
+    Extension(email_repository): Extension<EmailRepository>,
+    Extension(email_client): Extension<Arc<EmailClient>>,
+    Extension(account_settings): Extension<AccountSettings>,
+) -> Result<StatusCode, AppError> {
+    let deletion_scheduled_at = user_repository
+        .schedule_deletion(user_id, account_settings.deletion_grace_period_days)
+        .await?;
+
+    let user = user_repository
+        .get_by_id(user_id)
+        .await?
+        .wrap_err("authenticated user not found")?;
+    let email = email_repository
+        .get_address(user.email_id)
+        .await?
+        .wrap_err("user email not found")?;
+
+    let notice = user.locale().email(
+        "email-account-deletion-scheduled",
+        &[
+            ("username", user.username.as_str().into()),
+            ("date", deletion_scheduled_at.date().to_string().into()),
+        ],
+    );
+    email_client
+        .send_email(
+            &email,
+            &notice.subject,
+            &notice.html_body,
+            &notice.text_body,
+        )
+        .await
+        .wrap_err("failed to send account deletion notice")?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Cancel the scheduled deletion of the account of the authenticated user.
+pub async fn cancel_account_deletion(
+    AuthenticatedUser(user_id): AuthenticatedUser,
+    Extension(user_repository): Extension<UserRepository>,
+) -> Result<StatusCode, AppError> {
+    if user_repository.cancel_deletion(user_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::NotFound)
+    }
+}
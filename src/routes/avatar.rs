@@ -0,0 +1,68 @@
+use axum::{extract::Multipart, http::StatusCode, Extension};
+use sqlx::PgPool;
+
+use crate::{
+    authentication::AuthenticatedUser,
+    configuration::UploadSettings,
+    erro::{AppError, ErrorMap},
+    repositories::{ImageRepository, UnitOfWork, UserRepository},
+    routes::UploadedImage,
+    storage::BlobStore,
+    validation::invalid_body,
+};
+
+/// Replace the profile picture of the authenticated user.
+///
+/// The image is sent in the `profile_pic` field of a multipart body, the only field
+/// accepted.
+/// The previous picture is deleted, unless a post also uses it.
+pub async fn update_avatar(
+    AuthenticatedUser(user_id): AuthenticatedUser,
+    Extension(db_pool): Extension<PgPool>,
+    Extension(blob_store): Extension<BlobStore>,
+    Extension(uploads): Extension<UploadSettings>,
+    Extension(user_repository): Extension<UserRepository>,
+    Extension(image_repository): Extension<ImageRepository>,
+    mut body: Multipart,
+) -> Result<StatusCode, AppError> {
+    let mut profile_pic = None;
+
+    while let Some(field) = body.next_field().await.map_err(|e| invalid_body(&e))? {
+        let field_name = field.name().unwrap_or_default().to_string();
+        match field_name.as_str() {
+            "profile_pic" => {
+                profile_pic = Some(
+                    UploadedImage::from_field(field, &blob_store, uploads.max_file_bytes).await?,
+                );
+            }
+            _ => return Err(invalid_body(&format!("unknown field `{field_name}`"))),
+        }
+    }
+
+    let profile_pic = match profile_pic {
+        Some(profile_pic) => profile_pic,
+        None => {
+            let mut errors = ErrorMap::new();
+            errors.add_error("profile_pic", "required");
+            return Err(AppError::UnprocessableEntity(errors));
+        }
+    };
+
+    let mut uow = UnitOfWork::begin(&db_pool, blob_store).await?;
+
+    let image_id = image_repository
+        .create_image(&mut uow, "Foto de perfil", profile_pic.file)
+        .await?;
+    let previous_image_id = user_repository
+        .set_profile_pic(uow.connection(), user_id, image_id)
+        .await?;
+    if let Some(previous_image_id) = previous_image_id {
+        image_repository
+            .delete_image(&mut uow, previous_image_id)
+            .await?;
+    }
+
+    uow.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
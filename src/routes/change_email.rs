@@ -0,0 +1,184 @@
+use std::sync::Arc;
+
+use axum::{extract::Query, http::StatusCode, Extension};
+use eyre::{Context, ContextCompat};
+use validator::Validate;
+
+use crate::{
+    authentication::AuthenticatedUser,
+    email_client::EmailClient,
+    erro::AppError,
+    i18n::Locale,
+    models::{EmailAddress, User},
+    repositories::{EmailOwner, EmailRepository, UserRepository},
+    startup::ApplicationBaseUrl,
+    utils::generate_token,
+    validation::{validate_email_address, ValidatedJson},
+};
+
+#[derive(serde::Deserialize, Validate)]
+pub struct ChangeEmailRequest {
+    #[validate(custom = "validate_email_address")]
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmEmailChangeParameters {
+    token: String,
+}
+
+/// Request a change of the email address of the authenticated user.
+///
+/// A confirmation link is sent to the new address and a notice to the current one,
+/// both in the locale of the user.
+/// The response is the same whether or not the new address belongs to another account.
+pub async fn change_email(
+    AuthenticatedUser(user_id): AuthenticatedUser,
+    ValidatedJson(body): ValidatedJson<ChangeEmailRequest>,
+    Extension(user_repository): Extension<UserRepository>,
+    Extension(email_repository): Extension<EmailRepository>,
+    Extension(email_client): Extension<Arc<EmailClient>>,
+    Extension(base_url): Extension<ApplicationBaseUrl>,
+) -> Result<StatusCode, AppError> {
+    let new_email = EmailAddress::parse(&body.email).wrap_err("validated email is invalid")?;
+
+    let user = user_repository
+        .get_by_id(user_id)
+        .await?
+        .wrap_err("authenticated user not found")?;
+    let old_email = email_repository
+        .get_address(user.email_id)
+        .await?
+        .wrap_err("user email not found")?;
+
+    let new_email_id = match email_repository.find_owner(&new_email).await? {
+        Some(EmailOwner {
+            user_id: Some(owner_id),
+            ..
+        }) => {
+            // Let the owner of the address know, but don't tell the requester.
+            if owner_id != user_id {
+                let locale = user_repository
+                    .get_by_id(owner_id)
+                    .await?
+                    .map_or_else(Locale::default, |owner| owner.locale());
+                send_address_in_use_notice(&email_client, new_email.as_ref(), locale).await?;
+            }
+            return Ok(StatusCode::ACCEPTED);
+        }
+        Some(EmailOwner {
+            email_id,
+            user_id: None,
+        }) => email_id,
+        None => email_repository.create_email(&new_email).await?,
+    };
+
+    let token = generate_token();
+    email_repository
+        .create_email_change(user_id, user.email_id, new_email_id, &token)
+        .await?;
+
+    send_confirmation_email(
+        &email_client,
+        new_email.as_ref(),
+        &user,
+        &base_url.0,
+        &token,
+    )
+    .await?;
+    send_change_notice(&email_client, &old_email, &user, new_email.as_ref()).await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Confirm an email change using the token sent to the new address.
+///
+/// Returns `409 Conflict` if another account took the address since the change was requested.
+pub async fn confirm_email_change(
+    Query(parameters): Query<ConfirmEmailChangeParameters>,
+    Extension(email_repository): Extension<EmailRepository>,
+) -> Result<StatusCode, AppError> {
+    match email_repository
+        .confirm_email_change(&parameters.token)
+        .await?
+    {
+        Some(_) => Ok(StatusCode::OK),
+        None => Err(AppError::InvalidToken),
+    }
+}
+
+async fn send_confirmation_email(
+    email_client: &EmailClient,
+    recipient: &str,
+    user: &User,
+    base_url: &str,
+    token: &str,
+) -> Result<(), AppError> {
+    let confirmation_link = format!("{base_url}/me/email/confirm?token={token}");
+    let email = user.locale().email(
+        "email-email-change-confirmation",
+        &[
+            ("username", user.username.as_str().into()),
+            ("link", confirmation_link.into()),
+        ],
+    );
+
+    email_client
+        .send_email(
+            recipient,
+            &email.subject,
+            &email.html_body,
+            &email.text_body,
+        )
+        .await
+        .wrap_err("failed to send email change confirmation")?;
+
+    Ok(())
+}
+
+async fn send_change_notice(
+    email_client: &EmailClient,
+    recipient: &str,
+    user: &User,
+    new_email: &str,
+) -> Result<(), AppError> {
+    let email = user.locale().email(
+        "email-email-change-notice",
+        &[
+            ("username", user.username.as_str().into()),
+            ("new_email", new_email.into()),
+        ],
+    );
+
+    email_client
+        .send_email(
+            recipient,
+            &email.subject,
+            &email.html_body,
+            &email.text_body,
+        )
+        .await
+        .wrap_err("failed to send email change notice")?;
+
+    Ok(())
+}
+
+async fn send_address_in_use_notice(
+    email_client: &EmailClient,
+    recipient: &str,
+    locale: Locale,
+) -> Result<(), AppError> {
+    let email = locale.email("email-address-in-use", &[]);
+
+    email_client
+        .send_email(
+            recipient,
+            &email.subject,
+            &email.html_body,
+            &email.text_body,
+        )
+        .await
+        .wrap_err("failed to send address in use notice")?;
+
+    Ok(())
+}
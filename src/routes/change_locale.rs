@@ -0,0 +1,25 @@
+use axum::{http::StatusCode, Extension};
+use validator::Validate;
+
+use crate::{
+    authentication::AuthenticatedUser, erro::AppError, i18n::Locale, repositories::UserRepository,
+    validation::ValidatedJson,
+};
+
+#[derive(serde::Deserialize, Validate)]
+pub struct ChangeLocaleRequest {
+    locale: Locale,
+}
+
+/// Change the language of the emails sent to the authenticated user.
+///
+/// Responses keep following the `Accept-Language` header of each request.
+pub async fn change_locale(
+    AuthenticatedUser(user_id): AuthenticatedUser,
+    ValidatedJson(body): ValidatedJson<ChangeLocaleRequest>,
+    Extension(user_repository): Extension<UserRepository>,
+) -> Result<StatusCode, AppError> {
+    user_repository.set_locale(user_id, body.locale).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
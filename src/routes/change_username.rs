@@ -0,0 +1,36 @@
+use axum::{http::StatusCode, Extension};
+use validator::Validate;
+
+use crate::{
+    authentication::AuthenticatedUser,
+    erro::AppError,
+    models::{validate_username, UsernamePolicy},
+    repositories::UserRepository,
+    validation::ValidatedJson,
+};
+
+#[derive(serde::Deserialize, Validate)]
+pub struct ChangeUsernameRequest {
+    #[validate(custom = "validate_username")]
+    username: String,
+}
+
+/// Change the username of the authenticated user.
+///
+/// The new username follows the same policy as the ones chosen at registration.
+pub async fn change_username(
+    AuthenticatedUser(user_id): AuthenticatedUser,
+    ValidatedJson(body): ValidatedJson<ChangeUsernameRequest>,
+    Extension(user_repository): Extension<UserRepository>,
+    Extension(username_policy): Extension<UsernamePolicy>,
+) -> Result<StatusCode, AppError> {
+    username_policy
+        .check(&body.username)
+        .map_err(|error| AppError::unprocessable_entity([("username", &error)]))?;
+
+    user_repository
+        .set_username(user_id, &body.username)
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
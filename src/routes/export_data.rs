@@ -0,0 +1,51 @@
+use axum::{
+    body::StreamBody,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use eyre::WrapErr;
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    authentication::AuthenticatedUser,
+    erro::AppError,
+    repositories::{DataExportRepository, DataExportStatus},
+    storage::BlobStore,
+};
+
+/// Download an archive with all the personal data of the authenticated user.
+///
+/// Archives are generated in the background, so until one is ready this returns
+/// `202 Accepted`. The user is notified by email once it can be downloaded.
+/// The archive is streamed from its file, never held in memory as a whole.
+pub async fn export_data(
+    AuthenticatedUser(user_id): AuthenticatedUser,
+    Extension(data_export_repository): Extension<DataExportRepository>,
+    Extension(blob_store): Extension<BlobStore>,
+) -> Result<Response, AppError> {
+    match data_export_repository.get_latest(user_id).await? {
+        Some(DataExportStatus::Ready { file_path }) => {
+            let archive = blob_store.open(&file_path).await?;
+            let size = archive
+                .metadata()
+                .await
+                .wrap_err("failed to read the size of the archive")?
+                .len();
+            let headers = [
+                (header::CONTENT_TYPE, "application/zip".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"chocoapi-export.zip\"".to_string(),
+                ),
+                (header::CONTENT_LENGTH, size.to_string()),
+            ];
+            Ok((headers, StreamBody::new(ReaderStream::new(archive))).into_response())
+        }
+        Some(DataExportStatus::Pending) => Ok(StatusCode::ACCEPTED.into_response()),
+        None => {
+            data_export_repository.create(user_id).await?;
+            Ok(StatusCode::ACCEPTED.into_response())
+        }
+    }
+}
@@ -1,6 +1,196 @@
-use axum::http::StatusCode;
+use std::{collections::BTreeMap, future::Future, time::Duration};
 
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{redis_connection::RedisConnection, storage::BlobStore};
+
+/// Report whether the API takes requests.
+///
+/// Once it's shutting down it answers `503 Service Unavailable` with `draining`, so load
+/// balancers stop routing requests to it before it stops accepting connections.
+#[allow(clippy::unused_async)]
+pub async fn health_check(Extension(shutdown): Extension<CancellationToken>) -> Response {
+    if shutdown.is_cancelled() {
+        (StatusCode::SERVICE_UNAVAILABLE, "draining").into_response()
+    } else {
+        StatusCode::OK.into_response()
+    }
+}
+
+/// Report that the process is alive, without checking anything else.
+///
+/// Orchestrators restart the process when it stops answering.
 #[allow(clippy::unused_async)]
-pub async fn health_check() -> StatusCode {
+pub async fn liveness() -> StatusCode {
     StatusCode::OK
 }
+
+/// The dependencies checked by `readiness`.
+#[derive(Clone)]
+pub struct ReadinessChecks {
+    pub db_pool: sqlx::PgPool,
+    pub blob_store: BlobStore,
+    /// The connection to Redis and whether it's required.
+    pub redis: Option<(RedisConnection, bool)>,
+    pub timeout: Duration,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ReadinessStatus {
+    Ready,
+    NotReady,
+    Draining,
+}
+
+#[derive(serde::Serialize)]
+pub struct Readiness {
+    status: ReadinessStatus,
+    /// The version of the last migration applied to the database.
+    migration_version: Option<i64>,
+    checks: BTreeMap<&'static str, DependencyStatus>,
+}
+
+#[derive(serde::Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum DependencyState {
+    Up,
+    Down,
+}
+
+#[derive(serde::Serialize)]
+struct DependencyStatus {
+    status: DependencyState,
+    required: bool,
+    latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+/// Report whether the API can serve requests, checking each of its dependencies.
+///
+/// It answers `503 Service Unavailable` if a required dependency is down or doesn't answer
+/// in time, or if the API is shutting down. Failures are logged, the response only says
+/// whether the dependency failed or timed out.
+pub async fn readiness(
+    Extension(checks): Extension<ReadinessChecks>,
+    Extension(shutdown): Extension<CancellationToken>,
+) -> (StatusCode, Json<Readiness>) {
+    let timeout = checks.timeout;
+    let ((postgres, migration_version), (blob_store, _), redis) = tokio::join!(
+        check("postgres", true, timeout, latest_migration(&checks.db_pool)),
+        check(
+            "blob_store",
+            true,
+            timeout,
+            probe_blob_store(&checks.blob_store)
+        ),
+        async {
+            match &checks.redis {
+                Some((connection, required)) => Some(
+                    check("redis", *required, timeout, ping_redis(connection))
+                        .await
+                        .0,
+                ),
+                None => None,
+            }
+        }
+    );
+
+    let mut dependencies = BTreeMap::from([("postgres", postgres), ("blob_store", blob_store)]);
+    if let Some(redis) = redis {
+        dependencies.insert("redis", redis);
+    }
+
+    let status = if shutdown.is_cancelled() {
+        ReadinessStatus::Draining
+    } else if dependencies
+        .values()
+        .any(|check| check.required && check.status == DependencyState::Down)
+    {
+        ReadinessStatus::NotReady
+    } else {
+        ReadinessStatus::Ready
+    };
+    let status_code = match status {
+        ReadinessStatus::Ready => StatusCode::OK,
+        _ => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (
+        status_code,
+        Json(Readiness {
+            status,
+            migration_version: migration_version.flatten(),
+            checks: dependencies,
+        }),
+    )
+}
+
+/// Run a single check, bounded by `timeout`, returning its status and result.
+async fn check<T>(
+    name: &'static str,
+    required: bool,
+    timeout: Duration,
+    check: impl Future<Output = eyre::Result<T>>,
+) -> (DependencyStatus, Option<T>) {
+    let start = Instant::now();
+    let outcome = tokio::time::timeout(timeout, check).await;
+    let latency_ms = start.elapsed().as_millis();
+
+    let (error, value) = match outcome {
+        Ok(Ok(value)) => (None, Some(value)),
+        Ok(Err(error)) => {
+            tracing::warn!(?error, dependency = name, "readiness check failed");
+            (Some("failed"), None)
+        }
+        Err(_) => {
+            tracing::warn!(?timeout, dependency = name, "readiness check timed out");
+            (Some("timed_out"), None)
+        }
+    };
+
+    let status = DependencyStatus {
+        status: if error.is_none() {
+            DependencyState::Up
+        } else {
+            DependencyState::Down
+        },
+        required,
+        latency_ms,
+        error,
+    };
+    (status, value)
+}
+
+async fn latest_migration(pool: &sqlx::PgPool) -> eyre::Result<Option<i64>> {
+    let version = sqlx::query_scalar!("SELECT max(version) FROM _sqlx_migrations WHERE success")
+        .fetch_one(pool)
+        .await?;
+    Ok(version)
+}
+
+/// Check that files can be written to the store, and deleted.
+///
+/// A probe interrupted by its timeout may leave its `.ready-*` file behind, which
+/// `images gc` deletes like any other unreferenced file once it's older than `--min-age-seconds`.
+async fn probe_blob_store(blob_store: &BlobStore) -> eyre::Result<()> {
+    let key = format!(".ready-{}", Uuid::new_v4());
+    blob_store.put(&key, b"ok").await?;
+    blob_store.delete(&key).await
+}
+
+async fn ping_redis(connection: &RedisConnection) -> eyre::Result<()> {
+    let mut connection = connection.get().await?;
+    redis::cmd("PING")
+        .query_async::<_, String>(&mut connection)
+        .await?;
+    Ok(())
+}
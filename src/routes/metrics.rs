@@ -0,0 +1,20 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use sqlx::PgPool;
+
+use crate::metrics::METRICS;
+
+/// Expose the metrics in the Prometheus text format.
+#[allow(clippy::unused_async)]
+pub async fn metrics(Extension(db_pool): Extension<PgPool>) -> Response {
+    match METRICS.render(&db_pool) {
+        Ok(body) => ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response(),
+        Err(error) => {
+            tracing::error!(?error, "failed to render metrics");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -1,5 +1,25 @@
+mod account;
+mod avatar;
+mod change_email;
+mod change_locale;
+mod change_username;
+mod export_data;
 mod health_check;
+mod metrics;
+mod newsletter;
+mod problems;
 mod register;
+mod upload;
 
+pub(crate) use account::*;
+pub(crate) use avatar::*;
+pub(crate) use change_email::*;
+pub(crate) use change_locale::*;
+pub(crate) use change_username::*;
+pub(crate) use export_data::*;
 pub(crate) use health_check::*;
+pub(crate) use metrics::*;
+pub(crate) use newsletter::*;
+pub(crate) use problems::*;
 pub(crate) use register::*;
+pub(crate) use upload::*;
@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use axum::{extract::Query, http::StatusCode, response::Html, Extension};
+use eyre::{Context, ContextCompat};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    authentication::AuthenticatedAdmin,
+    configuration::NewsletterSettings,
+    email_client::EmailClient,
+    erro::AppError,
+    i18n::Locale,
+    models::EmailAddress,
+    newsletter::UnsubscribeLinks,
+    repositories::{EmailRepository, NewsletterRepository},
+    startup::ApplicationBaseUrl,
+    utils::generate_token,
+    validation::{validate_email_address, validate_not_blank, ValidatedJson},
+};
+
+#[derive(serde::Deserialize, Validate)]
+pub struct SubscribeRequest {
+    #[validate(custom = "validate_email_address")]
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmSubscriptionParameters {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    email_id: Uuid,
+    signature: String,
+}
+
+#[derive(serde::Deserialize, Validate)]
+pub struct PublishNewsletterRequest {
+    #[validate(custom = "validate_not_blank")]
+    title: String,
+    #[validate(custom = "validate_not_blank")]
+    html_content: String,
+    #[validate(custom = "validate_not_blank")]
+    text_content: String,
+}
+
+/// Subscribe an email address to the newsletter.
+///
+/// A confirmation link is sent to the address (double opt-in), unless it's already subscribed
+/// or one was sent recently, in the language negotiated from the `Accept-Language` header.
+/// The response is the same either way, so it can't be used to find out who is subscribed.
+pub async fn subscribe(
+    ValidatedJson(body): ValidatedJson<SubscribeRequest>,
+    Extension(email_repository): Extension<EmailRepository>,
+    Extension(newsletter_repository): Extension<NewsletterRepository>,
+    Extension(newsletter_settings): Extension<NewsletterSettings>,
+    Extension(email_client): Extension<Arc<EmailClient>>,
+    Extension(base_url): Extension<ApplicationBaseUrl>,
+    locale: Locale,
+) -> Result<StatusCode, AppError> {
+    let email = EmailAddress::parse(&body.email).wrap_err("validated email is invalid")?;
+
+    let email_id = match email_repository.find_owner(&email).await? {
+        Some(owner) => owner.email_id,
+        None => email_repository.create_email(&email).await?,
+    };
+
+    if newsletter_repository.is_subscribed(email_id).await? {
+        return Ok(StatusCode::ACCEPTED);
+    }
+
+    let token = generate_token();
+    let created = newsletter_repository
+        .create_subscription_token(
+            email_id,
+            &token,
+            newsletter_settings.confirmation_cooldown(),
+        )
+        .await?;
+    if !created {
+        return Ok(StatusCode::ACCEPTED);
+    }
+
+    let confirmation_link = format!("{}/newsletter/confirm?token={token}", base_url.0);
+    let confirmation = locale.email(
+        "email-subscription-confirmation",
+        &[("link", confirmation_link.into())],
+    );
+    email_client
+        .send_email(
+            email.as_ref(),
+            &confirmation.subject,
+            &confirmation.html_body,
+            &confirmation.text_body,
+        )
+        .await
+        .wrap_err("failed to send subscription confirmation")?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Confirm a subscription using the token sent to the subscribed address.
+pub async fn confirm_subscription(
+    Query(parameters): Query<ConfirmSubscriptionParameters>,
+    Extension(newsletter_repository): Extension<NewsletterRepository>,
+) -> Result<StatusCode, AppError> {
+    match newsletter_repository
+        .confirm_subscription(&parameters.token)
+        .await?
+    {
+        Some(_) => Ok(StatusCode::OK),
+        None => Err(AppError::InvalidToken),
+    }
+}
+
+/// Show a form to confirm the unsubscription.
+///
+/// Visiting the link doesn't unsubscribe by itself, since link scanners of email
+/// providers would unsubscribe everyone.
+pub async fn unsubscribe_form(
+    Query(parameters): Query<UnsubscribeParameters>,
+    Extension(unsubscribe_links): Extension<UnsubscribeLinks>,
+    locale: Locale,
+) -> Result<Html<String>, AppError> {
+    if !unsubscribe_links.verify(parameters.email_id, &parameters.signature) {
+        return Err(AppError::InvalidToken);
+    }
+
+    let action = unsubscribe_links.link(parameters.email_id);
+    Ok(Html(format!(
+        "<!DOCTYPE html>\
+        <html lang=\"{lang}\">\
+        <head><meta charset=\"utf-8\"><title>{unsubscribe}</title></head>\
+        <body>\
+        <form method=\"post\" action=\"{action}\">\
+        <p>{question}</p>\
+        <button type=\"submit\">{unsubscribe}</button>\
+        </form>\
+        </body>\
+        </html>",
+        lang = locale.as_str(),
+        unsubscribe = locale.format("newsletter-unsubscribe", &[]),
+        question = locale.format("newsletter-unsubscribe-question", &[]),
+    )))
+}
+
+/// Unsubscribe an email address from the newsletter using a signed link.
+///
+/// This is also the target of one-click unsubscriptions (RFC 8058).
+pub async fn unsubscribe(
+    Query(parameters): Query<UnsubscribeParameters>,
+    Extension(unsubscribe_links): Extension<UnsubscribeLinks>,
+    Extension(newsletter_repository): Extension<NewsletterRepository>,
+) -> Result<StatusCode, AppError> {
+    if !unsubscribe_links.verify(parameters.email_id, &parameters.signature) {
+        return Err(AppError::InvalidToken);
+    }
+
+    if newsletter_repository
+        .unsubscribe(parameters.email_id)
+        .await?
+    {
+        Ok(StatusCode::OK)
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+/// Publish a newsletter issue.
+///
+/// The issue is delivered in the background to every confirmed and subscribed address.
+pub async fn publish_newsletter(
+    AuthenticatedAdmin(author_id): AuthenticatedAdmin,
+    ValidatedJson(body): ValidatedJson<PublishNewsletterRequest>,
+    Extension(newsletter_repository): Extension<NewsletterRepository>,
+) -> Result<StatusCode, AppError> {
+    newsletter_repository
+        .create_issue(
+            author_id,
+            &body.title,
+            &body.html_content,
+            &body.text_content,
+        )
+        .await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
@@ -0,0 +1,86 @@
+use axum::{
+    extract::Path,
+    http::{header, HeaderValue},
+    response::{Html, IntoResponse, Response},
+};
+
+use crate::{
+    erro::AppError,
+    i18n::Locale,
+    problems::{ProblemType, PROBLEMS_PATH},
+};
+
+/// List the problem types the API can report.
+pub async fn list_problem_types(locale: Locale) -> Response {
+    let items: String = ProblemType::ALL
+        .into_iter()
+        .map(|problem_type| {
+            format!(
+                "<li><a href=\"{}\"><code>{}</code></a>: {}</li>",
+                problem_type.path(),
+                problem_type.code(),
+                problem_type.title(locale)
+            )
+        })
+        .collect();
+
+    localized_html(
+        locale,
+        format!(
+            "<!DOCTYPE html>\
+            <html lang=\"{lang}\">\
+            <head><meta charset=\"utf-8\"><title>{title}</title></head>\
+            <body>\
+            <h1>{title}</h1>\
+            <p>{intro}</p>\
+            <ul>{items}</ul>\
+            </body>\
+            </html>",
+            lang = locale.as_str(),
+            title = locale.format("problems-index-title", &[]),
+            intro = locale.format("problems-index-intro", &[]),
+        ),
+    )
+}
+
+/// Document a single problem type, the target of the `type` URI of error responses.
+pub async fn describe_problem_type(
+    Path(code): Path<String>,
+    locale: Locale,
+) -> Result<Response, AppError> {
+    let problem_type =
+        ProblemType::from_code(code.trim_start_matches('/')).ok_or(AppError::NotFound)?;
+
+    Ok(localized_html(
+        locale,
+        format!(
+            "<!DOCTYPE html>\
+            <html lang=\"{lang}\">\
+            <head><meta charset=\"utf-8\"><title>{title}</title></head>\
+            <body>\
+            <h1>{title}</h1>\
+            <p><code>{code}</code> · HTTP {status}</p>\
+            <p>{description}</p>\
+            <p><a href=\"{PROBLEMS_PATH}\">{link}</a></p>\
+            </body>\
+            </html>",
+            lang = locale.as_str(),
+            title = problem_type.title(locale),
+            code = problem_type.code(),
+            status = problem_type.status(),
+            description = problem_type.description(locale),
+            link = locale.format("problems-index-link", &[]),
+        ),
+    ))
+}
+
+fn localized_html(locale: Locale, html: String) -> Response {
+    (
+        [(
+            header::CONTENT_LANGUAGE,
+            HeaderValue::from_static(locale.as_str()),
+        )],
+        Html(html),
+    )
+        .into_response()
+}
@@ -1,36 +1,128 @@
 use axum::{
-    extract::{Json, Multipart},
-    http::StatusCode,
-    Extension,
+    async_trait,
+    body::{Bytes, HttpBody},
+    extract::{FromRequest, Json, Multipart, RequestParts},
+    http::{header, StatusCode},
+    BoxError, Extension,
 };
 use eyre::{Context, ContextCompat};
-use tracing::warn;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use validator::{Validate, ValidationError};
 
 use crate::{
+    authentication::{compute_password_hash, Password, PasswordPolicy},
+    configuration::UploadSettings,
     erro::{AppError, ErrorMap},
-    models::{InsertableUserBuilder, User},
-    repositories::{EmailRepository, ImageRepository, UserRepository},
+    i18n::Locale,
+    metrics::METRICS,
+    models::{validate_username, EmailAddress, InsertableUserBuilder, User, UsernamePolicy},
+    repositories::{ImageRepository, UnitOfWork, UserRepository},
+    routes::UploadedImage,
+    storage::BlobStore,
+    telemetry::spawn_blocking_with_tracing,
+    validation::{invalid_body, validate_email_address, ValidatedForm, ValidatedJson},
 };
 
-/// Register a user.
-pub async fn register(
-    mut body: Multipart,
-    Extension(user_repository): Extension<UserRepository>,
-    Extension(email_repository): Extension<EmailRepository>,
-    Extension(image_repository): Extension<ImageRepository>,
-) -> Result<(StatusCode, Json<User>), AppError> {
-    let mut builder = InsertableUserBuilder::new();
-    let mut errors = ErrorMap::<String, String>::new();
-
-    while let Some(field) = body
-        .next_field()
-        .await
-        .wrap_err("failed to parse multipart form data")?
-    {
-        if let Some(field_name) = field.name() {
-            match field_name {
+/// The data needed to register a user.
+///
+/// The limits are the ones of the `users` table. Unknown fields are rejected, whatever
+/// the format of the body.
+#[derive(Default, serde::Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterRequest {
+    #[validate(required, custom = "validate_username")]
+    username: Option<String>,
+    #[validate(required, custom = "validate_password")]
+    password: Option<Password>,
+    #[validate(required, custom = "validate_email_address")]
+    email: Option<String>,
+    #[validate(length(max = 127))]
+    full_name: Option<String>,
+}
+
+fn validate_password(password: &Password) -> Result<(), ValidationError> {
+    if password.expose_secret().is_empty() {
+        return Err(ValidationError::new("required"));
+    }
+    Ok(())
+}
+
+/// The validated body of a registration request, either JSON, url-encoded or multipart.
+///
+/// Only multipart bodies can include a profile picture, in the `profile_pic` field.
+/// Otherwise it can be uploaded afterwards through `PUT /me/avatar`.
+pub struct RegisterBody {
+    request: RegisterRequest,
+    profile_pic: Option<UploadedImage>,
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for RegisterBody
+where
+    B: HttpBody<Data = Bytes> + Default + Unpin + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|mime| mime.trim().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        match content_type.as_str() {
+            "application/json" => {
+                let ValidatedJson(request) = ValidatedJson::from_request(req).await?;
+                Ok(Self {
+                    request,
+                    profile_pic: None,
+                })
+            }
+            "application/x-www-form-urlencoded" => {
+                let ValidatedForm(request) = ValidatedForm::from_request(req).await?;
+                Ok(Self {
+                    request,
+                    profile_pic: None,
+                })
+            }
+            "multipart/form-data" => {
+                let blob_store = req
+                    .extensions()
+                    .get::<BlobStore>()
+                    .cloned()
+                    .wrap_err("missing blob store")?;
+                let uploads = *req
+                    .extensions()
+                    .get::<UploadSettings>()
+                    .wrap_err("missing upload settings")?;
+                let multipart = Multipart::from_request(req)
+                    .await
+                    .map_err(|e| invalid_body(&e))?;
+                Self::from_multipart(multipart, &blob_store, uploads).await
+            }
+            _ => Err(AppError::UnsupportedMediaType),
+        }
+    }
+}
+
+impl RegisterBody {
+    async fn from_multipart(
+        mut body: Multipart,
+        blob_store: &BlobStore,
+        uploads: UploadSettings,
+    ) -> Result<Self, AppError> {
+        let mut request = RegisterRequest::default();
+        let mut profile_pic = None;
+
+        while let Some(field) = body.next_field().await.map_err(|e| invalid_body(&e))? {
+            let field_name = field.name().unwrap_or_default().to_string();
+            match field_name.as_str() {
                 "username" => {
-                    builder = builder.with_username(
+                    request.username = Some(
                         field
                             .text()
                             .await
@@ -38,15 +130,15 @@ pub async fn register(
                     );
                 }
                 "password" => {
-                    builder = builder.with_password(
+                    request.password = Some(Password::new(
                         field
                             .text()
                             .await
                             .wrap_err("failed to parse form password")?,
-                    );
+                    ));
                 }
                 "full_name" => {
-                    builder = builder.with_full_name(
+                    request.full_name = Some(
                         field
                             .text()
                             .await
@@ -54,38 +146,101 @@ pub async fn register(
                     );
                 }
                 "email" => {
-                    let email = field.text().await.wrap_err("failed to parse form email")?;
-                    builder = builder.with_email_id(email_repository.create_email(email).await?);
+                    request.email =
+                        Some(field.text().await.wrap_err("failed to parse form email")?);
                 }
                 "profile_pic" => {
-                    let mime_type = field
-                        .content_type()
-                        .wrap_err("failed to fetch image content type")?
-                        .to_string();
-                    let image = field.bytes().await.wrap_err("failed to parse form image")?;
-                    builder = builder.with_profile_pic_id(
-                        image_repository
-                            .create_image(&mime_type, image.to_vec())
+                    profile_pic = Some(
+                        UploadedImage::from_field(field, blob_store, uploads.max_file_bytes)
                             .await?,
                     );
                 }
-                _ => {
-                    errors.add_error(field_name.to_string(), "Invalid field".to_string());
-                    warn!("invalid field name in registration form: {}", field_name);
-                }
+                _ => return Err(invalid_body(&format!("unknown field `{field_name}`"))),
             }
         }
+
+        request.validate()?;
+
+        Ok(Self {
+            request,
+            profile_pic,
+        })
+    }
+}
+
+/// Register a user.
+///
+/// The user, its email address and its profile picture are created in a single transaction,
+/// so nothing is left behind if any of them fails. The emails sent to the user are in the
+/// language negotiated from the `Accept-Language` header, until it's changed.
+#[allow(clippy::too_many_arguments)]
+pub async fn register(
+    Extension(db_pool): Extension<PgPool>,
+    Extension(blob_store): Extension<BlobStore>,
+    Extension(user_repository): Extension<UserRepository>,
+    Extension(image_repository): Extension<ImageRepository>,
+    Extension(username_policy): Extension<UsernamePolicy>,
+    Extension(password_policy): Extension<PasswordPolicy>,
+    locale: Locale,
+    RegisterBody {
+        request,
+        profile_pic,
+    }: RegisterBody,
+) -> Result<(StatusCode, Json<User>), AppError> {
+    let mut builder = InsertableUserBuilder::new().with_locale(locale);
+    if let Some(username) = &request.username {
+        username_policy
+            .check(username)
+            .map_err(|error| AppError::unprocessable_entity([("username", &error)]))?;
+        builder = builder.with_username(username.clone());
+    }
+    if let Some(full_name) = &request.full_name {
+        builder = builder.with_full_name(full_name.clone());
+    }
+    if let Some(email) = request.email.as_deref().and_then(EmailAddress::parse) {
+        builder = builder.with_email(email);
+    }
+    if let Some(password) = request.password {
+        let password = password.into_secret();
+        let user_inputs = [&request.username, &request.email, &request.full_name]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        password_policy.check(&password, &user_inputs).await?;
+
+        let passwd_hash = spawn_blocking_with_tracing(move || compute_password_hash(&password))
+            .await
+            .wrap_err("failed to spawn blocking task")??;
+        builder = builder.with_password_hash(passwd_hash.expose_secret().to_string());
     }
 
     // TODO: insert permissions for new user
     // TODO: send confirmation email
 
+    // Dropping `uow` on an early return rolls everything back.
+    let mut uow = UnitOfWork::begin(&db_pool, blob_store).await?;
+
+    if let Some(profile_pic) = profile_pic {
+        builder = builder.with_profile_pic_id(
+            image_repository
+                .create_image(&mut uow, "Foto de perfil", profile_pic.file)
+                .await?,
+        );
+    }
+
     match builder.build() {
         Ok(insertable_user) => {
-            let user = user_repository.create_user(insertable_user).await?;
+            let user = user_repository
+                .create_user(uow.connection(), insertable_user)
+                .await?;
+            uow.commit().await?;
+            METRICS.registrations.inc();
             Ok((StatusCode::CREATED, Json(user)))
         }
         Err(errs) => {
+            uow.rollback().await?;
+            let mut errors = ErrorMap::new();
             errors.merge(errs);
             Err(AppError::UnprocessableEntity(errors))
         }
@@ -0,0 +1,37 @@
+use axum::extract::multipart::Field;
+use eyre::Context;
+
+use crate::{
+    erro::AppError,
+    storage::{BlobStore, StagedFile},
+};
+
+/// An image uploaded in a multipart field, written to the staging area of the blob store.
+///
+/// The content type sent by the client is ignored: the one stored is detected from the
+/// content of the file once it's decoded.
+pub struct UploadedImage {
+    pub file: StagedFile,
+}
+
+impl UploadedImage {
+    /// Stream the image in `field` to the staging area of `blob_store` chunk by chunk,
+    /// so it's never held in memory as a whole.
+    ///
+    /// Returns `413 Payload Too Large` as soon as it goes over `max_bytes`.
+    pub async fn from_field(
+        mut field: Field<'_>,
+        blob_store: &BlobStore,
+        max_bytes: u64,
+    ) -> Result<Self, AppError> {
+        let mut file = blob_store.stage().await?;
+        while let Some(chunk) = field.chunk().await.wrap_err("failed to read form image")? {
+            if file.size() + chunk.len() as u64 > max_bytes {
+                return Err(AppError::PayloadTooLarge);
+            }
+            file.write(&chunk).await?;
+        }
+
+        Ok(Self { file })
+    }
+}
@@ -0,0 +1,110 @@
+use axum::{
+    http::{
+        header::{self, HeaderName},
+        HeaderValue, Method, Request,
+    },
+    middleware::Next,
+    response::Response,
+};
+use eyre::{Result, WrapErr};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::configuration::{CorsSettings, SecurityHeadersSettings};
+
+/// The policy of the HTML pages served by the API, e.g. the unsubscribe form: nothing is
+/// loaded from anywhere, and forms may only be sent back to the API.
+const CONTENT_SECURITY_POLICY: &str =
+    "default-src 'none'; form-action 'self'; frame-ancestors 'none'; base-uri 'none'";
+
+/// Let the front-ends in `settings` call the API from a browser.
+///
+/// Requests from other origins are still served, browsers just don't let their scripts
+/// read the responses.
+pub fn cors_layer(settings: &CorsSettings) -> Result<CorsLayer> {
+    let origins = settings
+        .allowed_origins
+        .iter()
+        .map(|origin| HeaderValue::from_str(origin))
+        .collect::<Result<Vec<_>, _>>()
+        .wrap_err("invalid CORS origin")?;
+
+    Ok(CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_credentials(settings.allow_credentials)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([
+            header::ACCEPT,
+            header::ACCEPT_LANGUAGE,
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            HeaderName::from_static("x-request-id"),
+        ])
+        .expose_headers([
+            header::CONTENT_LANGUAGE,
+            header::RETRY_AFTER,
+            HeaderName::from_static("x-request-id"),
+        ])
+        .max_age(settings.max_age()))
+}
+
+/// The headers [`add_security_headers`] adds to every response.
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    /// `Strict-Transport-Security`, only sent when the API is served over HTTPS.
+    hsts: Option<HeaderValue>,
+}
+
+impl SecurityHeaders {
+    pub fn new(settings: &SecurityHeadersSettings, base_url: &str) -> Result<Self> {
+        let hsts = base_url
+            .starts_with("https://")
+            .then(|| {
+                HeaderValue::from_str(&format!(
+                    "max-age={}; includeSubDomains",
+                    settings.hsts_max_age_seconds
+                ))
+                .wrap_err("invalid Strict-Transport-Security header")
+            })
+            .transpose()?;
+        Ok(Self { hsts })
+    }
+}
+
+/// Add the security headers to the response, and a `Content-Security-Policy` to HTML pages.
+///
+/// Headers already set by the handler are kept.
+pub async fn add_security_headers<B>(
+    security_headers: SecurityHeaders,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut response = next.run(request).await;
+
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with("text/html"));
+    let headers = response.headers_mut();
+    headers
+        .entry(header::X_CONTENT_TYPE_OPTIONS)
+        .or_insert(HeaderValue::from_static("nosniff"));
+    headers
+        .entry(header::X_FRAME_OPTIONS)
+        .or_insert(HeaderValue::from_static("DENY"));
+    headers
+        .entry(header::REFERRER_POLICY)
+        .or_insert(HeaderValue::from_static("no-referrer"));
+    if let Some(hsts) = security_headers.hsts {
+        headers
+            .entry(header::STRICT_TRANSPORT_SECURITY)
+            .or_insert(hsts);
+    }
+    if is_html {
+        headers
+            .entry(header::CONTENT_SECURITY_POLICY)
+            .or_insert(HeaderValue::from_static(CONTENT_SECURITY_POLICY));
+    }
+
+    response
+}
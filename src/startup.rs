@@ -1,97 +1,345 @@
 use crate::{
+    authentication::PasswordPolicy,
+    body_limit::{limit_request_body, BodyLimits},
+    client_ip::{resolve_client_ip, TrustedProxies},
     configuration::{DatabaseSettings, Settings},
-    repositories::{EmailRepository, ImageRepository, UserRepository},
-    routes::{health_check, register},
+    erro::handle_panic,
+    metrics::{track_requests, METRICS},
+    models::UsernamePolicy,
+    newsletter::UnsubscribeLinks,
+    problems::{complete_problem_details, PROBLEMS_PATH},
+    rate_limit::{limit_signups, RateLimiter},
+    redis_connection::RedisConnection,
+    repositories::{
+        DataExportRepository, EmailRepository, ImageRepository, NewsletterRepository,
+        UserRepository,
+    },
+    routes::{
+        cancel_account_deletion, change_email, change_locale, change_username,
+        confirm_email_change, confirm_subscription, deactivate_account, delete_account,
+        describe_problem_type, export_data, health_check, list_problem_types, liveness, metrics,
+        publish_newsletter, reactivate_account, readiness, register, subscribe, unsubscribe,
+        unsubscribe_form, update_avatar, ReadinessChecks,
+    },
+    security::{add_security_headers, cors_layer, SecurityHeaders},
+    storage::BlobStore,
+    telemetry::{log_request_headers, make_request_span, record_route},
+    utils::backoff_with_jitter,
 };
 use axum::{
-    routing::{get, post, IntoMakeService},
+    extract::connect_info::IntoMakeServiceWithConnectInfo,
+    http::header,
+    middleware,
+    routing::{delete, get, post, put, IntoMakeService},
     Extension, Router, Server,
 };
 use eyre::{Result, WrapErr};
 use hyper::server::conn::AddrIncoming;
-use sqlx::{postgres::PgPoolOptions, PgPool};
-use std::net::SocketAddr;
-use tower_http::trace::TraceLayer;
+use sqlx::{migrate::Migrator, postgres::PgPoolOptions, PgPool};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tower_http::{
+    catch_panic::CatchPanicLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    sensitive_headers::SetSensitiveRequestHeadersLayer,
+    trace::TraceLayer,
+};
+
+/// The migrations in `migrations/`, embedded in the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
 
 pub struct Application {
     local_address: SocketAddr,
-    server: Server<AddrIncoming, IntoMakeService<Router>>,
+    server: Server<AddrIncoming, IntoMakeServiceWithConnectInfo<Router, SocketAddr>>,
+    /// The server of `/metrics`, when it has its own port.
+    metrics_server: Option<Server<AddrIncoming, IntoMakeService<Router>>>,
+    shutdown: CancellationToken,
+    drain_delay: Duration,
 }
 
 impl Application {
-    pub async fn build(configuration: Settings) -> Result<Self> {
-        let connection_pool = get_connection_pool(&configuration.database).await?;
-        sqlx::migrate!("./migrations")
-            .run(&connection_pool)
-            .await
-            .expect("Failed to migrate the database");
+    /// Build the API on the `connection_pool` it shares with the workers.
+    pub async fn build(configuration: Settings, connection_pool: PgPool) -> Result<Self> {
+        if configuration.database.migrate_on_startup {
+            MIGRATOR
+                .run(&connection_pool)
+                .await
+                .wrap_err("failed to migrate the database")?;
+        }
 
         let address = SocketAddr::from((
             configuration.application.host,
             configuration.application.port,
         ));
 
-        let app = app(connection_pool);
+        METRICS
+            .db_pool_max_connections
+            .set(configuration.database.pool.max_connections.into());
+        let metrics_server = match configuration.metrics.port {
+            Some(port) if configuration.metrics.enabled => {
+                let address = SocketAddr::from((configuration.application.host, port));
+                let app = metrics_app(connection_pool.clone());
+                Some(axum::Server::bind(&address).serve(app.into_make_service()))
+            }
+            _ => None,
+        };
 
-        let server = axum::Server::bind(&address).serve(app.into_make_service());
+        let shutdown = CancellationToken::new();
+        let drain_delay = configuration.shutdown.drain_delay();
+        let redis = configuration
+            .redis
+            .as_ref()
+            .map(RedisConnection::new)
+            .transpose()?;
+        let rate_limiter =
+            RateLimiter::new(configuration.rate_limit.clone(), redis.as_ref()).await?;
+        let app = app(
+            connection_pool,
+            shutdown.clone(),
+            rate_limiter,
+            redis,
+            configuration,
+        )?;
+
+        // The address of the client is recorded in the span of each request
+        let server = axum::Server::bind(&address)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>());
 
         let local_address = server.local_addr();
 
         Ok(Application {
             local_address,
             server,
+            metrics_server,
+            shutdown,
+            drain_delay,
         })
     }
 
+    /// Serve requests until the shutdown token is cancelled.
+    ///
+    /// The health check reports the API is draining during the drain delay, then no more
+    /// connections are accepted and this returns once the requests in flight are done.
     pub async fn run_until_stopped(self) -> Result<()> {
-        self.server.await.wrap_err("error running HTTP server")
+        let drained = |shutdown: CancellationToken, drain_delay: Duration| async move {
+            shutdown.cancelled().await;
+            tracing::info!(?drain_delay, "draining HTTP server");
+            tokio::time::sleep(drain_delay).await;
+        };
+
+        let api = self
+            .server
+            .with_graceful_shutdown(drained(self.shutdown.clone(), self.drain_delay));
+        let metrics = async {
+            match self.metrics_server {
+                Some(server) => {
+                    server
+                        .with_graceful_shutdown(drained(self.shutdown.clone(), self.drain_delay))
+                        .await
+                }
+                None => Ok(()),
+            }
+        };
+        tokio::try_join!(api, metrics).wrap_err("error running HTTP server")?;
+        Ok(())
     }
 
     pub fn local_address(&self) -> SocketAddr {
         self.local_address
     }
+
+    /// The address `/metrics` is served at, when it has its own port.
+    pub fn metrics_address(&self) -> Option<SocketAddr> {
+        self.metrics_server.as_ref().map(Server::local_addr)
+    }
+
+    /// The token that shuts the API down once cancelled, to be shared with the workers.
+    #[must_use]
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
 }
 
-pub async fn get_connection_pool(configuration: &DatabaseSettings) -> Result<PgPool> {
-    // TODO: Add max_retries to the configuration file.
-    const MAX_RETRIES: i32 = 5;
+/// Wait for `SIGINT` (Ctrl+C) or, on Unix, `SIGTERM`, e.g. from `docker compose stop`.
+pub async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
 
-    let mut retries = 0;
+        let mut terminate =
+            signal(SignalKind::terminate()).wrap_err("failed to listen for SIGTERM")?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.wrap_err("failed to listen for SIGINT"),
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c()
+            .await
+            .wrap_err("failed to listen for Ctrl+C")
+    }
+}
+
+/// Connect to the database, retrying with an exponential backoff while it isn't reachable.
+pub async fn get_connection_pool(configuration: &DatabaseSettings) -> Result<PgPool> {
+    let pool = &configuration.pool;
+    let mut attempt = 0;
 
     loop {
         match PgPoolOptions::new()
-            .acquire_timeout(std::time::Duration::from_secs(2))
-            // The default connection limit for a Postgres server is 100 connections, minus 3 for superusers.
-            // We should leave some connections available for manual access.
-            //
-            // NOTE: If you're deploying your application with multiple replicas, then the total
-            // across all replicas should not exceed the Postgres connection limit.
-            .max_connections(50)
+            .max_connections(pool.max_connections)
+            .min_connections(pool.min_connections)
+            .acquire_timeout(pool.acquire_timeout())
+            .idle_timeout(pool.idle_timeout())
+            .max_lifetime(pool.max_lifetime())
             .connect_with(configuration.with_db())
             .await
             .wrap_err("error starting Postgres db")
         {
             Ok(pool) => break Ok(pool),
-            Err(e) => {
-                if retries < MAX_RETRIES {
-                    tracing::warn!("retrying db connection");
-                    retries += 1;
-                } else {
-                    break Err(e);
-                }
+            Err(error) if attempt < pool.connect_retries => {
+                let delay = backoff_with_jitter(
+                    attempt,
+                    pool.retry_initial_delay(),
+                    pool.retry_max_delay(),
+                );
+                attempt += 1;
+                tracing::warn!(?error, attempt, ?delay, "retrying db connection");
+                tokio::time::sleep(delay).await;
             }
+            Err(error) => break Err(error),
         }
     }
 }
 
-// TODO: only `merge` here and delegate to routes folder
-#[must_use]
-fn app(db_pool: PgPool) -> Router {
+/// Serve only `/metrics`, on its own port.
+fn metrics_app(db_pool: PgPool) -> Router {
     Router::new()
+        .route("/metrics", get(metrics))
+        .layer(Extension(db_pool))
+        .layer(TraceLayer::new_for_http())
+}
+
+/// The public URL of the application, used to build links sent to users.
+#[derive(Clone, Debug)]
+pub struct ApplicationBaseUrl(pub String);
+
+// TODO: only `merge` here and delegate to routes folder
+fn app(
+    db_pool: PgPool,
+    shutdown: CancellationToken,
+    rate_limiter: RateLimiter,
+    redis: Option<RedisConnection>,
+    configuration: Settings,
+) -> Result<Router> {
+    let email_client = configuration.email_client.client()?;
+    let blob_store = BlobStore::new(configuration.storage.root);
+    let base_url = configuration.application.base_url;
+    let trusted_proxies = TrustedProxies::new(configuration.application.trusted_proxies);
+    let unsubscribe_links = UnsubscribeLinks::new(
+        base_url.clone(),
+        configuration.newsletter.hmac_secret.clone(),
+    );
+    let redis = redis.zip(configuration.redis.map(|redis| redis.required));
+    let security_headers = SecurityHeaders::new(&configuration.security_headers, &base_url)?;
+    let cors = cors_layer(&configuration.cors)?;
+    let body_limits = BodyLimits::new(configuration.body_limits.default_bytes)
+        .route("/register", configuration.body_limits.upload_bytes)
+        .route("/me/avatar", configuration.body_limits.upload_bytes);
+    let readiness_checks = ReadinessChecks {
+        db_pool: db_pool.clone(),
+        blob_store: blob_store.clone(),
+        redis,
+        timeout: configuration.health.check_timeout(),
+    };
+
+    let mut router = Router::new()
         .route("/health_check", get(health_check))
-        .route("/register", post(register))
+        .route("/health/live", get(liveness))
+        .route("/health/ready", get(readiness))
+        .route(
+            "/register",
+            post(register).route_layer(middleware::from_fn(limit_signups)),
+        )
+        .route("/me", delete(delete_account))
+        .route("/me/avatar", put(update_avatar))
+        .route("/me/deactivate", post(deactivate_account))
+        .route("/me/deletion/cancel", post(cancel_account_deletion))
+        .route("/me/email", post(change_email))
+        .route("/me/email/confirm", get(confirm_email_change))
+        .route("/me/export", get(export_data))
+        .route("/me/locale", put(change_locale))
+        .route("/me/username", put(change_username))
+        .route(
+            "/newsletter/subscribe",
+            post(subscribe).route_layer(middleware::from_fn(limit_signups)),
+        )
+        .route("/newsletter/confirm", get(confirm_subscription))
+        .route(
+            "/newsletter/unsubscribe",
+            get(unsubscribe_form).post(unsubscribe),
+        )
+        .route("/admin/users/:id/reactivate", post(reactivate_account))
+        .route("/admin/newsletters", post(publish_newsletter))
+        .route(PROBLEMS_PATH, get(list_problem_types))
+        .route(
+            &format!("{PROBLEMS_PATH}/*code"),
+            get(describe_problem_type),
+        );
+    if configuration.metrics.enabled && configuration.metrics.port.is_none() {
+        router = router.route("/metrics", get(metrics));
+    }
+
+    let router = router
+        .route_layer(middleware::from_fn(move |request, next| {
+            limit_request_body(body_limits.clone(), request, next)
+        }))
+        .route_layer(middleware::from_fn(track_requests))
+        .route_layer(middleware::from_fn(record_route))
         .layer(Extension(UserRepository::new(db_pool.clone())))
-        .layer(Extension(ImageRepository::new(db_pool.clone())))
-        .layer(Extension(EmailRepository::new(db_pool)))
-        .layer(TraceLayer::new_for_http())
+        .layer(Extension(ImageRepository::new(blob_store.clone())))
+        .layer(Extension(EmailRepository::new(db_pool.clone())))
+        .layer(Extension(DataExportRepository::new(db_pool.clone())))
+        .layer(Extension(NewsletterRepository::new(db_pool.clone())))
+        .layer(Extension(db_pool))
+        .layer(Extension(unsubscribe_links))
+        .layer(Extension(blob_store))
+        .layer(Extension(Arc::new(email_client)))
+        .layer(Extension(ApplicationBaseUrl(base_url.clone())))
+        .layer(Extension(configuration.accounts))
+        .layer(Extension(configuration.newsletter))
+        .layer(Extension(configuration.uploads))
+        .layer(Extension(PasswordPolicy::new(configuration.passwords)))
+        .layer(Extension(UsernamePolicy::new(
+            configuration.usernames.reserved,
+        )))
+        .layer(Extension(shutdown))
+        .layer(Extension(readiness_checks))
+        .layer(Extension(rate_limiter))
+        .layer(CatchPanicLayer::custom(handle_panic))
+        .layer(middleware::from_fn(move |request, next| {
+            complete_problem_details(base_url.clone(), request, next)
+        }))
+        .layer(cors)
+        .layer(middleware::from_fn(move |request, next| {
+            add_security_headers(security_headers.clone(), request, next)
+        }))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(make_request_span)
+                .on_request(log_request_headers),
+        )
+        .layer(middleware::from_fn(move |request, next| {
+            resolve_client_ip(trusted_proxies.clone(), request, next)
+        }))
+        .layer(SetSensitiveRequestHeadersLayer::new([
+            header::AUTHORIZATION,
+            header::PROXY_AUTHORIZATION,
+            header::COOKIE,
+        ]))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
+
+    Ok(router)
 }
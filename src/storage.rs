@@ -0,0 +1,230 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use eyre::{Result, WrapErr};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// The directory of the store where uploads are written until they're kept.
+const STAGING_DIRECTORY: &str = ".staging";
+
+/// A store for uploaded files backed by a directory in the local filesystem.
+///
+/// Files are addressed by a relative key, e.g. `image_uuid/image_file_uuid.png`.
+/// Uploads are first written to a staging area with [`BlobStore::stage`], and only moved
+/// to their key once they're checked.
+#[derive(Clone, Debug)]
+pub struct BlobStore {
+    root: PathBuf,
+}
+
+impl BlobStore {
+    /// Create a new `BlobStore` that keeps its files inside `root`.
+    #[must_use]
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The path in the filesystem of the file with the given key.
+    #[must_use]
+    pub fn path(&self, key: impl AsRef<Path>) -> PathBuf {
+        self.root.join(key)
+    }
+
+    /// Store a file, creating any missing parent directories.
+    pub async fn put(&self, key: impl AsRef<Path>, bytes: &[u8]) -> Result<()> {
+        let path = self.path(key);
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .wrap_err_with(|| format!("failed to create directory {}", parent.display()))?;
+        }
+
+        tokio::fs::write(&path, bytes)
+            .await
+            .wrap_err_with(|| format!("failed to write file {}", path.display()))
+    }
+
+    /// Create an empty file in the staging area, to write an upload to.
+    ///
+    /// The file is deleted when dropped, unless it's moved to a key with [`BlobStore::persist`].
+    /// Staged files left behind by a crash are deleted by the garbage collection of images,
+    /// like any other file no row references.
+    pub async fn stage(&self) -> Result<StagedFile> {
+        let directory = self.path(STAGING_DIRECTORY);
+        tokio::fs::create_dir_all(&directory)
+            .await
+            .wrap_err_with(|| format!("failed to create directory {}", directory.display()))?;
+
+        let path = directory.join(Uuid::new_v4().to_string());
+        let file = tokio::fs::File::create(&path)
+            .await
+            .wrap_err_with(|| format!("failed to create file {}", path.display()))?;
+        Ok(StagedFile {
+            path,
+            file,
+            hasher: Sha256::new(),
+            size: 0,
+            persisted: false,
+        })
+    }
+
+    /// Move a staged file to `key`, creating any missing parent directories.
+    pub async fn persist(&self, mut staged: StagedFile, key: impl AsRef<Path>) -> Result<()> {
+        let path = self.path(key);
+        staged.flush().await?;
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .wrap_err_with(|| format!("failed to create directory {}", parent.display()))?;
+        }
+
+        tokio::fs::rename(&staged.path, &path)
+            .await
+            .wrap_err_with(|| format!("failed to move file to {}", path.display()))?;
+        staged.persisted = true;
+        Ok(())
+    }
+
+    /// Read a stored file.
+    pub async fn get(&self, key: impl AsRef<Path>) -> Result<Vec<u8>> {
+        let path = self.path(key);
+
+        tokio::fs::read(&path)
+            .await
+            .wrap_err_with(|| format!("failed to read file {}", path.display()))
+    }
+
+    /// Open a stored file, to stream it instead of reading it as a whole.
+    pub async fn open(&self, key: impl AsRef<Path>) -> Result<tokio::fs::File> {
+        let path = self.path(key);
+
+        tokio::fs::File::open(&path)
+            .await
+            .wrap_err_with(|| format!("failed to open file {}", path.display()))
+    }
+
+    /// Delete a stored file. Deleting a file that doesn't exist is not an error.
+    pub async fn delete(&self, key: impl AsRef<Path>) -> Result<()> {
+        let path = self.path(key);
+
+        match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(e).wrap_err_with(|| format!("failed to delete file {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// List the keys of all the stored files, with the time they were last modified.
+    pub async fn list(&self) -> Result<Vec<(String, SystemTime)>> {
+        let mut files = Vec::new();
+        let mut directories = vec![self.root.clone()];
+
+        while let Some(directory) = directories.pop() {
+            let mut entries = match tokio::fs::read_dir(&directory).await {
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                entries => entries.wrap_err_with(|| {
+                    format!("failed to read directory {}", directory.display())
+                })?,
+            };
+
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .wrap_err_with(|| format!("failed to read directory {}", directory.display()))?
+            {
+                let path = entry.path();
+                let metadata = entry
+                    .metadata()
+                    .await
+                    .wrap_err_with(|| format!("failed to read metadata of {}", path.display()))?;
+                if metadata.is_dir() {
+                    directories.push(path);
+                } else if let Ok(key) = path.strip_prefix(&self.root) {
+                    let key = key
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    let modified = metadata.modified().wrap_err_with(|| {
+                        format!("failed to read modification time of {}", path.display())
+                    })?;
+                    files.push((key, modified));
+                }
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+}
+
+/// An upload written to the staging area of a [`BlobStore`], hashed as it's written.
+pub struct StagedFile {
+    path: PathBuf,
+    file: tokio::fs::File,
+    hasher: Sha256,
+    size: u64,
+    persisted: bool,
+}
+
+impl StagedFile {
+    /// Append a chunk to the file.
+    ///
+    /// The last chunk may still be in flight afterwards: [`StagedFile::flush`] before
+    /// reading the file back.
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.file
+            .write_all(chunk)
+            .await
+            .wrap_err_with(|| format!("failed to write file {}", self.path.display()))?;
+        self.hasher.update(chunk);
+        self.size += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Wait until every chunk written is in the file.
+    pub async fn flush(&mut self) -> Result<()> {
+        self.file
+            .flush()
+            .await
+            .wrap_err_with(|| format!("failed to flush file {}", self.path.display()))
+    }
+
+    /// The path of the file in the filesystem, to read it back, or to write it from
+    /// blocking code instead of through [`StagedFile::write`].
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The bytes written so far.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The SHA-256 hash of the bytes written so far.
+    #[must_use]
+    pub fn sha256(&self) -> [u8; 32] {
+        self.hasher.clone().finalize().into()
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        // A single unlink, not worth a blocking task
+        if !self.persisted {
+            if let Err(error) = std::fs::remove_file(&self.path) {
+                tracing::warn!(?error, path = %self.path.display(), "failed to delete staged file");
+            }
+        }
+    }
+}
@@ -1,15 +1,47 @@
+use std::{any::Any, panic};
+
+use axum::{
+    extract::MatchedPath,
+    http::{header, Request},
+    middleware::Next,
+    response::Response,
+};
 use eyre::{Result, WrapErr};
-use tracing::{subscriber, Level, Subscriber};
+use opentelemetry::{
+    global, runtime,
+    sdk::{
+        propagation::TraceContextPropagator,
+        trace::{self, Sampler, Tracer, TracerProvider},
+        Resource,
+    },
+    trace::TracerProvider as _,
+    KeyValue,
+};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+use tokio::task::JoinHandle;
+use tower_http::request_id::RequestId;
+use tracing::{dispatcher, field, subscriber, Dispatch, Level, Span, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
 use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
 
+use crate::{client_ip::ClientIp, configuration::OtlpSettings};
+
 /// Compose multiple layers into a `tracing`'s subscriber.
 ///
+/// Spans are also exported through `tracer` when given, see [`otlp_tracer_provider`].
+///
 /// # Implementation Notes
 ///
 /// We are using `impl Subscriber` as return type to avoid having to spell out the actual
 /// type of the returned subscriber, which is indeed quite complex.
-pub fn get_subscriber<Sink>(name: String, level: Level, sink: Sink) -> impl Subscriber + Sync + Send
+pub fn get_subscriber<Sink>(
+    name: String,
+    level: Level,
+    sink: Sink,
+    tracer: Option<Tracer>,
+) -> impl Subscriber + Sync + Send
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
@@ -21,11 +53,164 @@ where
         .with(env_filter)
         .with(JsonStorageLayer)
         .with(formatting_layer)
+        .with(tracer.map(otlp_layer))
 }
 
-/// Register a subscriber as global default to process span data.
+/// The layer that exports spans through `tracer`.
+pub fn otlp_layer<S>(tracer: Tracer) -> OpenTelemetryLayer<S, Tracer>
+where
+    S: Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(tracer)
+}
+
+/// Build the provider of the tracers that export spans to the collector of `settings`.
+///
+/// Spans are sent in batches by a thread of its own, so flushing them when the provider is
+/// shut down doesn't block the runtime that records them. Tracers only keep a weak
+/// reference to their provider, so it must outlive them, e.g. by making it the global one.
+pub fn otlp_tracer_provider(settings: &OtlpSettings) -> Result<TracerProvider> {
+    let exporter = SpanExporterBuilder::from(
+        opentelemetry_otlp::new_exporter()
+            .http()
+            .with_endpoint(&settings.endpoint),
+    )
+    .build_span_exporter()
+    .wrap_err("failed to build the OTLP exporter")?;
+
+    // Follow the decision of the caller when continuing its trace
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+        settings.sampling_ratio,
+    )));
+    let config = trace::config()
+        .with_sampler(sampler)
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            settings.service_name.clone(),
+        )]));
+
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::TokioCurrentThread)
+        .with_config(config)
+        .build())
+}
+
+/// Build the tracer that exports spans to the collector of `settings`, making its provider
+/// the global one. Spans still in the batch are exported by
+/// `opentelemetry::global::shutdown_tracer_provider`.
+pub fn init_otlp_tracer(settings: &OtlpSettings) -> Result<Tracer> {
+    let provider = otlp_tracer_provider(settings)?;
+    let tracer = provider.tracer("chocoapi");
+    global::set_tracer_provider(provider);
+    Ok(tracer)
+}
+
+/// Register a subscriber as global default to process span data, and log panics through it.
+///
+/// Traces are propagated with the W3C `traceparent` header.
 ///
 /// It should only be called once!
 pub fn init_subscriber(subscriber: impl Subscriber + Sync + Send) -> Result<()> {
-    subscriber::set_global_default(subscriber).wrap_err("failed to set subscriber")
+    subscriber::set_global_default(subscriber).wrap_err("failed to set subscriber")?;
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    init_panic_hook();
+    Ok(())
+}
+
+/// The span of a request, which continues the trace of the caller when the request has
+/// a `traceparent` header.
+///
+/// The matched route and the authenticated user aren't known yet, they're recorded later
+/// by [`record_route`] and the authentication extractors.
+pub fn make_request_span<B>(request: &Request<B>) -> Span {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|request_id| request_id.header_value().to_str().ok());
+    let client_ip = request
+        .extensions()
+        .get::<ClientIp>()
+        .map(|ClientIp(address)| *address);
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok());
+
+    // The path only, the query may have tokens, e.g. the one to confirm an email
+    let span = tracing::info_span!(
+        "request",
+        request_id,
+        method = %request.method(),
+        path = request.uri().path(),
+        version = ?request.version(),
+        route = field::Empty,
+        user_id = field::Empty,
+        client_ip = client_ip.map(field::display),
+        user_agent,
+    );
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    span.set_parent(parent);
+    span
+}
+
+/// Log the start of a request with its headers, at the `DEBUG` level.
+///
+/// Headers marked as sensitive, e.g. `Authorization`, are logged as `Sensitive`.
+pub fn log_request_headers<B>(request: &Request<B>, _: &Span) {
+    tracing::debug!(headers = ?request.headers(), "started processing request");
+}
+
+/// Record the route the request matched in its span.
+///
+/// It must be added with `Router::route_layer`, so the route is already matched.
+pub async fn record_route<B>(request: Request<B>, next: Next<B>) -> Response {
+    if let Some(route) = request.extensions().get::<MatchedPath>() {
+        Span::current().record("route", &route.as_str());
+    }
+    next.run(request).await
+}
+
+/// Log panics with their location and backtrace, in the span where they happened, e.g. the one
+/// of the request whose handler panicked. The default hook still runs afterwards.
+fn init_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let payload = panic_message(info.payload());
+        let location = info.location().map(ToString::to_string);
+        let backtrace = backtrace::Backtrace::new();
+        tracing::error!(
+            panic.payload = payload,
+            panic.location = location,
+            panic.backtrace = ?backtrace,
+            "panic"
+        );
+        default_hook(info);
+    }));
+}
+
+/// The message of a panic, if it was given one.
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>")
+}
+
+/// Run a blocking closure on the blocking thread pool, keeping the current span.
+///
+/// The current subscriber is kept too, as the span only exists in the one that created it,
+/// which isn't the global one when it was set with `tracing::subscriber::set_default`.
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    let dispatch = dispatcher::get_default(Dispatch::clone);
+    tokio::task::spawn_blocking(move || {
+        dispatcher::with_default(&dispatch, || current_span.in_scope(f))
+    })
 }
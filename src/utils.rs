@@ -1 +1,61 @@
+use std::time::Duration;
 
+use rand::{thread_rng, Rng, RngCore};
+
+/// Generate a random, URL safe token to be sent to users, e.g. in a confirmation link.
+#[must_use]
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 20];
+    thread_rng().fill_bytes(&mut bytes);
+    base32::encode(base32::Alphabet::Crockford, &bytes).to_lowercase()
+}
+
+/// The pause before the retry number `attempt` (starting at 0) of a failed operation.
+///
+/// It's an exponential backoff with "full jitter": a random duration up to `initial`
+/// doubled on each attempt, capped at `max`, so clients that failed at the same time
+/// don't retry at the same time.
+#[must_use]
+pub fn backoff_with_jitter(attempt: u32, initial: Duration, max: Duration) -> Duration {
+    let ceiling = initial
+        .checked_mul(2u32.saturating_pow(attempt))
+        .map_or(max, |delay| delay.min(max));
+    ceiling.mul_f64(thread_rng().gen::<f64>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INITIAL: Duration = Duration::from_millis(100);
+    const MAX: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn the_pause_is_at_most_the_doubled_initial_delay() {
+        for attempt in 0..5 {
+            let ceiling = INITIAL * 2u32.pow(attempt);
+            for _ in 0..100 {
+                assert!(backoff_with_jitter(attempt, INITIAL, MAX) <= ceiling);
+            }
+        }
+    }
+
+    #[test]
+    fn the_pause_is_capped_at_the_max_delay() {
+        // 100ms doubled 6 times is already above the cap, and 2^40 doesn't fit in a u32
+        for attempt in [6, 10, 40, u32::MAX] {
+            for _ in 0..100 {
+                assert!(backoff_with_jitter(attempt, INITIAL, MAX) <= MAX);
+            }
+        }
+    }
+
+    #[test]
+    fn the_pause_is_randomized() {
+        let pauses: Vec<_> = (0..20)
+            .map(|_| backoff_with_jitter(10, INITIAL, MAX))
+            .collect();
+
+        assert!(pauses.iter().any(|pause| *pause != pauses[0]));
+    }
+}
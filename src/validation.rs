@@ -0,0 +1,88 @@
+use axum::{
+    async_trait,
+    body::HttpBody,
+    extract::{Form, FromRequest, Json, RequestParts},
+    BoxError,
+};
+use serde::de::DeserializeOwned;
+use validator::{Validate, ValidationError};
+
+use crate::{
+    erro::{AppError, ErrorMap},
+    models::EmailAddress,
+};
+
+/// A JSON body deserialized into `T` and validated with its `Validate` implementation.
+///
+/// Malformed bodies are rejected with an `invalid_body` error and invalid ones with
+/// the errors of each field, both as `422 Unprocessable Entity`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    B: HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req)
+            .await
+            .map_err(|rejection| invalid_body(&rejection))?;
+        value.validate()?;
+        Ok(Self(value))
+    }
+}
+
+/// A url-encoded form deserialized into `T` and validated with its `Validate` implementation.
+///
+/// Rejections are the same as the ones of [`ValidatedJson`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedForm<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidatedForm<T>
+where
+    T: DeserializeOwned + Validate,
+    B: HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Form(value) = Form::<T>::from_request(req)
+            .await
+            .map_err(|rejection| invalid_body(&rejection))?;
+        value.validate()?;
+        Ok(Self(value))
+    }
+}
+
+/// Reject a body that couldn't be read or deserialized.
+pub fn invalid_body(rejection: &dyn std::fmt::Display) -> AppError {
+    tracing::warn!(%rejection, "invalid request body");
+    let mut errors = ErrorMap::new();
+    errors.add_error("body", "invalid_body");
+    AppError::UnprocessableEntity(errors)
+}
+
+/// Check that `email` is an address accepted by [`EmailAddress::parse`].
+pub fn validate_email_address(email: &str) -> Result<(), ValidationError> {
+    EmailAddress::parse(email)
+        .map(|_| ())
+        .ok_or_else(|| ValidationError::new("invalid_email"))
+}
+
+/// Check that `value` has something other than whitespace, reported as `required`.
+pub fn validate_not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        Err(ValidationError::new("required"))
+    } else {
+        Ok(())
+    }
+}
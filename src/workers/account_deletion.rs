@@ -0,0 +1,268 @@
+use std::time::Duration;
+
+use eyre::Result;
+use sqlx::{Acquire, PgConnection, PgPool};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use super::{pause, ExecutionOutcome, MAX_RETRIES};
+use crate::{configuration::Settings, repositories::ImageRepository, storage::BlobStore};
+
+/// Delete the accounts whose grace period is over until the application stops.
+///
+/// The task in progress is finished when `shutdown` is cancelled, then the worker stops.
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    connection_pool: PgPool,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let blob_store = BlobStore::new(configuration.storage.root);
+    worker_loop(connection_pool, blob_store, shutdown).await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    blob_store: BlobStore,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    while !shutdown.is_cancelled() {
+        match try_execute_task(&pool, &blob_store).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                pause(Duration::from_secs(60), &shutdown).await;
+            }
+            Err(_) => {
+                pause(Duration::from_secs(1), &shutdown).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete a single account whose grace period is over.
+///
+/// Users that authored posts are anonymized instead, so their posts keep an author.
+/// Either way their roles, email addresses, data exports and profile picture are removed.
+/// Failed deletions are postponed with an exponential backoff, up to `MAX_RETRIES` times.
+#[tracing::instrument(
+    skip_all,
+    fields(user_id = tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(pool: &PgPool, blob_store: &BlobStore) -> Result<ExecutionOutcome> {
+    let mut transaction = pool.begin().await?;
+
+    let user = sqlx::query!(
+        r#"
+        SELECT id, email_id, profile_pic_id, deletion_retries
+        FROM users
+        WHERE deletion_scheduled_at <= transaction_timestamp() AND deletion_failed_at IS NULL
+        ORDER BY deletion_scheduled_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    let user = match user {
+        Some(user) => user,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+
+    tracing::Span::current().record("user_id", &tracing::field::display(user.id));
+
+    // A failed attempt is rolled back to the savepoint, keeping the lock to record it
+    let mut attempt = transaction.begin().await?;
+    let file_paths = match delete_user(
+        &mut attempt,
+        blob_store,
+        user.id,
+        user.email_id,
+        user.profile_pic_id,
+    )
+    .await
+    {
+        Ok(file_paths) => {
+            attempt.commit().await?;
+            file_paths
+        }
+        Err(error) => {
+            attempt.rollback().await?;
+            tracing::warn!(
+                ?error,
+                n_retries = user.deletion_retries,
+                "failed to delete account"
+            );
+            if user.deletion_retries + 1 >= MAX_RETRIES {
+                tracing::error!("giving up on the deletion of the account");
+            }
+            sqlx::query!(
+                r#"
+                UPDATE users
+                SET deletion_retries = deletion_retries + 1,
+                    deletion_scheduled_at = transaction_timestamp() + make_interval(secs => 2 ^ deletion_retries * 60),
+                    deletion_failed_at = CASE
+                        WHEN deletion_retries + 1 >= $2 THEN transaction_timestamp()
+                    END
+                WHERE id = $1
+                "#,
+                user.id,
+                i32::from(MAX_RETRIES)
+            )
+            .execute(&mut transaction)
+            .await?;
+            Vec::new()
+        }
+    };
+
+    transaction.commit().await?;
+
+    // Files are only removed once the database no longer references them.
+    for file_path in file_paths {
+        if let Err(error) = blob_store.delete(&file_path).await {
+            tracing::warn!(?error, file_path, "failed to delete file");
+        }
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Delete or anonymize the user `user_id`, returning the paths of the files to delete
+/// once that's committed.
+async fn delete_user(
+    conn: &mut PgConnection,
+    blob_store: &BlobStore,
+    user_id: Uuid,
+    email_id: Uuid,
+    profile_pic_id: Option<Uuid>,
+) -> Result<Vec<String>> {
+    // Addresses the user had before, kept until now for audit purposes.
+    let previous_email_ids: Vec<Uuid> = sqlx::query!(
+        r#"
+        SELECT old_email_id AS "email_id!" FROM email_changes WHERE user_id = $1
+        UNION
+        SELECT new_email_id AS "email_id!" FROM email_changes WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|r| r.email_id)
+    .collect();
+
+    // The rows are deleted on cascade with the user, but not the archives.
+    let mut file_paths: Vec<String> = sqlx::query!(
+        r#"DELETE FROM data_exports WHERE user_id = $1 RETURNING file_path"#,
+        user_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .filter_map(|r| r.file_path)
+    .collect();
+
+    let has_posts = sqlx::query!(
+        r#"SELECT EXISTS (SELECT 1 FROM posts WHERE author_id = $1) AS "exists!""#,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .exists;
+
+    if has_posts {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET username = 'deleted-' || left(replace(id::text, '-', ''), 23),
+                full_name = NULL,
+                profile_pic_id = NULL,
+                passwd_hash = '',
+                active = FALSE,
+                deletion_scheduled_at = NULL,
+                updated_at = transaction_timestamp()
+            WHERE id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE emails
+            SET email = 'deleted-' || id::text || '@invalid',
+                email_confirmed_at = NULL,
+                subscribed = FALSE,
+                active = FALSE,
+                updated_at = transaction_timestamp()
+            WHERE id = $1
+            "#,
+            email_id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query!(r#"DELETE FROM users_roles WHERE user_id = $1"#, user_id)
+            .execute(&mut *conn)
+            .await?;
+
+        sqlx::query!(r#"DELETE FROM email_changes WHERE user_id = $1"#, user_id)
+            .execute(&mut *conn)
+            .await?;
+    } else {
+        // `users_roles` and `email_changes` are deleted on cascade
+        sqlx::query!(r#"DELETE FROM users WHERE id = $1"#, user_id)
+            .execute(&mut *conn)
+            .await?;
+
+        sqlx::query!(r#"DELETE FROM emails WHERE id = $1"#, email_id)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    sqlx::query!(
+        r#"
+        DELETE FROM emails
+        WHERE id = ANY($1) AND NOT EXISTS (SELECT 1 FROM users WHERE users.email_id = emails.id)
+        "#,
+        &previous_email_ids
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    if let Some(image_id) = profile_pic_id {
+        let image = sqlx::query!(
+            r#"
+            DELETE FROM images
+            WHERE id = $1
+                AND NOT EXISTS (
+                    SELECT 1 FROM posts WHERE cover_image_id = $1 OR og_image_id = $1
+                )
+            RETURNING small_file_id, medium_file_id, large_file_id
+            "#,
+            image_id
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        if let Some(image) = image {
+            let image_file_paths = ImageRepository::new(blob_store.clone())
+                .release_files(
+                    &mut *conn,
+                    &[
+                        image.small_file_id,
+                        image.medium_file_id,
+                        image.large_file_id,
+                    ],
+                )
+                .await?;
+            file_paths.extend(image_file_paths);
+        }
+    }
+
+    Ok(file_paths)
+}
@@ -0,0 +1,469 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use eyre::{Result, WrapErr};
+use serde::Serialize;
+use sqlx::{Acquire, PgConnection, PgPool};
+use time::OffsetDateTime;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+use zip::{write::FileOptions, ZipWriter};
+
+use super::{pause, ExecutionOutcome, MAX_RETRIES};
+use crate::{
+    configuration::Settings, email_client::EmailClient, i18n::Locale, storage::BlobStore,
+    telemetry::spawn_blocking_with_tracing,
+};
+
+/// Generate the requested data exports until the application stops.
+///
+/// The task in progress is finished when `shutdown` is cancelled, then the worker stops.
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    connection_pool: PgPool,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let blob_store = BlobStore::new(configuration.storage.root);
+    let email_client = configuration.email_client.client()?;
+    worker_loop(
+        connection_pool,
+        blob_store,
+        email_client,
+        configuration.application.base_url,
+        shutdown,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    blob_store: BlobStore,
+    email_client: EmailClient,
+    base_url: String,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    while !shutdown.is_cancelled() {
+        match try_execute_task(&pool, &blob_store, &email_client, &base_url).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                if let Err(error) = delete_expired_exports(&pool, &blob_store).await {
+                    tracing::warn!(?error, "failed to delete expired data exports");
+                }
+                pause(Duration::from_secs(10), &shutdown).await;
+            }
+            Err(_) => {
+                pause(Duration::from_secs(1), &shutdown).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Profile {
+    id: Uuid,
+    username: String,
+    full_name: Option<String>,
+    active: bool,
+    created_at: OffsetDateTime,
+    updated_at: OffsetDateTime,
+    deletion_scheduled_at: Option<OffsetDateTime>,
+    locale: String,
+}
+
+#[derive(Serialize)]
+struct EmailRecord {
+    email: String,
+    current: bool,
+    email_confirmed_at: Option<OffsetDateTime>,
+    subscribed: bool,
+    active: bool,
+    created_at: OffsetDateTime,
+    updated_at: OffsetDateTime,
+}
+
+struct Post {
+    title: String,
+    slug: String,
+    description: String,
+    content: String,
+    published_at: Option<OffsetDateTime>,
+}
+
+/// Generate a single pending data export and notify its user by email, in their locale.
+///
+/// The archive contains the profile, email addresses, roles and posts of the user,
+/// along with the original files of their images. Failed exports are retried later with
+/// an exponential backoff, up to `MAX_RETRIES` times.
+#[tracing::instrument(
+    skip_all,
+    fields(data_export_id = tracing::field::Empty, user_id = tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    blob_store: &BlobStore,
+    email_client: &EmailClient,
+    base_url: &str,
+) -> Result<ExecutionOutcome> {
+    let mut transaction = pool.begin().await?;
+
+    let data_export = sqlx::query!(
+        r#"
+        SELECT id, user_id, n_retries
+        FROM data_exports
+        WHERE completed_at IS NULL
+            AND failed_at IS NULL
+            AND execute_after <= transaction_timestamp()
+        ORDER BY execute_after
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    let data_export = match data_export {
+        Some(data_export) => data_export,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+
+    let span = tracing::Span::current();
+    span.record("data_export_id", &tracing::field::display(data_export.id));
+    span.record("user_id", &tracing::field::display(data_export.user_id));
+
+    // A failed attempt is rolled back to the savepoint, keeping the lock to record it
+    let mut attempt = transaction.begin().await?;
+    let notification = match generate_export(
+        &mut attempt,
+        blob_store,
+        data_export.id,
+        data_export.user_id,
+    )
+    .await
+    {
+        Ok(notification) => {
+            attempt.commit().await?;
+            Some(notification)
+        }
+        Err(error) => {
+            attempt.rollback().await?;
+            tracing::warn!(
+                ?error,
+                n_retries = data_export.n_retries,
+                "failed to generate data export"
+            );
+            if data_export.n_retries + 1 >= MAX_RETRIES {
+                tracing::error!("giving up on the data export");
+            }
+            sqlx::query!(
+                r#"
+                UPDATE data_exports
+                SET n_retries = n_retries + 1,
+                    execute_after = transaction_timestamp() + make_interval(secs => 2 ^ n_retries * 60),
+                    failed_at = CASE WHEN n_retries + 1 >= $2 THEN transaction_timestamp() END
+                WHERE id = $1
+                "#,
+                data_export.id,
+                i32::from(MAX_RETRIES)
+            )
+            .execute(&mut transaction)
+            .await?;
+            None
+        }
+    };
+
+    transaction.commit().await?;
+
+    if let Some(notification) = notification {
+        notify_user(email_client, base_url, notification).await?;
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Who to tell that their data export is ready.
+struct Notification {
+    username: String,
+    email: String,
+    locale: Locale,
+}
+
+/// Build the archive of the data export `data_export_id` of the user `user_id`, store it
+/// and mark the export as completed.
+async fn generate_export(
+    conn: &mut PgConnection,
+    blob_store: &BlobStore,
+    data_export_id: Uuid,
+    user_id: Uuid,
+) -> Result<Notification> {
+    let profile = sqlx::query_as!(
+        Profile,
+        r#"
+        SELECT id, username, full_name, active, created_at, updated_at, deletion_scheduled_at,
+            locale
+        FROM users
+        WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let emails = sqlx::query_as!(
+        EmailRecord,
+        r#"
+        SELECT emails.email, emails.id = users.email_id AS "current!", emails.email_confirmed_at,
+            emails.subscribed, emails.active, emails.created_at, emails.updated_at
+        FROM users
+        JOIN emails ON emails.id = users.email_id OR emails.id IN (
+            SELECT old_email_id FROM email_changes WHERE user_id = users.id
+            UNION
+            SELECT new_email_id FROM email_changes WHERE user_id = users.id
+        )
+        WHERE users.id = $1
+        ORDER BY emails.created_at
+        "#,
+        user_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let roles: Vec<String> = sqlx::query!(
+        r#"
+        SELECT roles.role_name
+        FROM users_roles
+        JOIN roles ON roles.id = users_roles.role_id
+        WHERE users_roles.user_id = $1
+        ORDER BY roles.role_name
+        "#,
+        user_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|r| r.role_name)
+    .collect();
+
+    let posts = sqlx::query_as!(
+        Post,
+        r#"
+        SELECT title, slug, description, content, published_at
+        FROM posts
+        WHERE author_id = $1
+        ORDER BY created_at
+        "#,
+        user_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let image_file_paths: Vec<String> = sqlx::query!(
+        r#"
+        SELECT image_files.file_path
+        FROM images
+        JOIN image_files ON image_files.id = images.large_file_id
+        WHERE images.id IN (
+            SELECT profile_pic_id FROM users WHERE id = $1
+            UNION
+            SELECT cover_image_id FROM posts WHERE author_id = $1
+            UNION
+            SELECT og_image_id FROM posts WHERE author_id = $1
+        )
+        "#,
+        user_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|r| r.file_path)
+    .collect();
+
+    let notification = Notification {
+        username: profile.username.clone(),
+        email: emails
+            .iter()
+            .find(|e| e.current)
+            .map(|e| e.email.clone())
+            .ok_or_else(|| eyre::eyre!("user has no current email"))?,
+        locale: Locale::parse(&profile.locale).unwrap_or_default(),
+    };
+
+    let images = image_file_paths
+        .into_iter()
+        .map(|file_path| {
+            let path = blob_store.path(&file_path);
+            (file_path, path)
+        })
+        .collect::<Vec<_>>();
+
+    // The archive is written straight to disk, the images are copied from their files
+    let archive = blob_store.stage().await?;
+    let archive_path = archive.path().to_owned();
+    spawn_blocking_with_tracing(move || {
+        build_archive(&archive_path, &profile, &emails, &roles, &posts, &images)
+    })
+    .await
+    .wrap_err("failed to spawn blocking task")??;
+
+    let file_path = format!("exports/{data_export_id}.zip");
+    blob_store.persist(archive, &file_path).await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE data_exports
+        SET file_path = $2, completed_at = transaction_timestamp()
+        WHERE id = $1
+        "#,
+        data_export_id,
+        file_path
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(notification)
+}
+
+async fn notify_user(
+    email_client: &EmailClient,
+    base_url: &str,
+    notification: Notification,
+) -> Result<()> {
+    let download_link = format!("{base_url}/me/export");
+    let email = notification.locale.email(
+        "email-data-export-ready",
+        &[
+            ("username", notification.username.into()),
+            ("link", download_link.into()),
+        ],
+    );
+    email_client
+        .send_email(
+            &notification.email,
+            &email.subject,
+            &email.html_body,
+            &email.text_body,
+        )
+        .await
+        .wrap_err("failed to send data export notification")
+}
+
+/// Write the archive to the file at `archive_path`.
+fn build_archive(
+    archive_path: &Path,
+    profile: &Profile,
+    emails: &[EmailRecord],
+    roles: &[String],
+    posts: &[Post],
+    images: &[(String, PathBuf)],
+) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(archive_path)
+        .wrap_err("failed to open the archive file")?;
+    let mut zip = ZipWriter::new(file);
+    let options = FileOptions::default();
+
+    zip.start_file("profile.json", options)?;
+    serde_json::to_writer_pretty(&mut zip, profile)?;
+
+    zip.start_file("emails.json", options)?;
+    serde_json::to_writer_pretty(&mut zip, emails)?;
+
+    zip.start_file("roles.json", options)?;
+    serde_json::to_writer_pretty(&mut zip, roles)?;
+
+    for post in posts {
+        zip.start_file(format!("posts/{}.md", entry_name(&post.slug)), options)?;
+        write!(zip, "# {}\n\n> {}\n\n", post.title, post.description)?;
+        if let Some(published_at) = post.published_at {
+            write!(zip, "Publicado el {}\n\n", published_at.date())?;
+        }
+        zip.write_all(post.content.as_bytes())?;
+    }
+
+    for (file_path, path) in images {
+        zip.start_file(format!("images/{file_path}"), options)?;
+        let mut image =
+            File::open(path).wrap_err_with(|| format!("failed to read file {}", path.display()))?;
+        io::copy(&mut image, &mut zip)?;
+    }
+
+    zip.finish()?.sync_all()?;
+    Ok(())
+}
+
+/// A slug as the name of an entry of the archive, without separators nor anything else
+/// that could place it outside of its directory.
+fn entry_name(slug: &str) -> String {
+    let name: String = slug
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    if name.is_empty() {
+        "post".to_string()
+    } else {
+        name
+    }
+}
+
+/// Delete the archives that are no longer available for download.
+///
+/// Each row is only deleted once its file is, so a file that fails to be deleted is
+/// retried the next time instead of being left behind.
+async fn delete_expired_exports(pool: &PgPool, blob_store: &BlobStore) -> Result<()> {
+    let expired = sqlx::query!(
+        r#"
+        SELECT id, file_path
+        FROM data_exports
+        WHERE completed_at <= transaction_timestamp() - interval '7 days'
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut deleted_ids = Vec::with_capacity(expired.len());
+    for data_export in expired {
+        if let Some(file_path) = &data_export.file_path {
+            if let Err(error) = blob_store.delete(file_path).await {
+                tracing::warn!(?error, file_path, "failed to delete expired data export");
+                continue;
+            }
+        }
+        deleted_ids.push(data_export.id);
+    }
+
+    sqlx::query!(
+        r#"DELETE FROM data_exports WHERE id = ANY($1)"#,
+        &deleted_ids
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::entry_name;
+
+    #[test]
+    fn slugs_cant_escape_the_directory_of_posts() {
+        assert_eq!("mi-primer-post", entry_name("mi-primer-post"));
+        assert_eq!("------etc-passwd", entry_name("../../etc/passwd"));
+        assert_eq!("post", entry_name(""));
+    }
+}
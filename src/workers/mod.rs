@@ -0,0 +1,24 @@
+pub mod account_deletion;
+pub mod data_export;
+pub mod newsletter_delivery;
+
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+
+/// Attempts at a data export or an account deletion before giving up on it. The failed
+/// ones are retried with an exponential backoff, so they don't hold up the others.
+const MAX_RETRIES: i16 = 5;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Sleep between two tasks, waking up early if the application is shutting down.
+async fn pause(duration: Duration, shutdown: &CancellationToken) {
+    tokio::select! {
+        _ = shutdown.cancelled() => {}
+        _ = tokio::time::sleep(duration) => {}
+    }
+}
@@ -0,0 +1,203 @@
+use std::time::Duration;
+
+use eyre::{Result, WrapErr};
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+use super::{pause, ExecutionOutcome};
+use crate::{
+    configuration::Settings, email_client::EmailClient, i18n::Locale, newsletter::UnsubscribeLinks,
+};
+
+/// Deliver the queued newsletter issues until the application stops.
+///
+/// The task in progress is finished when `shutdown` is cancelled, then the worker stops.
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    connection_pool: PgPool,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let email_client = configuration.email_client.client()?;
+    let delivery_interval = configuration.newsletter.delivery_interval();
+    let max_retries = configuration.newsletter.max_retries;
+    let unsubscribe_links = UnsubscribeLinks::new(
+        configuration.application.base_url,
+        configuration.newsletter.hmac_secret,
+    );
+    worker_loop(
+        connection_pool,
+        email_client,
+        unsubscribe_links,
+        delivery_interval,
+        max_retries,
+        shutdown,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    unsubscribe_links: UnsubscribeLinks,
+    delivery_interval: Duration,
+    max_retries: i16,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    while !shutdown.is_cancelled() {
+        match try_execute_task(&pool, &email_client, &unsubscribe_links, max_retries).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                pause(Duration::from_secs(10), &shutdown).await;
+            }
+            Err(_) => {
+                pause(Duration::from_secs(1), &shutdown).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {
+                // Throttle deliveries to stay under the rate limits of the email API.
+                pause(delivery_interval, &shutdown).await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Deliver a newsletter issue to a single address.
+///
+/// Failed deliveries are retried later with an exponential backoff, up to `max_retries` times.
+/// Addresses that unsubscribed or were deactivated since the issue was published are skipped.
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id = tracing::field::Empty, email_id = tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    unsubscribe_links: &UnsubscribeLinks,
+    max_retries: i16,
+) -> Result<ExecutionOutcome> {
+    let mut transaction = pool.begin().await?;
+
+    let task = sqlx::query!(
+        r#"
+        SELECT issue_delivery_queue.newsletter_issue_id, issue_delivery_queue.email_id,
+            issue_delivery_queue.n_retries, emails.email, users.locale AS "locale?",
+            emails.email_confirmed_at IS NOT NULL AND emails.subscribed AND emails.active
+                AS "deliverable!"
+        FROM issue_delivery_queue
+        JOIN emails ON emails.id = issue_delivery_queue.email_id
+        LEFT JOIN users ON users.email_id = emails.id
+        WHERE issue_delivery_queue.execute_after <= transaction_timestamp()
+        FOR UPDATE OF issue_delivery_queue
+        SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    let task = match task {
+        Some(task) => task,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+
+    let span = tracing::Span::current();
+    span.record(
+        "newsletter_issue_id",
+        &tracing::field::display(task.newsletter_issue_id),
+    );
+    span.record("email_id", &tracing::field::display(task.email_id));
+
+    let mut delivered = true;
+    if task.deliverable {
+        let issue = sqlx::query!(
+            r#"
+            SELECT title, html_content, text_content
+            FROM newsletter_issues
+            WHERE id = $1
+            "#,
+            task.newsletter_issue_id
+        )
+        .fetch_one(&mut transaction)
+        .await?;
+
+        // Subscribers without an account get the default locale
+        let locale = task
+            .locale
+            .as_deref()
+            .and_then(Locale::parse)
+            .unwrap_or_default();
+        let unsubscribe_link = unsubscribe_links.link(task.email_id);
+        let html_body = format!(
+            "{}<p><a href=\"{unsubscribe_link}\">{}</a></p>",
+            issue.html_content,
+            locale.format("newsletter-unsubscribe", &[])
+        );
+        let text_body = format!(
+            "{}\n\n{}",
+            issue.text_content,
+            locale.format(
+                "newsletter-unsubscribe-text",
+                &[("link", unsubscribe_link.as_str().into())]
+            )
+        );
+        let list_unsubscribe = format!("<{unsubscribe_link}>");
+
+        if let Err(error) = email_client
+            .send_email_with_headers(
+                &task.email,
+                &issue.title,
+                &html_body,
+                &text_body,
+                &[
+                    ("List-Unsubscribe", &list_unsubscribe),
+                    ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+                ],
+            )
+            .await
+        {
+            tracing::warn!(
+                ?error,
+                n_retries = task.n_retries,
+                "failed to deliver issue"
+            );
+            delivered = false;
+        }
+    }
+
+    if delivered || task.n_retries + 1 >= max_retries {
+        if !delivered {
+            tracing::error!("giving up on the delivery of the issue");
+        }
+        sqlx::query!(
+            r#"
+            DELETE FROM issue_delivery_queue
+            WHERE newsletter_issue_id = $1 AND email_id = $2
+            "#,
+            task.newsletter_issue_id,
+            task.email_id
+        )
+        .execute(&mut transaction)
+        .await?;
+    } else {
+        sqlx::query!(
+            r#"
+            UPDATE issue_delivery_queue
+            SET n_retries = n_retries + 1,
+                execute_after = transaction_timestamp() + make_interval(secs => 2 ^ n_retries * 60)
+            WHERE newsletter_issue_id = $1 AND email_id = $2
+            "#,
+            task.newsletter_issue_id,
+            task.email_id
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .wrap_err("failed to update the delivery queue")?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
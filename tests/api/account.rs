@@ -0,0 +1,342 @@
+use http_api_problem::StatusCode;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use chocoapi::workers::account_deletion::try_execute_task;
+
+use crate::helpers::{TestApp, TestUser};
+
+async fn user_id(app: &TestApp, username: &str) -> Uuid {
+    sqlx::query_scalar::<_, Uuid>("SELECT id FROM users WHERE username = $1")
+        .bind(username)
+        .fetch_one(&*app.db)
+        .await
+        .expect("failed to fetch user id")
+}
+
+async fn expire_grace_period(app: &TestApp, user_id: Uuid) {
+    sqlx::query(
+        "UPDATE users SET deletion_scheduled_at = now() - interval '1 minute' WHERE id = $1",
+    )
+    .bind(user_id)
+    .execute(&*app.db)
+    .await
+    .expect("failed to expire grace period");
+}
+
+async fn mount_email_api(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn deactivated_users_can_no_longer_authenticate() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/me/deactivate", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+
+    let response = app
+        .api_client
+        .post(format!("{}/me/deactivate", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn only_admins_can_reactivate_accounts() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+    let other_user = TestUser::generate();
+    other_user.register(&app).await;
+
+    app.api_client
+        .post(format!("{}/me/deactivate", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+    let reactivate_url = format!(
+        "{}/admin/users/{}/reactivate",
+        &app.address,
+        user_id(&app, &user.username).await
+    );
+
+    // Act
+    let response = app
+        .api_client
+        .post(&reactivate_url)
+        .basic_auth(&other_user.username, Some(&other_user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::FORBIDDEN, response.status());
+
+    app.make_admin(&other_user.username).await;
+    let response = app
+        .api_client
+        .post(&reactivate_url)
+        .basic_auth(&other_user.username, Some(&other_user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+
+    let response = app
+        .api_client
+        .post(format!("{}/me/deactivate", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+}
+
+#[tokio::test]
+async fn reactivating_an_unknown_user_returns_not_found() {
+    // Arrange
+    let app = TestApp::new().await;
+    let admin = TestUser::generate();
+    admin.register(&app).await;
+    app.make_admin(&admin.username).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!(
+            "{}/admin/users/{}/reactivate",
+            &app.address,
+            Uuid::new_v4()
+        ))
+        .basic_auth(&admin.username, Some(&admin.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+}
+
+#[tokio::test]
+async fn account_deletion_can_be_cancelled_during_the_grace_period() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .api_client
+        .delete(format!("{}/me", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/me/deletion/cancel", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+
+    // Nothing is deleted once the grace period is over
+    try_execute_task(&app.db, &app.blob_store).await.unwrap();
+    user_id(&app, &user.username).await;
+}
+
+#[tokio::test]
+async fn accounts_are_deleted_once_the_grace_period_is_over() {
+    // Arrange
+    let app = TestApp::new().await;
+    mount_email_api(&app).await;
+    let user = TestUser::generate();
+    user.register_with_profile_pic(&app).await;
+    let user_id = user_id(&app, &user.username).await;
+
+    let file_path = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT image_files.file_path
+        FROM users
+        JOIN images ON images.id = users.profile_pic_id
+        JOIN image_files ON image_files.id = images.small_file_id
+        WHERE users.id = $1
+        "#,
+    )
+    .bind(user_id)
+    .fetch_one(&*app.db)
+    .await
+    .unwrap();
+    assert!(app.blob_store.path(&file_path).exists());
+
+    app.api_client
+        .delete(format!("{}/me", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+    expire_grace_period(&app, user_id).await;
+
+    // Act
+    try_execute_task(&app.db, &app.blob_store).await.unwrap();
+
+    // Assert
+    let remaining = sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT (SELECT count(*) FROM users) + (SELECT count(*) FROM emails)
+            + (SELECT count(*) FROM images) + (SELECT count(*) FROM image_files)
+        "#,
+    )
+    .fetch_one(&*app.db)
+    .await
+    .unwrap();
+    assert_eq!(0, remaining);
+    assert!(!app.blob_store.path(&file_path).exists());
+}
+
+#[tokio::test]
+async fn authors_of_posts_are_anonymized_instead_of_deleted() {
+    // Arrange
+    let app = TestApp::new().await;
+    mount_email_api(&app).await;
+    let user = TestUser::generate();
+    user.register_with_profile_pic(&app).await;
+    let user_id = user_id(&app, &user.username).await;
+
+    sqlx::query(
+        r#"
+        INSERT INTO posts (title, short_title, slug, description, content, author_id, cover_image_id, og_image_id)
+        SELECT 'Hola', 'Hola', 'hola', 'Hola', 'Hola mundo', id, profile_pic_id, profile_pic_id
+        FROM users WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .execute(&*app.db)
+    .await
+    .unwrap();
+
+    app.api_client
+        .delete(format!("{}/me", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+    expire_grace_period(&app, user_id).await;
+
+    // Act
+    try_execute_task(&app.db, &app.blob_store).await.unwrap();
+
+    // Assert
+    let (username, email, active) = sqlx::query_as::<_, (String, String, bool)>(
+        r#"
+        SELECT users.username, emails.email, users.active
+        FROM posts
+        JOIN users ON users.id = posts.author_id
+        JOIN emails ON emails.id = users.email_id
+        "#,
+    )
+    .fetch_one(&*app.db)
+    .await
+    .unwrap();
+    assert!(username.starts_with("deleted-"));
+    assert!(email.ends_with("@invalid"));
+    assert!(!active);
+}
+
+#[tokio::test]
+async fn failed_deletions_are_postponed_without_holding_up_the_others() {
+    // Arrange
+    let app = TestApp::new().await;
+    mount_email_api(&app).await;
+    let mut user_ids = Vec::new();
+    for _ in 0..2 {
+        let user = TestUser::generate();
+        user.register(&app).await;
+        app.api_client
+            .delete(format!("{}/me", &app.address))
+            .basic_auth(&user.username, Some(&user.password))
+            .send()
+            .await
+            .expect("failed to execute request");
+        let user_id = user_id(&app, &user.username).await;
+        expire_grace_period(&app, user_id).await;
+        user_ids.push(user_id);
+    }
+    let broken_id = user_ids[0];
+    // The first user is due first, but can't be deleted
+    sqlx::query(
+        "UPDATE users SET deletion_scheduled_at = now() - interval '2 minutes' WHERE id = $1",
+    )
+    .bind(broken_id)
+    .execute(&*app.db)
+    .await
+    .unwrap();
+    sqlx::query(
+        r#"
+        CREATE FUNCTION fail_deletion() RETURNS trigger LANGUAGE plpgsql
+            AS $$ BEGIN RAISE EXCEPTION 'deletion failed'; END $$
+        "#,
+    )
+    .execute(&*app.db)
+    .await
+    .unwrap();
+    sqlx::query(&format!(
+        r#"
+        CREATE TRIGGER fail_deletion BEFORE DELETE ON users
+        FOR EACH ROW WHEN (OLD.id = '{broken_id}') EXECUTE FUNCTION fail_deletion()
+        "#
+    ))
+    .execute(&*app.db)
+    .await
+    .unwrap();
+
+    // Act
+    for _ in 0..2 {
+        try_execute_task(&app.db, &app.blob_store).await.unwrap();
+    }
+
+    // Assert
+    let users = sqlx::query_as::<_, (Uuid, i16, bool)>(
+        "SELECT id, deletion_retries, deletion_scheduled_at > now() FROM users",
+    )
+    .fetch_all(&*app.db)
+    .await
+    .unwrap();
+    // The other one is deleted
+    assert_eq!(vec![(broken_id, 1, true)], users);
+}
@@ -0,0 +1,329 @@
+use http_api_problem::StatusCode;
+use reqwest::multipart;
+use uuid::Uuid;
+
+use crate::helpers::{png_image, png_image_of_color, TestApp, TestUser};
+
+fn avatar_form() -> multipart::Form {
+    avatar_form_with(png_image())
+}
+
+fn avatar_form_with(image: Vec<u8>) -> multipart::Form {
+    let profile_pic = multipart::Part::bytes(image)
+        .file_name("avatar.png")
+        .mime_str("image/png")
+        .unwrap();
+    multipart::Form::new().part("profile_pic", profile_pic)
+}
+
+async fn update_avatar(app: &TestApp, user: &TestUser, image: Vec<u8>) {
+    let response = app
+        .api_client
+        .put(format!("{}/me/avatar", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .multipart(avatar_form_with(image))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+}
+
+/// The files left in the staging area of the blob store.
+fn staged_files(app: &TestApp) -> usize {
+    std::fs::read_dir(app.blob_store.path(".staging")).map_or(0, Iterator::count)
+}
+
+async fn profile_pic(app: &TestApp, username: &str) -> Option<(Uuid, String)> {
+    sqlx::query_as::<_, (Uuid, String)>(
+        r#"
+        SELECT images.id, image_files.file_path
+        FROM users
+        JOIN images ON images.id = users.profile_pic_id
+        JOIN image_files ON image_files.id = images.large_file_id
+        WHERE users.username = $1
+        "#,
+    )
+    .bind(username)
+    .fetch_optional(&*app.db)
+    .await
+    .expect("failed to fetch profile picture")
+}
+
+#[tokio::test]
+async fn update_avatar_without_credentials_returns_unauthorized() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .put(format!("{}/me/avatar", &app.address))
+        .multipart(avatar_form())
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn update_avatar_without_image_returns_unprocessable_entity() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .put(format!("{}/me/avatar", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .multipart(multipart::Form::new())
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+}
+
+#[tokio::test]
+async fn update_avatar_with_unknown_fields_returns_unprocessable_entity() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .put(format!("{}/me/avatar", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .multipart(avatar_form().text("alt_text", "Yo"))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("invalid_body", problem["body"][0]["code"]);
+    assert!(profile_pic(&app, &user.username).await.is_none());
+}
+
+#[tokio::test]
+async fn update_avatar_sets_the_profile_picture() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .put(format!("{}/me/avatar", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .multipart(avatar_form())
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+    let (_, file_path) = profile_pic(&app, &user.username).await.unwrap();
+    assert_eq!(
+        png_image(),
+        std::fs::read(app.blob_store.path(file_path)).unwrap()
+    );
+    assert_eq!(0, staged_files(&app));
+}
+
+#[tokio::test]
+async fn update_avatar_rejects_images_larger_than_the_limit() {
+    // Arrange
+    // The body may be larger, so the image itself is what goes over the limit
+    let app = TestApp::with_configuration(|c| c.uploads.max_file_bytes = 16 * 1024).await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+    let profile_pic = multipart::Part::bytes(vec![0; 32 * 1024])
+        .file_name("avatar.png")
+        .mime_str("image/png")
+        .unwrap();
+
+    // Act
+    let response = app
+        .api_client
+        .put(format!("{}/me/avatar", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .multipart(multipart::Form::new().part("profile_pic", profile_pic))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+    assert_eq!(0, staged_files(&app));
+}
+
+#[tokio::test]
+async fn update_avatar_deletes_the_previous_picture() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register_with_profile_pic(&app).await;
+    let (previous_id, previous_file_path) = profile_pic(&app, &user.username).await.unwrap();
+
+    // Act
+    let response = app
+        .api_client
+        .put(format!("{}/me/avatar", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .multipart(avatar_form_with(png_image_of_color([0, 0, 255])))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+    let (id, _) = profile_pic(&app, &user.username).await.unwrap();
+    assert_ne!(previous_id, id);
+    let images = sqlx::query_scalar::<_, i64>("SELECT count(*) FROM images")
+        .fetch_one(&*app.db)
+        .await
+        .unwrap();
+    assert_eq!(1, images);
+    assert!(!app.blob_store.path(previous_file_path).exists());
+}
+
+#[tokio::test]
+async fn concurrent_uploads_of_a_new_mime_type_succeed() {
+    // Arrange
+    let app = TestApp::new().await;
+    let users: Vec<TestUser> = (0..4).map(|_| TestUser::generate()).collect();
+    for user in &users {
+        user.register(&app).await;
+    }
+
+    // Act
+    let requests: Vec<_> = users
+        .iter()
+        .map(|user| {
+            tokio::spawn(
+                app.api_client
+                    .put(format!("{}/me/avatar", &app.address))
+                    .basic_auth(&user.username, Some(&user.password))
+                    .multipart(avatar_form())
+                    .send(),
+            )
+        })
+        .collect();
+
+    // Assert
+    for request in requests {
+        let response = request.await.unwrap().expect("failed to execute request");
+        assert_eq!(StatusCode::NO_CONTENT, response.status());
+    }
+    let mime_types = sqlx::query_scalar::<_, i64>(
+        "SELECT count(*) FROM image_mime_types WHERE mime = 'image/png'",
+    )
+    .fetch_one(&*app.db)
+    .await
+    .unwrap();
+    assert_eq!(1, mime_types);
+}
+
+#[tokio::test]
+async fn update_avatar_stores_the_mime_type_of_the_detected_format() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+    let profile_pic = multipart::Part::bytes(png_image())
+        .file_name("avatar.gif")
+        .mime_str("image/gif")
+        .unwrap();
+
+    // Act
+    let response = app
+        .api_client
+        .put(format!("{}/me/avatar", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .multipart(multipart::Form::new().part("profile_pic", profile_pic))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+    let mime_type = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT image_mime_types.mime
+        FROM users
+        JOIN images ON images.id = users.profile_pic_id
+        JOIN image_files ON image_files.id = images.large_file_id
+        JOIN image_mime_types ON image_mime_types.id = image_files.mime_id
+        WHERE users.username = $1
+        "#,
+    )
+    .bind(&user.username)
+    .fetch_one(&*app.db)
+    .await
+    .unwrap();
+    assert_eq!("image/png", mime_type);
+}
+
+#[tokio::test]
+async fn images_with_the_same_content_share_their_file() {
+    // Arrange
+    let app = TestApp::new().await;
+    let (first, second) = (TestUser::generate(), TestUser::generate());
+    first.register(&app).await;
+    second.register(&app).await;
+
+    // Act
+    update_avatar(&app, &first, png_image()).await;
+    update_avatar(&app, &second, png_image()).await;
+
+    // Assert
+    let (first_id, first_file_path) = profile_pic(&app, &first.username).await.unwrap();
+    let (second_id, second_file_path) = profile_pic(&app, &second.username).await.unwrap();
+    assert_ne!(first_id, second_id);
+    assert_eq!(first_file_path, second_file_path);
+    let ref_count =
+        sqlx::query_scalar::<_, i32>("SELECT ref_count FROM image_files WHERE file_path = $1")
+            .bind(&first_file_path)
+            .fetch_one(&*app.db)
+            .await
+            .unwrap();
+    assert_eq!(2, ref_count);
+    assert_eq!(0, staged_files(&app));
+}
+
+#[tokio::test]
+async fn shared_files_are_deleted_with_the_last_image_using_them() {
+    // Arrange
+    let app = TestApp::new().await;
+    let (first, second) = (TestUser::generate(), TestUser::generate());
+    first.register(&app).await;
+    second.register(&app).await;
+    update_avatar(&app, &first, png_image()).await;
+    update_avatar(&app, &second, png_image()).await;
+    let (_, shared_file_path) = profile_pic(&app, &first.username).await.unwrap();
+
+    // Act
+    update_avatar(&app, &first, png_image_of_color([0, 0, 255])).await;
+    let kept = app.blob_store.path(&shared_file_path).exists();
+    update_avatar(&app, &second, png_image_of_color([0, 255, 0])).await;
+
+    // Assert
+    assert!(kept, "the file was deleted while an image still used it");
+    assert!(!app.blob_store.path(&shared_file_path).exists());
+    let rows =
+        sqlx::query_scalar::<_, i64>("SELECT count(*) FROM image_files WHERE file_path = $1")
+            .bind(&shared_file_path)
+            .fetch_one(&*app.db)
+            .await
+            .unwrap();
+    assert_eq!(0, rows);
+}
@@ -0,0 +1,295 @@
+use http_api_problem::StatusCode;
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{TestApp, TestUser};
+
+async fn current_email(app: &TestApp, username: &str) -> String {
+    sqlx::query_scalar::<_, String>(
+        "SELECT emails.email FROM users JOIN emails ON emails.id = users.email_id WHERE username = $1",
+    )
+    .bind(username)
+    .fetch_one(&*app.db)
+    .await
+    .expect("failed to fetch user email")
+}
+
+async fn request_change(app: &TestApp, user: &TestUser, email: &str) {
+    let response = app
+        .api_client
+        .post(format!("{}/me/email", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&json!({ "email": email }))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+}
+
+/// The link of the last confirmation sent to `email`.
+async fn confirmation_link(app: &TestApp, email: &str) -> reqwest::Url {
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let confirmation_request = email_requests
+        .iter()
+        .rev()
+        .find(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap()["To"] == email)
+        .unwrap();
+    app.get_link(confirmation_request)
+}
+
+#[tokio::test]
+async fn change_email_without_credentials_returns_unauthorized() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/me/email", &app.address))
+        .json(&json!({ "email": "new@example.com" }))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn change_email_with_invalid_address_returns_unprocessable_entity() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/me/email", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&json!({ "email": "not-an-email" }))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("invalid_email", problem["email"][0]["code"]);
+}
+
+#[tokio::test]
+async fn change_email_sends_confirmation_to_new_address_and_notice_to_old_one() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/me/email", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&json!({ "email": "new@example.com" }))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+
+    let recipients: Vec<String> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap()["To"].to_string())
+        .collect();
+    assert!(recipients.contains(&json!("new@example.com").to_string()));
+    assert!(recipients.contains(&json!(user.email).to_string()));
+
+    // The email doesn't change until the new address is confirmed
+    assert_eq!(user.email, current_email(&app, &user.username).await);
+}
+
+#[tokio::test]
+async fn clicking_the_confirmation_link_switches_the_user_email() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.api_client
+        .post(format!("{}/me/email", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&json!({ "email": "new@example.com" }))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let confirmation_request = email_requests
+        .iter()
+        .find(|r| {
+            serde_json::from_slice::<serde_json::Value>(&r.body).unwrap()["To"] == "new@example.com"
+        })
+        .unwrap();
+    let confirmation_link = app.get_link(confirmation_request);
+
+    // Act
+    let response = app
+        .api_client
+        .get(confirmation_link.clone())
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!("new@example.com", current_email(&app, &user.username).await);
+
+    // The old address is kept for audit purposes
+    let old_email_count =
+        sqlx::query_scalar::<_, i64>("SELECT count(*) FROM emails WHERE email = $1")
+            .bind(&user.email)
+            .fetch_one(&*app.db)
+            .await
+            .unwrap();
+    assert_eq!(1, old_email_count);
+
+    // The link can only be used once
+    let response = app
+        .api_client
+        .get(confirmation_link)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("auth/invalid-token", problem["code"]);
+}
+
+#[tokio::test]
+async fn change_email_to_an_address_of_another_account_does_not_leak_it() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+    let other_user = TestUser::generate();
+    other_user.register(&app).await;
+
+    // Only the owner of the address is notified, no confirmation is sent
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/me/email", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&json!({ "email": other_user.email }))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+    assert_eq!(user.email, current_email(&app, &user.username).await);
+
+    let pending_changes = sqlx::query_scalar::<_, i64>("SELECT count(*) FROM email_changes")
+        .fetch_one(&*app.db)
+        .await
+        .unwrap();
+    assert_eq!(0, pending_changes);
+}
+
+#[tokio::test]
+async fn confirming_a_change_to_an_address_taken_meanwhile_returns_conflict() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    request_change(&app, &user, "new@example.com").await;
+    let confirmation_link = confirmation_link(&app, "new@example.com").await;
+
+    let mut other_user = TestUser::generate();
+    other_user.email = "new@example.com".to_string();
+    other_user.register(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(confirmation_link)
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::CONFLICT, response.status());
+    assert_eq!(user.email, current_email(&app, &user.username).await);
+}
+
+#[tokio::test]
+async fn confirming_a_change_discards_the_other_pending_changes_to_the_address() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+    let other_user = TestUser::generate();
+    other_user.register(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    request_change(&app, &other_user, "new@example.com").await;
+    let other_confirmation_link = confirmation_link(&app, "new@example.com").await;
+    request_change(&app, &user, "new@example.com").await;
+    let confirmation_link = confirmation_link(&app, "new@example.com").await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(confirmation_link)
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::OK, response.status());
+    let response = app
+        .api_client
+        .get(other_confirmation_link)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    assert_eq!(
+        other_user.email,
+        current_email(&app, &other_user.username).await
+    );
+}
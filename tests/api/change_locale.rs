@@ -0,0 +1,76 @@
+use http_api_problem::StatusCode;
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{TestApp, TestUser};
+
+async fn change_locale(
+    app: &TestApp,
+    user: &TestUser,
+    locale: serde_json::Value,
+) -> reqwest::Response {
+    app.api_client
+        .put(format!("{}/me/locale", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&json!({ "locale": locale }))
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+#[tokio::test]
+async fn emails_are_sent_in_the_locale_chosen_by_the_user() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = change_locale(&app, &user, json!("en")).await;
+
+    // Assert
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+
+    let response = app
+        .api_client
+        .delete(format!("{}/me", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!("Account deletion scheduled", body["Subject"]);
+    let text_body = body["TextBody"].as_str().unwrap();
+    assert!(text_body.starts_with(&format!("Hi {},\n", user.username)));
+}
+
+#[tokio::test]
+async fn change_locale_rejects_unsupported_locales() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    for locale in [json!("fr"), json!(null)] {
+        // Act
+        let response = change_locale(&app, &user, locale.clone()).await;
+
+        // Assert
+        assert_eq!(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            response.status(),
+            "unexpected status for {locale}"
+        );
+    }
+}
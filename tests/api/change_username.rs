@@ -0,0 +1,82 @@
+use http_api_problem::StatusCode;
+use serde_json::json;
+
+use crate::helpers::{TestApp, TestUser};
+
+async fn change_username(app: &TestApp, user: &TestUser, username: &str) -> reqwest::Response {
+    app.api_client
+        .put(format!("{}/me/username", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&json!({ "username": username }))
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+#[tokio::test]
+async fn change_username_replaces_the_username_used_to_authenticate() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    // Act
+    let response = change_username(&app, &user, "john.doe").await;
+
+    // Assert
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+
+    let response = app
+        .api_client
+        .post(format!("{}/me/deactivate", &app.address))
+        .basic_auth("john.doe", Some(&user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+}
+
+#[tokio::test]
+async fn change_username_enforces_the_username_policy() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    for (username, code) in [
+        ("jo", "too_short"),
+        ("john doe", "invalid_characters"),
+        ("-johndoe", "invalid_characters"),
+        ("jöhndoe", "invalid_characters"),
+        ("Support", "reserved"),
+    ] {
+        // Act
+        let response = change_username(&app, &user, username).await;
+
+        // Assert
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(
+            code, problem["username"][0]["code"],
+            "unexpected error for {username}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn change_username_to_one_that_looks_taken_returns_conflict() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+    let other_user = TestUser::generate();
+    other_user.register(&app).await;
+
+    // Act
+    let response = change_username(&app, &user, &other_user.username.to_uppercase()).await;
+
+    // Assert
+    assert_eq!(StatusCode::CONFLICT, response.status());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("already_taken", problem["username"][0]["code"]);
+}
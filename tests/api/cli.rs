@@ -0,0 +1,293 @@
+use std::time::Duration;
+
+use secrecy::SecretString;
+
+use chocoapi::cli::{
+    collect_image_garbage, create_admin, migrate_down, migrate_up, migration_status, seed,
+    MigrationState,
+};
+
+use crate::helpers::{TestApp, TestUser};
+
+#[tokio::test]
+async fn create_admin_creates_a_user_with_the_admin_role() {
+    // Arrange
+    let app = TestApp::new().await;
+    let password = SecretString::new("un cacao de fino aroma".to_string());
+
+    // Act
+    let id = create_admin(
+        &app.db,
+        &app.configuration,
+        "admin",
+        "admin@example.com",
+        password,
+    )
+    .await
+    .expect("failed to create admin");
+
+    // Assert
+    let is_admin = sqlx::query_as::<_, (bool,)>(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM users_roles JOIN roles ON roles.id = users_roles.role_id
+            WHERE users_roles.user_id = $1 AND roles.role_name = 'admin'
+        )
+        "#,
+    )
+    .bind(id)
+    .fetch_one(&*app.db)
+    .await
+    .unwrap()
+    .0;
+    assert!(is_admin);
+}
+
+#[tokio::test]
+async fn create_admin_follows_the_password_policy() {
+    // Arrange
+    let app = TestApp::new().await;
+    let password = SecretString::new("password".to_string());
+
+    // Act
+    let error = create_admin(
+        &app.db,
+        &app.configuration,
+        "admin",
+        "admin@example.com",
+        password,
+    )
+    .await
+    .unwrap_err();
+
+    // Assert
+    assert!(error.to_string().starts_with("password: "), "{error}");
+}
+
+#[tokio::test]
+async fn migrations_can_be_reverted_and_applied_again() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let reverted = migrate_down(&app.db, 2).await.unwrap();
+    let status = migration_status(&app.db).await.unwrap();
+    let applied = migrate_up(&app.db).await.unwrap();
+
+    // Assert
+    assert_eq!(2, reverted);
+    assert_eq!(2, applied);
+    let (kept, reverted) = status.split_at(status.len() - 2);
+    assert!(kept
+        .iter()
+        .all(|migration| migration.state == MigrationState::Applied));
+    assert!(reverted
+        .iter()
+        .all(|migration| migration.state == MigrationState::Pending));
+    assert!(migration_status(&app.db)
+        .await
+        .unwrap()
+        .iter()
+        .all(|migration| migration.state == MigrationState::Applied));
+}
+
+#[tokio::test]
+async fn usernames_with_the_same_skeleton_are_renamed_by_the_migration() {
+    // Arrange
+    let app = TestApp::new().await;
+    // Back to before `username_skeletons`
+    migrate_down(&app.db, 3).await.unwrap();
+    for (username, created_at) in [
+        ("John.Doe", "2022-01-02"),
+        ("johndoe", "2022-01-01"),
+        ("j0hnd0e", "2022-01-03"),
+    ] {
+        sqlx::query(
+            r#"
+            WITH email AS (
+                INSERT INTO emails (email) VALUES ($1 || '@example.com') RETURNING id
+            )
+            INSERT INTO users (username, email_id, passwd_hash, created_at)
+            SELECT $1, id, 'hash', $2::timestamptz FROM email
+            "#,
+        )
+        .bind(username)
+        .bind(created_at)
+        .execute(&*app.db)
+        .await
+        .unwrap();
+    }
+
+    // Act
+    migrate_up(&app.db).await.unwrap();
+
+    // Assert
+    let usernames: Vec<String> =
+        sqlx::query_scalar("SELECT username FROM users ORDER BY created_at")
+            .fetch_all(&*app.db)
+            .await
+            .unwrap();
+    assert_eq!("johndoe", usernames[0]);
+    assert!(usernames[1].starts_with("John.Doe-"), "{usernames:?}");
+    assert!(usernames[2].starts_with("j0hnd0e-"), "{usernames:?}");
+}
+
+#[tokio::test]
+async fn addresses_that_only_differ_in_case_are_merged_by_the_migration() {
+    // Arrange
+    let app = TestApp::new().await;
+    // Back to before `case_insensitive_emails`
+    migrate_down(&app.db, 4).await.unwrap();
+    for (username, email, created_at) in [
+        ("john", "John@Doe.com", "2022-01-01"),
+        ("john2", "JOHN@doe.com", "2022-01-02"),
+    ] {
+        sqlx::query(
+            r#"
+            WITH email AS (INSERT INTO emails (email) VALUES ($2) RETURNING id)
+            INSERT INTO users (username, email_id, passwd_hash, created_at)
+            SELECT $1, id, 'hash', $3::timestamptz FROM email
+            "#,
+        )
+        .bind(username)
+        .bind(email)
+        .bind(created_at)
+        .execute(&*app.db)
+        .await
+        .unwrap();
+    }
+    sqlx::query(
+        r#"
+        WITH email AS (
+            INSERT INTO emails (email, email_confirmed_at, subscribed)
+            VALUES ('john@doe.com', now(), true)
+            RETURNING id
+        )
+        INSERT INTO subscription_tokens (token, email_id, expires_at)
+        SELECT 'token', id, now() FROM email
+        "#,
+    )
+    .execute(&*app.db)
+    .await
+    .unwrap();
+
+    // Act
+    migrate_up(&app.db).await.unwrap();
+
+    // Assert
+    let emails: Vec<(String, String, bool)> = sqlx::query_as(
+        r#"
+        SELECT users.username, emails.email, emails.subscribed
+        FROM users JOIN emails ON emails.id = users.email_id
+        ORDER BY users.created_at
+        "#,
+    )
+    .fetch_all(&*app.db)
+    .await
+    .unwrap();
+    assert_eq!(("john".into(), "John@Doe.com".into(), true), emails[0]);
+    assert!(emails[1].1.ends_with("@duplicate.invalid"), "{emails:?}");
+    let (n_emails, n_tokens): (i64, i64) = sqlx::query_as(
+        r#"
+        SELECT
+            (SELECT count(*) FROM emails WHERE lower(email) = 'john@doe.com'),
+            (SELECT count(*) FROM subscription_tokens
+                JOIN emails ON emails.id = subscription_tokens.email_id
+                WHERE emails.email = 'John@Doe.com')
+        "#,
+    )
+    .fetch_one(&*app.db)
+    .await
+    .unwrap();
+    assert_eq!((1, 1), (n_emails, n_tokens));
+}
+
+#[tokio::test]
+async fn seeding_twice_only_creates_the_data_once() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let first = seed(&app.db, &app.blob_store).await.unwrap();
+    let second = seed(&app.db, &app.blob_store).await.unwrap();
+
+    // Assert
+    assert_eq!((3, 3, 2), (first.users, first.posts, first.subscribers));
+    assert_eq!((0, 0, 0), (second.users, second.posts, second.subscribers));
+    let response = app
+        .api_client
+        .get(format!("{}/me/export", &app.address))
+        .basic_auth("john.demo", Some(chocoapi::cli::DEMO_PASSWORD))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn image_garbage_collection_deletes_only_unused_files() {
+    // Arrange
+    let app = TestApp::new().await;
+    TestUser::generate().register_with_profile_pic(&app).await;
+    app.blob_store
+        .put("orphan/file.png", b"not referenced")
+        .await
+        .unwrap();
+    // A row left behind, whose file was just stored
+    app.blob_store
+        .put("released/file.png", b"not used")
+        .await
+        .unwrap();
+    sqlx::query(
+        r#"
+        INSERT INTO image_files (width_px, height_px, file_path, size_bytes, mime_id)
+        SELECT 1, 1, 'released/file.png', 8, mime_id FROM image_files LIMIT 1
+        "#,
+    )
+    .execute(&*app.db)
+    .await
+    .unwrap();
+    let min_age = Duration::from_secs(3600);
+
+    // Act
+    let dry_run = collect_image_garbage(&app.db, &app.blob_store, Duration::ZERO, true)
+        .await
+        .unwrap();
+    let kept = app.blob_store.get("orphan/file.png").await.is_ok();
+    let young = collect_image_garbage(&app.db, &app.blob_store, min_age, false)
+        .await
+        .unwrap();
+    let report = collect_image_garbage(&app.db, &app.blob_store, Duration::ZERO, false)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(1, dry_run.deleted_rows);
+    assert!(kept);
+    // The file of the deleted row goes with it, whatever its age
+    assert_eq!(1, young.deleted_rows);
+    assert_eq!(vec!["released/file.png".to_string()], young.deleted_files);
+    assert_eq!(0, report.deleted_rows);
+    assert_eq!(vec!["orphan/file.png".to_string()], report.deleted_files);
+    let stored = app.blob_store.list().await.unwrap();
+    assert_eq!(1, stored.len());
+    assert!(!stored[0].0.starts_with("orphan/"));
+}
+
+#[tokio::test]
+async fn config_check_reports_invalid_values_without_leaking_secrets() {
+    // Arrange
+    let app = TestApp::new().await;
+    let mut configuration = app.configuration.clone();
+    assert_eq!(Vec::<String>::new(), configuration.check());
+
+    // Act
+    configuration.application.base_url = "127.0.0.1".to_string();
+    configuration.database.pool.min_connections = configuration.database.pool.max_connections + 1;
+    let problems = configuration.check();
+    let printed = format!("{configuration:#?}");
+
+    // Assert
+    assert_eq!(2, problems.len(), "{problems:?}");
+    assert!(!printed.contains("LOCALTESTING"));
+}
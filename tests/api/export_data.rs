@@ -0,0 +1,175 @@
+use std::io::{Cursor, Read};
+
+use http_api_problem::StatusCode;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use chocoapi::workers::data_export::try_execute_task;
+
+use crate::helpers::{TestApp, TestUser};
+
+#[tokio::test]
+async fn export_data_without_credentials_returns_unauthorized() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/me/export", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[tokio::test]
+async fn export_data_is_only_requested_once_while_pending() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    // Act
+    for _ in 0..2 {
+        let response = app
+            .api_client
+            .get(format!("{}/me/export", &app.address))
+            .basic_auth(&user.username, Some(&user.password))
+            .send()
+            .await
+            .expect("failed to execute request");
+        assert_eq!(StatusCode::ACCEPTED, response.status());
+    }
+
+    // Assert
+    let exports = sqlx::query_scalar::<_, i64>("SELECT count(*) FROM data_exports")
+        .fetch_one(&*app.db)
+        .await
+        .unwrap();
+    assert_eq!(1, exports);
+}
+
+#[tokio::test]
+async fn export_data_can_be_downloaded_once_generated() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register_with_profile_pic(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.api_client
+        .get(format!("{}/me/export", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Act
+    try_execute_task(&app.db, &app.blob_store, &app.email_client(), &app.base_url)
+        .await
+        .unwrap();
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let download_link = app.get_link(email_request);
+
+    let response = app
+        .api_client
+        .get(download_link)
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!("application/zip", response.headers()["content-type"]);
+
+    let archive = response.bytes().await.unwrap();
+    let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+
+    let mut profile = String::new();
+    archive
+        .by_name("profile.json")
+        .unwrap()
+        .read_to_string(&mut profile)
+        .unwrap();
+    let profile: serde_json::Value = serde_json::from_str(&profile).unwrap();
+    assert_eq!(user.username, profile["username"]);
+
+    let mut emails = String::new();
+    archive
+        .by_name("emails.json")
+        .unwrap()
+        .read_to_string(&mut emails)
+        .unwrap();
+    assert!(emails.contains(&user.email));
+
+    assert!(archive.file_names().any(|name| name.starts_with("images/")));
+}
+
+#[tokio::test]
+async fn failed_exports_are_retried_later_without_holding_up_the_others() {
+    // Arrange
+    let app = TestApp::new().await;
+    let broken = TestUser::generate();
+    broken.register_with_profile_pic(&app).await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    for requester in [&broken, &user] {
+        app.api_client
+            .get(format!("{}/me/export", &app.address))
+            .basic_auth(&requester.username, Some(&requester.password))
+            .send()
+            .await
+            .expect("failed to execute request");
+    }
+    // The export of the first user can't be generated without its profile picture
+    let file_path = sqlx::query_scalar::<_, String>("SELECT file_path FROM image_files")
+        .fetch_one(&*app.db)
+        .await
+        .unwrap();
+    app.blob_store.delete(&file_path).await.unwrap();
+
+    // Act
+    for _ in 0..2 {
+        try_execute_task(&app.db, &app.blob_store, &app.email_client(), &app.base_url)
+            .await
+            .unwrap();
+    }
+
+    // Assert
+    let exports = sqlx::query_as::<_, (String, i16, bool)>(
+        r#"
+        SELECT users.username, data_exports.n_retries, data_exports.completed_at IS NOT NULL
+        FROM data_exports
+        JOIN users ON users.id = data_exports.user_id
+        ORDER BY data_exports.created_at
+        "#,
+    )
+    .fetch_all(&*app.db)
+    .await
+    .unwrap();
+    assert_eq!(
+        vec![
+            (broken.username.clone(), 1, false),
+            (user.username.clone(), 0, true)
+        ],
+        exports
+    );
+}
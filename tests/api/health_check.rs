@@ -1,6 +1,11 @@
+use http_api_problem::StatusCode;
+
+use chocoapi::startup::MIGRATOR;
+
 use crate::helpers::TestApp;
 
 #[tokio::test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 async fn health_check_works() {
     // Arrange
     let app = TestApp::new().await;
@@ -18,3 +23,72 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn liveness_works() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health/live", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::OK, response.status());
+}
+
+#[tokio::test]
+async fn readiness_reports_each_dependency() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::OK, response.status());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let last_migration = MIGRATOR.iter().map(|m| m.version).max().unwrap();
+    assert_eq!("ready", body["status"]);
+    assert_eq!(last_migration, body["migration_version"]);
+    for dependency in ["postgres", "blob_store"] {
+        assert_eq!("up", body["checks"][dependency]["status"]);
+        assert_eq!(true, body["checks"][dependency]["required"]);
+        assert!(body["checks"][dependency]["latency_ms"].is_u64());
+    }
+    // Redis isn't required, whether it's running or not
+    assert_eq!(false, body["checks"]["redis"]["required"]);
+}
+
+#[tokio::test]
+async fn readiness_fails_when_a_required_dependency_is_down() {
+    // Arrange
+    let app = TestApp::new().await;
+    // Files can't be stored if the storage directory is a file
+    std::fs::write(&app.configuration.storage.root, b"not a directory").unwrap();
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("not_ready", body["status"]);
+    assert_eq!("down", body["checks"]["blob_store"]["status"]);
+    assert_eq!("failed", body["checks"]["blob_store"]["error"]);
+    assert_eq!("up", body["checks"]["postgres"]["status"]);
+}
@@ -1,7 +1,18 @@
+use std::{io::Cursor, time::Duration};
+
+use image::{ImageBuffer, ImageOutputFormat, Rgb};
 use once_cell::sync::Lazy;
+use reqwest::multipart;
+use secrecy::SecretString;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+use wiremock::MockServer;
 
-use chocoapi::configuration;
+use chocoapi::configuration::{self, Settings};
+use chocoapi::email_client::EmailClient;
+use chocoapi::newsletter::UnsubscribeLinks;
 use chocoapi::startup::Application;
+use chocoapi::storage::BlobStore;
 use chocoapi::telemetry::{get_subscriber, init_subscriber};
 
 use crate::wrappers::{TestAPI, TestConfiguration};
@@ -13,10 +24,11 @@ static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = tracing::Level::INFO;
     let subscriber_name = "test".to_string();
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let subscriber =
+            get_subscriber(subscriber_name, default_filter_level, std::io::stdout, None);
         init_subscriber(subscriber).unwrap();
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink, None);
         init_subscriber(subscriber).unwrap();
     };
 });
@@ -29,36 +41,72 @@ pub struct TestApp {
     pub port: u16,
     /// The database to use in tests.
     pub db: TestDatabase,
+    /// A mock of the email API.
+    pub email_server: MockServer,
+    /// The store where the API keeps uploaded files.
+    pub blob_store: BlobStore,
+    /// The base URL used in the links sent to users.
+    pub base_url: String,
+    /// Signs the newsletter unsubscribe links like the API does.
+    pub unsubscribe_links: UnsubscribeLinks,
     /// An http client to be used to hit the API during tests.
     pub api_client: reqwest::Client,
+    /// The configuration the API was started with.
+    pub configuration: Settings,
+    /// Shuts the API down once cancelled.
+    pub shutdown: CancellationToken,
+    /// The address `/metrics` is served at, when it has its own port.
+    pub metrics_address: Option<String>,
 }
 
 impl TestApp {
     pub async fn new() -> Self {
+        Self::with_configuration(|_| {}).await
+    }
+
+    /// Start an application with a customized configuration.
+    pub async fn with_configuration(customize: impl FnOnce(&mut Settings)) -> Self {
         Lazy::force(&TRACING);
 
+        let email_server = MockServer::start().await;
+
         // Randomise configuration to ensure test isolation
         let configuration = {
             let environment = configuration::get_environment().expect("failed to get environment");
-            let c = configuration::extract(environment).expect("Failed to read configuration.");
+            let mut c = configuration::extract(environment).expect("Failed to read configuration.");
+            c.email_client.base_url = email_server.uri();
+            customize(&mut c);
             TestConfiguration::new(c)
         };
 
         // Create the test database
         let db = TestDatabase::new(&configuration).await;
+        let blob_store = BlobStore::new(configuration.storage.root.clone());
+        let base_url = configuration.application.base_url.clone();
+        let unsubscribe_links = UnsubscribeLinks::new(
+            base_url.clone(),
+            configuration.newsletter.hmac_secret.clone(),
+        );
 
         // Launch the application as a background task
-        let (address, port) = {
+        let settings = Settings::clone(&configuration);
+        let (address, port, shutdown, metrics_address) = {
             let application = TestAPI::new(configuration).await;
 
             let local_address = application.local_address();
+            let shutdown = application.shutdown_token();
+            let metrics_address = application
+                .metrics_address()
+                .map(|address| format!("http://{address}"));
 
             let application: Application = application.into();
-            let _ = tokio::spawn(application.run_until_stopped());
+            tokio::spawn(application.run_until_stopped());
 
             (
                 format!("http://{}:{}", local_address.ip(), local_address.port()),
                 local_address.port(),
+                shutdown,
+                metrics_address,
             )
         };
 
@@ -72,7 +120,123 @@ impl TestApp {
             address,
             port,
             db,
+            email_server,
+            blob_store,
+            base_url,
+            unsubscribe_links,
             api_client,
+            configuration: settings,
+            shutdown,
+            metrics_address,
+        }
+    }
+
+    /// An email client that sends emails to the mock email API.
+    pub fn email_client(&self) -> EmailClient {
+        EmailClient::new(
+            self.email_server.uri(),
+            "test@example.com".to_string(),
+            SecretString::new("token".to_string()),
+            Duration::from_secs(2),
+        )
+        .expect("failed to build the email client")
+    }
+
+    /// Grant the `admin` role to a user.
+    pub async fn make_admin(&self, username: &str) {
+        sqlx::query(
+            r#"
+            INSERT INTO users_roles (user_id, role_id)
+            SELECT users.id, roles.id FROM users, roles
+            WHERE users.username = $1 AND roles.role_name = 'admin'
+            "#,
+        )
+        .bind(username)
+        .execute(&*self.db)
+        .await
+        .expect("failed to make user an admin");
+    }
+
+    /// Extract the first link of the plain text body of an email sent through the email API.
+    pub fn get_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let text_body = body["TextBody"].as_str().unwrap();
+
+        let link = text_body
+            .split_whitespace()
+            .find(|word| word.starts_with("http"))
+            .expect("no link found in email");
+        let mut link = reqwest::Url::parse(link).unwrap();
+
+        // The base URL in the configuration doesn't include the random port of the test API
+        assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+        link.set_port(Some(self.port)).unwrap();
+        link
+    }
+}
+
+/// A user that can be registered in a `TestApp`.
+pub struct TestUser {
+    pub username: String,
+    pub password: String,
+    pub email: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        let id = Uuid::new_v4().simple().to_string();
+        Self {
+            username: id[..16].to_string(),
+            password: Uuid::new_v4().to_string(),
+            email: format!("{}@example.com", &id[..16]),
         }
     }
+
+    pub fn form_data(&self) -> multipart::Form {
+        multipart::Form::new()
+            .text("username", self.username.clone())
+            .text("password", self.password.clone())
+            .text("email", self.email.clone())
+    }
+
+    /// Register this user through the API.
+    pub async fn register(&self, app: &TestApp) {
+        self.register_with_form(app, self.form_data()).await;
+    }
+
+    /// Register this user through the API, uploading a profile picture.
+    pub async fn register_with_profile_pic(&self, app: &TestApp) {
+        let profile_pic = multipart::Part::bytes(png_image())
+            .file_name("profile_pic.png")
+            .mime_str("image/png")
+            .unwrap();
+        let form_data = self.form_data().part("profile_pic", profile_pic);
+        self.register_with_form(app, form_data).await;
+    }
+
+    async fn register_with_form(&self, app: &TestApp, form_data: multipart::Form) {
+        let response = app
+            .api_client
+            .post(format!("{}/register", &app.address))
+            .multipart(form_data)
+            .send()
+            .await
+            .expect("failed to execute request");
+
+        assert!(response.status().is_success());
+    }
+}
+
+/// A small PNG image to be uploaded in tests.
+pub fn png_image() -> Vec<u8> {
+    png_image_of_color([255, 128, 0])
+}
+
+/// A small PNG image of a single color, to upload images with different contents.
+pub fn png_image_of_color(color: [u8; 3]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    ImageBuffer::from_pixel(4, 4, Rgb(color))
+        .write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)
+        .unwrap();
+    bytes
 }
@@ -1,5 +1,20 @@
+mod account;
+mod avatar;
+mod change_email;
+mod change_locale;
+mod change_username;
+mod cli;
+mod export_data;
 mod health_check;
+mod newsletter;
 mod helpers;
+mod metrics;
+mod problems;
+mod rate_limit;
 mod register;
+mod security;
 mod services;
+mod shutdown;
+mod startup;
+mod telemetry;
 mod wrappers;
@@ -0,0 +1,71 @@
+use http_api_problem::StatusCode;
+
+use crate::helpers::{TestApp, TestUser};
+
+async fn get_metrics(app: &TestApp, address: &str) -> String {
+    let response = app
+        .api_client
+        .get(format!("{address}/metrics"))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::OK, response.status());
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    response.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn metrics_count_requests_by_route_and_status() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register_with_profile_pic(&app).await;
+    app.api_client
+        .get(format!("{}/me/export", &app.address))
+        .basic_auth(&user.username, Some("wrong password"))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Act
+    let metrics = get_metrics(&app, &app.address).await;
+
+    // Assert
+    for series in [
+        r#"chocoapi_http_requests_total{method="POST",route="/register",status="201"}"#,
+        r#"chocoapi_http_request_duration_seconds_bucket{method="POST",route="/register",status="201""#,
+        r#"chocoapi_http_requests_total{method="GET",route="/me/export",status="401"}"#,
+        r#"chocoapi_auth_attempts_total{outcome="failure"}"#,
+        "chocoapi_registrations_total",
+        "chocoapi_image_upload_bytes_bucket",
+        "chocoapi_image_processing_duration_seconds_bucket",
+        "chocoapi_db_pool_acquire_duration_seconds_bucket",
+        r#"chocoapi_db_pool_connections{state="idle"}"#,
+        "chocoapi_db_pool_max_connections",
+    ] {
+        assert!(metrics.contains(series), "{series} missing");
+    }
+}
+
+#[tokio::test]
+async fn metrics_can_be_served_on_their_own_port() {
+    // Arrange
+    let app = TestApp::with_configuration(|c| c.metrics.port = Some(0)).await;
+    let metrics_address = app.metrics_address.clone().expect("no metrics server");
+
+    // Act
+    let on_api = app
+        .api_client
+        .get(format!("{}/metrics", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+    let metrics = get_metrics(&app, &metrics_address).await;
+
+    // Assert
+    assert_eq!(StatusCode::NOT_FOUND, on_api.status());
+    assert!(metrics.contains("chocoapi_db_pool_connections"));
+}
@@ -0,0 +1,296 @@
+use http_api_problem::StatusCode;
+use serde_json::json;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use chocoapi::workers::{newsletter_delivery::try_execute_task, ExecutionOutcome};
+
+use crate::helpers::{TestApp, TestUser};
+
+const MAX_RETRIES: i16 = 3;
+
+async fn subscribe(app: &TestApp, email: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/newsletter/subscribe", &app.address))
+        .json(&json!({ "email": email }))
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+/// Subscribe an address and follow the confirmation link sent to it.
+async fn confirmed_subscriber(app: &TestApp, email: &str) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    subscribe(app, email).await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_link = app.get_link(&email_request);
+    let response = app
+        .api_client
+        .get(confirmation_link)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::OK, response.status());
+}
+
+async fn email_id(app: &TestApp, email: &str) -> Uuid {
+    sqlx::query_scalar::<_, Uuid>("SELECT id FROM emails WHERE email = $1")
+        .bind(email)
+        .fetch_one(&*app.db)
+        .await
+        .expect("failed to fetch email id")
+}
+
+async fn is_subscribed(app: &TestApp, email: &str) -> bool {
+    sqlx::query_scalar::<_, bool>(
+        "SELECT subscribed AND email_confirmed_at IS NOT NULL FROM emails WHERE email = $1",
+    )
+    .bind(email)
+    .fetch_one(&*app.db)
+    .await
+    .expect("failed to fetch subscription")
+}
+
+async fn publish_newsletter(app: &TestApp, admin: &TestUser) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/admin/newsletters", &app.address))
+        .basic_auth(&admin.username, Some(&admin.password))
+        .json(&json!({
+            "title": "Novedades",
+            "html_content": "<p>Hola a todos</p>",
+            "text_content": "Hola a todos",
+        }))
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+async fn deliver_all_issues(app: &TestApp) {
+    let email_client = app.email_client();
+    while let ExecutionOutcome::TaskCompleted =
+        try_execute_task(&app.db, &email_client, &app.unsubscribe_links, MAX_RETRIES)
+            .await
+            .unwrap()
+    {}
+}
+
+#[tokio::test]
+async fn subscribe_with_invalid_address_returns_unprocessable_entity() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = subscribe(&app, "not-an-email").await;
+
+    // Assert
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("invalid_email", problem["email"][0]["code"]);
+}
+
+#[tokio::test]
+async fn subscriptions_are_only_active_once_confirmed() {
+    // Arrange
+    let app = TestApp::new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = subscribe(&app, "reader@example.com").await;
+
+    // Assert
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+    assert!(!is_subscribed(&app, "reader@example.com").await);
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_link(email_request);
+    let response = app
+        .api_client
+        .get(confirmation_link)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::OK, response.status());
+    assert!(is_subscribed(&app, "reader@example.com").await);
+}
+
+#[tokio::test]
+async fn confirmations_are_not_resent_during_the_cooldown() {
+    // Arrange
+    let app = TestApp::new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    subscribe(&app, "reader@example.com").await;
+
+    // Act
+    let response = subscribe(&app, "reader@example.com").await;
+
+    // Assert
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+}
+
+#[tokio::test]
+async fn confirmations_are_resent_after_the_cooldown() {
+    // Arrange
+    let app = TestApp::with_configuration(|c| c.newsletter.confirmation_cooldown_seconds = 0).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    subscribe(&app, "reader@example.com").await;
+
+    // Act
+    let response = subscribe(&app, "reader@example.com").await;
+
+    // Assert
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+}
+
+#[tokio::test]
+async fn only_admins_can_publish_newsletters() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    // Act
+    let response = publish_newsletter(&app, &user).await;
+
+    // Assert
+    assert_eq!(StatusCode::FORBIDDEN, response.status());
+}
+
+#[tokio::test]
+async fn newsletters_are_only_delivered_to_confirmed_subscribers() {
+    // Arrange
+    let app = TestApp::new().await;
+    let admin = TestUser::generate();
+    admin.register(&app).await;
+    app.make_admin(&admin.username).await;
+
+    confirmed_subscriber(&app, "reader@example.com").await;
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    subscribe(&app, "unconfirmed@example.com").await;
+    drop(mock_guard);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = publish_newsletter(&app, &admin).await;
+    deliver_all_issues(&app).await;
+
+    // Assert
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!("reader@example.com", body["To"]);
+    assert_eq!("Novedades", body["Subject"]);
+    let headers = body["Headers"].as_array().unwrap();
+    assert!(headers.iter().any(
+        |h| h["Name"] == "List-Unsubscribe-Post" && h["Value"] == "List-Unsubscribe=One-Click"
+    ));
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_later() {
+    // Arrange
+    let app = TestApp::new().await;
+    let admin = TestUser::generate();
+    admin.register(&app).await;
+    app.make_admin(&admin.username).await;
+    confirmed_subscriber(&app, "reader@example.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_newsletter(&app, &admin).await;
+    deliver_all_issues(&app).await;
+
+    // Assert
+    let n_retries = sqlx::query_scalar::<_, i16>("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_one(&*app.db)
+        .await
+        .unwrap();
+    assert_eq!(1, n_retries);
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_requires_a_valid_signature() {
+    // Arrange
+    let app = TestApp::new().await;
+    confirmed_subscriber(&app, "reader@example.com").await;
+    let email_id = email_id(&app, "reader@example.com").await;
+
+    let mut link = reqwest::Url::parse(&app.unsubscribe_links.link(email_id)).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    let mut tampered_link = link.clone();
+    tampered_link.set_query(Some(&format!("email_id={email_id}&signature=0000")));
+
+    // Act
+    let response = app
+        .api_client
+        .post(tampered_link)
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    assert!(is_subscribed(&app, "reader@example.com").await);
+
+    let response = app
+        .api_client
+        .post(link)
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::OK, response.status());
+    assert!(!is_subscribed(&app, "reader@example.com").await);
+}
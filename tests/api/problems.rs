@@ -0,0 +1,135 @@
+use http_api_problem::StatusCode;
+
+use crate::helpers::TestApp;
+
+#[tokio::test]
+async fn error_responses_include_a_stable_code_type_and_instance() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/me/deactivate", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    assert_eq!(
+        "application/problem+json",
+        response.headers()["Content-Type"]
+    );
+    let request_id = response.headers()["X-Request-Id"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("auth/invalid-credentials", problem["code"]);
+    assert_eq!(
+        format!("{}/problems/auth/invalid-credentials", app.base_url),
+        problem["type"]
+    );
+    assert_eq!(request_id, problem["instance"]);
+}
+
+#[tokio::test]
+async fn the_instance_is_the_request_id_sent_by_the_client() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/me/deactivate", &app.address))
+        .header("X-Request-Id", "my-request-id")
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!("my-request-id", response.headers()["X-Request-Id"]);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("my-request-id", problem["instance"]);
+}
+
+#[tokio::test]
+async fn error_messages_follow_the_accept_language_header() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/register", &app.address))
+        .header("Accept-Language", "en-GB,en;q=0.9,es;q=0.8")
+        .json(&serde_json::json!({
+            "username": "jo",
+            "password": "volcán-azul-mañana-17",
+            "email": "jo@example.com",
+        }))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+    assert_eq!("en", response.headers()["Content-Language"]);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("request/validation-failed", problem["code"]);
+    assert_eq!("Invalid fields", problem["title"]);
+    assert_eq!(
+        "It must have at least 3 characters.",
+        problem["username"][0]["message"]
+    );
+}
+
+#[tokio::test]
+async fn problem_types_are_documented() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/problems/user/username-taken", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::OK, response.status());
+    let page = response.text().await.unwrap();
+    assert!(page.contains("Nombre de usuario en uso"));
+
+    let catalogue = app
+        .api_client
+        .get(format!("{}/problems", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(catalogue.contains("/problems/user/username-taken"));
+}
+
+#[tokio::test]
+async fn unknown_problem_types_return_not_found() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/problems/user/unknown", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("resource/not-found", problem["code"]);
+}
@@ -0,0 +1,209 @@
+use http_api_problem::StatusCode;
+
+use chocoapi::configuration::{BucketSettings, Settings};
+
+use crate::helpers::{TestApp, TestUser};
+
+/// Allow a single registration from each address.
+fn one_signup_per_ip(configuration: &mut Settings) {
+    configuration.rate_limit.signup_per_ip = BucketSettings {
+        capacity: 1,
+        refill_interval_milliseconds: 60_000,
+    };
+}
+
+async fn register(app: &TestApp, forwarded_for: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/register", &app.address))
+        .header("X-Forwarded-For", forwarded_for)
+        .multipart(TestUser::generate().form_data())
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+async fn get_export(app: &TestApp, user: &TestUser, password: &str) -> reqwest::Response {
+    get_export_from(app, user, password, "127.0.0.1").await
+}
+
+/// Log in from `forwarded_for`, when loopback is a trusted proxy.
+async fn get_export_from(
+    app: &TestApp,
+    user: &TestUser,
+    password: &str,
+    forwarded_for: &str,
+) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/me/export", &app.address))
+        .header("X-Forwarded-For", forwarded_for)
+        .basic_auth(&user.username, Some(password))
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+#[tokio::test]
+async fn registrations_from_the_same_address_are_rate_limited() {
+    // Arrange
+    let app = TestApp::with_configuration(one_signup_per_ip).await;
+    TestUser::generate().register(&app).await;
+
+    // Act
+    let response = register(&app, "198.51.100.1").await;
+
+    // Assert
+    assert_eq!(StatusCode::TOO_MANY_REQUESTS, response.status());
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=60).contains(&retry_after), "{retry_after}");
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("request/too-many-requests", problem["code"]);
+}
+
+#[tokio::test]
+async fn clients_behind_a_trusted_proxy_are_limited_by_their_own_address() {
+    // Arrange
+    let app = TestApp::with_configuration(|configuration| {
+        one_signup_per_ip(configuration);
+        configuration.application.trusted_proxies = vec!["127.0.0.0/8".parse().unwrap()];
+    })
+    .await;
+
+    // Act
+    let first = register(&app, "198.51.100.1").await;
+    let other_client = register(&app, "198.51.100.2").await;
+    let spoofed = register(&app, "198.51.100.1, 198.51.100.3").await;
+    let same_client = register(&app, "198.51.100.1").await;
+
+    // Assert
+    assert_eq!(StatusCode::CREATED, first.status());
+    assert_eq!(StatusCode::CREATED, other_client.status());
+    assert_eq!(StatusCode::CREATED, spoofed.status());
+    assert_eq!(StatusCode::TOO_MANY_REQUESTS, same_client.status());
+}
+
+#[tokio::test]
+async fn accounts_are_locked_after_repeated_failed_logins() {
+    // Arrange
+    let app = TestApp::with_configuration(|configuration| {
+        configuration.rate_limit.lockout.max_failures = 3;
+        configuration.rate_limit.lockout.initial_lockout_seconds = 60;
+    })
+    .await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    // Act
+    let mut failures = Vec::new();
+    for _ in 0..3 {
+        failures.push(get_export(&app, &user, "wrong password").await.status());
+    }
+    let response = get_export(&app, &user, &user.password).await;
+
+    // Assert
+    assert_eq!(vec![StatusCode::UNAUTHORIZED; 3], failures);
+    assert_eq!(StatusCode::TOO_MANY_REQUESTS, response.status());
+    assert_eq!("60", response.headers()["Retry-After"]);
+}
+
+#[tokio::test]
+async fn a_successful_login_forgets_the_failed_ones() {
+    // Arrange
+    let app = TestApp::with_configuration(|configuration| {
+        configuration.rate_limit.lockout.max_failures = 3;
+    })
+    .await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    // Act
+    for _ in 0..2 {
+        get_export(&app, &user, "wrong password").await;
+    }
+    get_export(&app, &user, &user.password).await;
+    for _ in 0..2 {
+        get_export(&app, &user, "wrong password").await;
+    }
+    let response = get_export(&app, &user, &user.password).await;
+
+    // Assert
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn accounts_are_only_locked_for_the_address_of_the_failed_logins() {
+    // Arrange
+    let app = TestApp::with_configuration(|configuration| {
+        configuration.rate_limit.lockout.max_failures = 3;
+        configuration.application.trusted_proxies = vec!["127.0.0.0/8".parse().unwrap()];
+    })
+    .await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    // Act
+    for _ in 0..3 {
+        get_export_from(&app, &user, "wrong password", "198.51.100.1").await;
+    }
+    let attacker = get_export_from(&app, &user, &user.password, "198.51.100.1").await;
+    let owner = get_export_from(&app, &user, &user.password, "198.51.100.2").await;
+
+    // Assert
+    assert_eq!(StatusCode::TOO_MANY_REQUESTS, attacker.status());
+    assert!(owner.status().is_success());
+}
+
+/// Allow two failed logins to the same account, from any address.
+fn two_failures_per_account(configuration: &mut Settings) {
+    configuration.rate_limit.login_per_account = BucketSettings {
+        capacity: 2,
+        refill_interval_milliseconds: 60_000,
+    };
+}
+
+#[tokio::test]
+async fn failed_logins_to_the_same_account_are_rate_limited() {
+    // Arrange
+    let app = TestApp::with_configuration(|configuration| {
+        two_failures_per_account(configuration);
+        configuration.application.trusted_proxies = vec!["127.0.0.0/8".parse().unwrap()];
+    })
+    .await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    // Act
+    let mut statuses = Vec::new();
+    for address in ["198.51.100.1", "198.51.100.2", "198.51.100.3"] {
+        statuses.push(
+            get_export_from(&app, &user, "wrong password", address)
+                .await
+                .status(),
+        );
+    }
+
+    // Assert
+    assert_eq!(StatusCode::UNAUTHORIZED, statuses[0]);
+    assert_eq!(StatusCode::UNAUTHORIZED, statuses[1]);
+    assert_eq!(StatusCode::TOO_MANY_REQUESTS, statuses[2]);
+}
+
+#[tokio::test]
+async fn successful_logins_are_not_rate_limited() {
+    // Arrange
+    let app = TestApp::with_configuration(two_failures_per_account).await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    // Act
+    let mut statuses = Vec::new();
+    for _ in 0..3 {
+        statuses.push(get_export(&app, &user, &user.password).await.status());
+    }
+
+    // Assert
+    assert!(statuses.iter().all(StatusCode::is_success), "{statuses:?}");
+}
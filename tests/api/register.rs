@@ -1,18 +1,24 @@
+use std::{path::Path, time::Duration};
+
 use http_api_problem::StatusCode;
 use reqwest::multipart;
 
 use chocoapi::models::User;
 
-use crate::helpers::TestApp;
+use crate::helpers::{png_image, TestApp, TestUser};
+
+/// A password that complies with the password policy.
+const PASSWORD: &str = "volcán-azul-mañana-17";
 
 #[tokio::test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 async fn hitting_register_with_valid_data_returns_created_and_new_user_as_json() {
     // Arrange
     let app = TestApp::new().await;
     let client = reqwest::Client::new();
     let form_data = multipart::Form::new()
         .text("username", "johndoe")
-        .text("password", "12345")
+        .text("password", PASSWORD)
         .text("full_name", "John Doe")
         .text("email", "john@doe.com");
 
@@ -41,12 +47,13 @@ async fn hitting_register_with_valid_data_returns_created_and_new_user_as_json()
 }
 
 #[tokio::test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 async fn hitting_register_endpoint_with_missing_username_returns_unprocessable_entity() {
     // Arrange
     let app = TestApp::new().await;
     let client = reqwest::Client::new();
     let form_data = multipart::Form::new()
-        .text("password", "12345")
+        .text("password", PASSWORD)
         .text("email", "john@doe.com");
 
     // Act
@@ -65,3 +72,506 @@ async fn hitting_register_endpoint_with_missing_username_returns_unprocessable_e
     assert!(response_status.is_client_error());
     assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response_status);
 }
+
+async fn register(app: &TestApp, username: &str, email: &str) -> reqwest::Response {
+    let form_data = multipart::Form::new()
+        .text("username", username.to_string())
+        .text("password", PASSWORD)
+        .text("email", email.to_string());
+
+    app.api_client
+        .post(format!("{}/register", &app.address))
+        .multipart(form_data)
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+#[tokio::test]
+async fn hitting_register_endpoint_with_malformed_email_returns_unprocessable_entity() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    for email in [
+        "john",
+        "john@",
+        "@doe.com",
+        "john doe@doe.com",
+        "john@doe..com",
+    ] {
+        // Act
+        let response = register(&app, "johndoe", email).await;
+
+        // Assert
+        assert_eq!(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            response.status(),
+            "the API did not reject {email}"
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!("invalid_email", problem["email"][0]["code"]);
+    }
+
+    let emails = sqlx::query_scalar::<_, i64>("SELECT count(*) FROM emails")
+        .fetch_one(&*app.db)
+        .await
+        .unwrap();
+    assert_eq!(0, emails);
+}
+
+#[tokio::test]
+async fn hitting_register_endpoint_normalizes_the_email_domain() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = register(&app, "johndoe", "  John@Bücher.Example ").await;
+
+    // Assert
+    assert_eq!(StatusCode::CREATED, response.status());
+    let email = sqlx::query_scalar::<_, String>("SELECT email FROM emails")
+        .fetch_one(&*app.db)
+        .await
+        .unwrap();
+    assert_eq!("John@xn--bcher-kva.example", email);
+}
+
+#[tokio::test]
+async fn hitting_register_endpoint_with_registered_email_returns_conflict() {
+    // Arrange
+    let app = TestApp::new().await;
+    let response = register(&app, "johndoe", "john@doe.com").await;
+    assert_eq!(StatusCode::CREATED, response.status());
+
+    // Act
+    let response = register(&app, "janedoe", "JOHN@doe.com").await;
+
+    // Assert
+    assert_eq!(StatusCode::CONFLICT, response.status());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("user/email-taken", problem["code"]);
+    assert_eq!("already_registered", problem["email"][0]["code"]);
+
+    let users = sqlx::query_scalar::<_, i64>("SELECT count(*) FROM users")
+        .fetch_one(&*app.db)
+        .await
+        .unwrap();
+    assert_eq!(1, users);
+}
+
+#[tokio::test]
+async fn hitting_register_endpoint_reuses_addresses_without_an_owner() {
+    // Arrange
+    let app = TestApp::new().await;
+    sqlx::query("INSERT INTO emails (email) VALUES ('john@doe.com')")
+        .execute(&*app.db)
+        .await
+        .unwrap();
+
+    // Act
+    let response = register(&app, "johndoe", "john@doe.com").await;
+
+    // Assert
+    assert_eq!(StatusCode::CREATED, response.status());
+    let emails = sqlx::query_scalar::<_, i64>("SELECT count(*) FROM emails")
+        .fetch_one(&*app.db)
+        .await
+        .unwrap();
+    assert_eq!(1, emails);
+}
+
+/// Count the files kept in the blob store of the app.
+fn stored_files(app: &TestApp) -> usize {
+    fn count(dir: &Path) -> usize {
+        std::fs::read_dir(dir).map_or(0, |entries| {
+            entries
+                .map(|entry| entry.unwrap().path())
+                .map(|path| if path.is_dir() { count(&path) } else { 1 })
+                .sum()
+        })
+    }
+    count(&app.blob_store.path(""))
+}
+
+async fn count_rows(app: &TestApp, table: &str) -> i64 {
+    sqlx::query_scalar::<_, i64>(&format!("SELECT count(*) FROM {table}"))
+        .fetch_one(&*app.db)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn failed_registrations_leave_nothing_behind() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+
+    let profile_pic = multipart::Part::bytes(png_image())
+        .file_name("profile_pic.png")
+        .mime_str("image/png")
+        .unwrap();
+    let form_data = multipart::Form::new()
+        .text("username", "janedoe")
+        .text("password", PASSWORD)
+        .text("email", user.email.clone())
+        .part("profile_pic", profile_pic);
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/register", &app.address))
+        .multipart(form_data)
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::CONFLICT, response.status());
+    assert_eq!(1, count_rows(&app, "users").await);
+    assert_eq!(1, count_rows(&app, "emails").await);
+    assert_eq!(0, count_rows(&app, "images").await);
+    assert_eq!(0, count_rows(&app, "image_files").await);
+
+    // Files of rolled back transactions are deleted in the background
+    for _ in 0..50 {
+        if stored_files(&app) == 0 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("the profile picture was not deleted");
+}
+
+#[tokio::test]
+async fn invalid_registrations_with_profile_pic_leave_nothing_behind() {
+    // Arrange
+    let app = TestApp::new().await;
+    let profile_pic = multipart::Part::bytes(png_image())
+        .file_name("profile_pic.png")
+        .mime_str("image/png")
+        .unwrap();
+    let form_data = multipart::Form::new()
+        .text("password", PASSWORD)
+        .text("email", "john@doe.com")
+        .part("profile_pic", profile_pic);
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/register", &app.address))
+        .multipart(form_data)
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+    assert_eq!(0, count_rows(&app, "emails").await);
+    assert_eq!(0, count_rows(&app, "images").await);
+    assert_eq!(0, stored_files(&app));
+}
+
+#[tokio::test]
+async fn hitting_register_endpoint_with_json_returns_created() {
+    // Arrange
+    let app = TestApp::new().await;
+    let body = serde_json::json!({
+        "username": "johndoe",
+        "password": PASSWORD,
+        "full_name": "John Doe",
+        "email": "john@doe.com",
+    });
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/register", &app.address))
+        .json(&body)
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::CREATED, response.status());
+    let created_user: User = response.json().await.unwrap();
+    assert_eq!("johndoe", created_user.username);
+}
+
+#[tokio::test]
+async fn hitting_register_endpoint_with_urlencoded_form_returns_created() {
+    // Arrange
+    let app = TestApp::new().await;
+    let body = [
+        ("username", "johndoe"),
+        ("password", PASSWORD),
+        ("email", "john@doe.com"),
+    ];
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/register", &app.address))
+        .form(&body)
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::CREATED, response.status());
+}
+
+#[tokio::test]
+async fn hitting_register_endpoint_reports_the_same_errors_for_every_format() {
+    // Arrange
+    let app = TestApp::new().await;
+    let requests = [
+        app.api_client
+            .post(format!("{}/register", &app.address))
+            .json(&serde_json::json!({ "email": "john" })),
+        app.api_client
+            .post(format!("{}/register", &app.address))
+            .form(&[("email", "john")]),
+        app.api_client
+            .post(format!("{}/register", &app.address))
+            .multipart(multipart::Form::new().text("email", "john")),
+    ];
+
+    for request in requests {
+        // Act
+        let response = request.send().await.expect("failed to execute request");
+
+        // Assert
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!("required", problem["username"][0]["code"]);
+        assert_eq!("required", problem["password"][0]["code"]);
+        assert_eq!("invalid_email", problem["email"][0]["code"]);
+        assert!(problem["email"][0]["message"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn hitting_register_endpoint_with_unknown_fields_returns_unprocessable_entity() {
+    // Arrange
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    let requests = [
+        app.api_client
+            .post(format!("{}/register", &app.address))
+            .json(&serde_json::json!({
+                "username": user.username,
+                "password": user.password,
+                "email": user.email,
+                "role": "admin",
+            })),
+        app.api_client
+            .post(format!("{}/register", &app.address))
+            .form(&[
+                ("username", user.username.as_str()),
+                ("password", user.password.as_str()),
+                ("email", user.email.as_str()),
+                ("role", "admin"),
+            ]),
+        app.api_client
+            .post(format!("{}/register", &app.address))
+            .multipart(user.form_data().text("role", "admin")),
+    ];
+
+    for request in requests {
+        // Act
+        let response = request.send().await.expect("failed to execute request");
+
+        // Assert
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!("invalid_body", problem["body"][0]["code"]);
+    }
+}
+
+#[tokio::test]
+async fn hitting_register_endpoint_with_unsupported_body_returns_unsupported_media_type() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/register", &app.address))
+        .header("Content-Type", "text/plain")
+        .body("johndoe")
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, response.status());
+}
+
+#[tokio::test]
+async fn hitting_register_endpoint_enforces_the_limits_of_the_users_table() {
+    // Arrange
+    let app = TestApp::new().await;
+    let cases = [
+        (
+            "username",
+            serde_json::json!({ "username": "a".repeat(32) }),
+            "too_long",
+        ),
+        (
+            "username",
+            serde_json::json!({ "username": "john doe" }),
+            "invalid_characters",
+        ),
+        (
+            "username",
+            serde_json::json!({ "username": "" }),
+            "too_short",
+        ),
+        (
+            "full_name",
+            serde_json::json!({ "full_name": "a".repeat(128) }),
+            "too_long",
+        ),
+    ];
+
+    for (field, mut body, code) in cases {
+        body["password"] = PASSWORD.into();
+        body["email"] = "john@doe.com".into();
+        if body.get("username").is_none() {
+            body["username"] = "johndoe".into();
+        }
+
+        // Act
+        let response = app
+            .api_client
+            .post(format!("{}/register", &app.address))
+            .json(&body)
+            .send()
+            .await
+            .expect("failed to execute request");
+
+        // Assert
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(
+            code, problem[field][0]["code"],
+            "unexpected error for {body}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn hitting_register_endpoint_with_malformed_json_returns_unprocessable_entity() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/register", &app.address))
+        .header("Content-Type", "application/json")
+        .body(r#"{"username": "johndoe","#)
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("invalid_body", problem["body"][0]["code"]);
+}
+
+#[tokio::test]
+async fn hitting_register_endpoint_with_reserved_username_returns_unprocessable_entity() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    for username in [
+        "admin",
+        "Adm1n",
+        "a.d.m.i.n",
+        "deleted-0123456789abcdef0123456",
+    ] {
+        // Act
+        let response = register(&app, username, "john@doe.com").await;
+
+        // Assert
+        assert_eq!(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            response.status(),
+            "the API did not reject {username}"
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!("reserved", problem["username"][0]["code"]);
+    }
+}
+
+#[tokio::test]
+async fn hitting_register_endpoint_with_taken_username_returns_conflict() {
+    // Arrange
+    let app = TestApp::new().await;
+    let response = register(&app, "johndoe", "john@doe.com").await;
+    assert_eq!(StatusCode::CREATED, response.status());
+
+    for (username, email) in [
+        ("johndoe", "john1@doe.com"),
+        ("JohnDoe", "john2@doe.com"),
+        ("j0hn.d0e", "john3@doe.com"),
+    ] {
+        // Act
+        let response = register(&app, username, email).await;
+
+        // Assert
+        assert_eq!(
+            StatusCode::CONFLICT,
+            response.status(),
+            "the API did not reject {username}"
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!("user/username-taken", problem["code"]);
+        assert_eq!("already_taken", problem["username"][0]["code"]);
+    }
+}
+
+#[tokio::test]
+async fn hitting_register_endpoint_with_weak_password_returns_unprocessable_entity() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    for (password, code) in [
+        ("12345", "too_short"),
+        ("P4ssw0rd2022", "too_weak"),
+        ("qwertyuiop", "too_weak"),
+        ("aaaaaaaaaaaa", "too_weak"),
+        ("johndoe-rules", "derived_from_user_data"),
+        ("mi-correo-es-jdoe", "derived_from_user_data"),
+        ("correct horse battery staple", "breached"),
+    ] {
+        let form_data = multipart::Form::new()
+            .text("username", "johndoe")
+            .text("password", password)
+            .text("email", "jdoe@example.com");
+
+        // Act
+        let response = app
+            .api_client
+            .post(format!("{}/register", &app.address))
+            .multipart(form_data)
+            .send()
+            .await
+            .expect("failed to execute request");
+
+        // Assert
+        assert_eq!(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            response.status(),
+            "the API did not reject {password}"
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(
+            code, problem["password"][0]["code"],
+            "unexpected error for {password}"
+        );
+    }
+}
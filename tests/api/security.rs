@@ -0,0 +1,162 @@
+use http_api_problem::StatusCode;
+use reqwest::multipart;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, TestUser};
+
+const FRONT_END: &str = "https://kokoa.example";
+
+async fn preflight(app: &TestApp, origin: &str) -> reqwest::Response {
+    app.api_client
+        .request(
+            reqwest::Method::OPTIONS,
+            format!("{}/me/avatar", &app.address),
+        )
+        .header("Origin", origin)
+        .header("Access-Control-Request-Method", "PUT")
+        .header("Access-Control-Request-Headers", "authorization")
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+#[tokio::test]
+async fn allowed_origins_can_send_credentials() {
+    // Arrange
+    let app =
+        TestApp::with_configuration(|c| c.cors.allowed_origins = vec![FRONT_END.to_string()]).await;
+
+    // Act
+    let response = preflight(&app, FRONT_END).await;
+
+    // Assert
+    assert!(response.status().is_success());
+    let headers = response.headers();
+    assert_eq!(FRONT_END, headers["Access-Control-Allow-Origin"]);
+    assert_eq!("true", headers["Access-Control-Allow-Credentials"]);
+    assert!(headers["Access-Control-Allow-Methods"]
+        .to_str()
+        .unwrap()
+        .contains("PUT"));
+}
+
+#[tokio::test]
+async fn other_origins_are_not_allowed() {
+    // Arrange
+    let app =
+        TestApp::with_configuration(|c| c.cors.allowed_origins = vec![FRONT_END.to_string()]).await;
+
+    // Act
+    let response = preflight(&app, "https://evil.example").await;
+
+    // Assert
+    assert!(response
+        .headers()
+        .get("Access-Control-Allow-Origin")
+        .is_none());
+}
+
+#[tokio::test]
+async fn responses_include_security_headers() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health_check", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    let headers = response.headers();
+    assert_eq!("nosniff", headers["X-Content-Type-Options"]);
+    assert_eq!("DENY", headers["X-Frame-Options"]);
+    // The API is served over plain HTTP in tests
+    assert!(headers.get("Strict-Transport-Security").is_none());
+    assert!(headers.get("Content-Security-Policy").is_none());
+}
+
+#[tokio::test]
+async fn html_pages_have_a_content_security_policy() {
+    // Arrange
+    let app = TestApp::new().await;
+    let mut link = reqwest::Url::parse(&app.unsubscribe_links.link(Uuid::new_v4())).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+
+    // Act
+    let response = app
+        .api_client
+        .get(link)
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::OK, response.status());
+    let policy = response.headers()["Content-Security-Policy"]
+        .to_str()
+        .unwrap();
+    assert!(policy.contains("default-src 'none'"));
+    assert!(policy.contains("form-action 'self'"));
+}
+
+#[tokio::test]
+async fn uploads_larger_than_the_limit_are_rejected() {
+    // Arrange
+    let app = TestApp::with_configuration(|c| c.body_limits.upload_bytes = 16 * 1024).await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+    let profile_pic = multipart::Part::bytes(vec![0; 32 * 1024])
+        .file_name("avatar.png")
+        .mime_str("image/png")
+        .unwrap();
+
+    // Act
+    let response = app
+        .api_client
+        .put(format!("{}/me/avatar", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .multipart(multipart::Form::new().part("profile_pic", profile_pic))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("request/payload-too-large", problem["code"]);
+}
+
+#[tokio::test]
+async fn chunked_bodies_are_cut_off_at_the_limit() {
+    // Arrange
+    let app = TestApp::with_configuration(|c| c.body_limits.default_bytes = 1024).await;
+    let body = format!(r#"{{"email": "{}@example.com"}}"#, "a".repeat(2048));
+
+    // Act
+    // Without a `Content-Length` the body is only rejected once it goes over the limit
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", app.port))
+        .await
+        .unwrap();
+    let request = format!(
+        "POST /newsletter/subscribe HTTP/1.1\r\n\
+        Host: 127.0.0.1\r\n\
+        Content-Type: application/json\r\n\
+        Transfer-Encoding: chunked\r\n\
+        Connection: close\r\n\r\n\
+        {:x}\r\n{body}\r\n0\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    // Assert
+    assert!(
+        response.starts_with("HTTP/1.1 413"),
+        "unexpected response: {response}"
+    );
+}
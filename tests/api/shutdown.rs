@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use http_api_problem::StatusCode;
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+use chocoapi::configuration::Settings;
+use chocoapi::workers::{account_deletion, data_export, newsletter_delivery};
+
+use crate::helpers::TestApp;
+
+#[tokio::test]
+async fn health_check_reports_draining_until_the_api_stops() {
+    // Arrange
+    let app = TestApp::new().await;
+
+    // Act
+    app.shutdown.cancel();
+    let draining = reqwest::Client::new()
+        .get(format!("{}/health_check", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+    let readiness = reqwest::Client::new()
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let stopped = reqwest::Client::new()
+        .get(format!("{}/health_check", &app.address))
+        .send()
+        .await;
+
+    // Assert
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, draining.status());
+    assert_eq!("draining", draining.text().await.unwrap());
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, readiness.status());
+    let readiness: serde_json::Value = readiness.json().await.unwrap();
+    assert_eq!("draining", readiness["status"]);
+    assert!(stopped.is_err());
+}
+
+#[tokio::test]
+async fn workers_stop_when_shutting_down() {
+    // Arrange
+    let app = TestApp::new().await;
+    let shutdown = CancellationToken::new();
+    let workers = tokio::spawn(run_workers(
+        app.configuration.clone(),
+        (*app.db).clone(),
+        shutdown.clone(),
+    ));
+
+    // Act
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    shutdown.cancel();
+
+    // Assert
+    tokio::time::timeout(Duration::from_secs(5), workers)
+        .await
+        .expect("workers didn't stop in time")
+        .unwrap();
+}
+
+/// Run every background worker until they all stop.
+async fn run_workers(configuration: Settings, pool: PgPool, shutdown: CancellationToken) {
+    let (account_deletion, data_export, newsletter_delivery) = tokio::join!(
+        account_deletion::run_worker_until_stopped(
+            configuration.clone(),
+            pool.clone(),
+            shutdown.clone()
+        ),
+        data_export::run_worker_until_stopped(
+            configuration.clone(),
+            pool.clone(),
+            shutdown.clone()
+        ),
+        newsletter_delivery::run_worker_until_stopped(configuration, pool, shutdown),
+    );
+    account_deletion.unwrap();
+    data_export.unwrap();
+    newsletter_delivery.unwrap();
+}
@@ -0,0 +1,98 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use chocoapi::configuration::{self, DatabaseSettings};
+use chocoapi::startup::get_connection_pool;
+use tokio::net::{TcpListener, TcpStream};
+
+fn database_settings() -> DatabaseSettings {
+    let environment = configuration::get_environment().expect("failed to get environment");
+    let mut database = configuration::extract(environment)
+        .expect("failed to read configuration")
+        .database;
+    // Always present, and nothing is written to it
+    database.database_name = "postgres".to_string();
+    database
+}
+
+/// Listen on a random port in front of the database, dropping the first `failures`
+/// connections. Returns the port, and the number of connections so far.
+async fn flaky_database(settings: &DatabaseSettings, failures: usize) -> (u16, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let database = (settings.host.clone(), settings.port);
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let counter = connections.clone();
+    tokio::spawn(async move {
+        while let Ok((mut client, _)) = listener.accept().await {
+            if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                continue;
+            }
+            let database = database.clone();
+            tokio::spawn(async move {
+                let mut server = TcpStream::connect(database).await.unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+            });
+        }
+    });
+    (port, connections)
+}
+
+#[tokio::test]
+async fn slow_statements_are_cancelled_after_the_statement_timeout() {
+    // Arrange
+    let mut settings = database_settings();
+    settings.pool.statement_timeout_milliseconds = Some(100);
+    let pool = get_connection_pool(&settings).await.unwrap();
+
+    // Act
+    let result = sqlx::query("SELECT pg_sleep(2)").execute(&pool).await;
+
+    // Assert
+    let error = result.expect_err("the statement wasn't cancelled");
+    let code = error.as_database_error().and_then(|e| e.code());
+    // query_canceled
+    assert_eq!(Some("57014"), code.as_deref());
+}
+
+#[tokio::test]
+async fn connecting_to_an_unreachable_database_gives_up_after_the_retries() {
+    // Arrange
+    let mut settings = database_settings();
+    let (port, connections) = flaky_database(&settings, usize::MAX).await;
+    settings.host = "127.0.0.1".to_string();
+    settings.port = port;
+    settings.pool.connect_retries = 3;
+    settings.pool.retry_initial_delay_milliseconds = 10;
+    settings.pool.retry_max_delay_milliseconds = 20;
+
+    // Act
+    let result = get_connection_pool(&settings).await;
+
+    // Assert
+    assert!(result.is_err());
+    // At least one connection per attempt
+    assert!(connections.load(Ordering::SeqCst) >= 4);
+}
+
+#[tokio::test]
+async fn connecting_to_a_database_that_comes_back_succeeds() {
+    // Arrange
+    let mut settings = database_settings();
+    let (port, connections) = flaky_database(&settings, 1).await;
+    settings.host = "127.0.0.1".to_string();
+    settings.port = port;
+    settings.pool.connect_retries = 3;
+    settings.pool.retry_initial_delay_milliseconds = 10;
+    settings.pool.retry_max_delay_milliseconds = 20;
+
+    // Act
+    let pool = get_connection_pool(&settings).await.unwrap();
+
+    // Assert
+    assert!(connections.load(Ordering::SeqCst) >= 2);
+    sqlx::query("SELECT 1").execute(&pool).await.unwrap();
+}
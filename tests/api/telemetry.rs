@@ -0,0 +1,195 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use opentelemetry::trace::TracerProvider as _;
+use tracing_subscriber::fmt::MakeWriter;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use chocoapi::{
+    configuration::OtlpSettings,
+    telemetry::{get_subscriber, otlp_tracer_provider},
+};
+
+use crate::helpers::{TestApp, TestUser};
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+/// Send a request that looks up credentials, with the `traceparent` header if given, and
+/// return the bodies received by the collector stand-in once the spans are flushed.
+async fn export_spans(sampling_ratio: f64, traceparent: Option<&str>) -> Vec<Vec<u8>> {
+    let collector = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/traces"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&collector)
+        .await;
+    let provider = otlp_tracer_provider(&OtlpSettings {
+        endpoint: format!("{}/v1/traces", collector.uri()),
+        sampling_ratio,
+        service_name: "chocoapi-test".to_string(),
+    })
+    .expect("failed to build the tracer provider");
+
+    // The app runs on the runtime of the test, so its spans go to this subscriber
+    let subscriber = get_subscriber(
+        "test".to_string(),
+        tracing::Level::INFO,
+        std::io::sink,
+        Some(provider.tracer("test")),
+    );
+    let guard = tracing::subscriber::set_default(subscriber);
+
+    let app = TestApp::new().await;
+    let mut request = app
+        .api_client
+        .get(format!("{}/me/export", &app.address))
+        .basic_auth("nobody", Some("wrong password"));
+    if let Some(traceparent) = traceparent {
+        request = request.header("traceparent", traceparent);
+    }
+    request.send().await.expect("failed to execute request");
+
+    drop(guard);
+    for result in provider.force_flush() {
+        result.expect("failed to export spans");
+    }
+    collector
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|request| request.body)
+        .collect()
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[tokio::test]
+async fn spans_of_a_request_continue_the_trace_of_its_traceparent() {
+    // Arrange
+    let trace_id: Vec<u8> = (0..TRACE_ID.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&TRACE_ID[i..i + 2], 16).unwrap())
+        .collect();
+
+    // Act
+    let bodies = export_spans(0.0, Some(&format!("00-{TRACE_ID}-00f067aa0ba902b7-01"))).await;
+
+    // Assert
+    assert!(!bodies.is_empty(), "no spans were exported");
+    let body = bodies.concat();
+    assert!(contains(&body, b"chocoapi-test"));
+    assert!(contains(&body, b"get_credentials"));
+    assert!(contains(&body, &trace_id));
+}
+
+#[tokio::test]
+async fn traces_left_out_by_the_sampling_ratio_are_not_exported() {
+    // Act
+    let bodies = export_spans(0.0, None).await;
+
+    // Assert
+    assert!(bodies.is_empty());
+}
+
+/// The lines logged by a subscriber, to check what the logs of a request contain.
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Logs {
+    fn lines(&self) -> Vec<serde_json::Value> {
+        self.0
+            .lock()
+            .unwrap()
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect()
+    }
+}
+
+impl io::Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for Logs {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[tokio::test]
+async fn logs_of_a_request_are_correlated_and_redact_credentials() {
+    // Arrange
+    let logs = Logs::default();
+    let subscriber = get_subscriber(
+        "test".to_string(),
+        tracing::Level::DEBUG,
+        logs.clone(),
+        None,
+    );
+    let _guard = tracing::subscriber::set_default(subscriber);
+    let app = TestApp::new().await;
+    let user = TestUser::generate();
+    user.register(&app).await;
+    let user_id = sqlx::query_as::<_, (Uuid,)>("SELECT id FROM users WHERE username = $1")
+        .bind(&user.username)
+        .fetch_one(&*app.db)
+        .await
+        .unwrap()
+        .0;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/me/export", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .header("X-Request-Id", "correlated-request")
+        .header("User-Agent", "chocoapi-tests")
+        .header("Cookie", "session=secret-cookie")
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!("correlated-request", response.headers()["X-Request-Id"]);
+    let lines: Vec<_> = logs
+        .lines()
+        .into_iter()
+        .filter(|line| line["request_id"] == "correlated-request")
+        .collect();
+    let end = lines
+        .iter()
+        .find(|line| line["msg"] == "[REQUEST - END]")
+        .expect("the request span wasn't closed");
+    assert_eq!("/me/export", end["route"]);
+    assert_eq!(user_id.to_string(), end["user_id"]);
+    assert_eq!("127.0.0.1", end["client_ip"]);
+    assert_eq!("chocoapi-tests", end["user_agent"]);
+    let headers = lines
+        .iter()
+        .find_map(|line| line["headers"].as_str())
+        .expect("the headers weren't logged");
+    assert!(headers.contains("chocoapi-tests"));
+    assert!(!headers.contains("secret-cookie"));
+    assert!(!headers.contains("Basic "));
+}
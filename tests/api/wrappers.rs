@@ -5,13 +5,16 @@ use sqlx::postgres::PgConnectOptions;
 use uuid::Uuid;
 
 use chocoapi::configuration::Settings;
-use chocoapi::startup::Application;
+use chocoapi::startup::{get_connection_pool, Application};
 
 pub struct TestAPI(Application);
 
 impl TestAPI {
     pub async fn new(configuration: TestConfiguration) -> Self {
-        let application = Application::build(configuration.into())
+        let connection_pool = get_connection_pool(&configuration.database)
+            .await
+            .expect("failed to connect to the database");
+        let application = Application::build(configuration.into(), connection_pool)
             .await
             .expect("failed to build application");
         TestAPI(application)
@@ -41,6 +44,14 @@ impl TestConfiguration {
         config.application.port = 0;
         // Use a different database for each test case
         config.database.database_name = Uuid::new_v4().to_string();
+        // `TestDatabase` runs the migrations before the application starts
+        config.database.migrate_on_startup = false;
+        // Use a different storage directory for each test case
+        config.storage.root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        // A few breached passwords, in the format of the Pwned Passwords range files
+        config.passwords.breached_passwords = Some("tests/fixtures/pwned-passwords".into());
+        // Keep serving for a moment after a shutdown, to see the health check draining
+        config.shutdown.drain_delay_seconds = 1;
         TestConfiguration(config)
     }
 }